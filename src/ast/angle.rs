@@ -0,0 +1,259 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::ops::{Add, Neg};
+
+// Float angles this close to a multiple of 2π count as no rotation.
+const EPSILON: f64 = 1e-12;
+
+// A rotation angle, kept as an exact fraction `num/den` of π when it can be
+// one so that sums of them cancel exactly, and in radians otherwise. Angles
+// are taken modulo 2π, into (-π, π].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Angle {
+    Pi { num: i64, den: i64 },
+    Radians(f64),
+}
+
+impl Angle {
+    pub fn pi(num: i64, den: i64) -> Self {
+        Self::exact(num, den).unwrap_or(Self::Radians(num as f64 / den as f64 * PI))
+    }
+
+    pub fn radians(value: f64) -> Self {
+        let value = value.rem_euclid(2.0 * PI);
+        Self::Radians(if value > PI { value - 2.0 * PI } else { value })
+    }
+
+    // `value` radians, made exact when it is within rounding of a fraction of
+    // π with a small denominator, as angles computed from matrices often are.
+    pub fn nearest(value: f64) -> Self {
+        let turns = value / PI;
+        for den in [1, 2, 3, 4, 6, 8, 12, 16, 32, 64] {
+            let num = (turns * den as f64).round();
+            if (turns * den as f64 - num).abs() < 1e-9 {
+                return Self::pi(num as i64, den);
+            }
+        }
+        Self::radians(value)
+    }
+
+    // `num/den` of π in lowest terms, unless the arithmetic overflows.
+    fn exact(num: i64, den: i64) -> Option<Self> {
+        let (num, den) = reduce(num, den)?;
+        let turn = den.checked_mul(2)?;
+        let mut num = num.rem_euclid(turn);
+        if num > den {
+            num -= turn;
+        }
+        Some(Self::Pi { num, den })
+    }
+
+    pub fn to_radians(self) -> f64 {
+        match self {
+            Self::Pi { num, den } => num as f64 / den as f64 * PI,
+            Self::Radians(value) => value,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        match self {
+            Self::Pi { num, .. } => num == 0,
+            Self::Radians(value) => value.abs() < EPSILON,
+        }
+    }
+}
+
+impl Add for Angle {
+    type Output = Angle;
+
+    fn add(self, other: Angle) -> Angle {
+        if let (Self::Pi { num: a, den: b }, Self::Pi { num: c, den: d }) = (self, other) {
+            if let Some(sum) = add_fractions((a, b), (c, d)).and_then(|(n, d)| Self::exact(n, d)) {
+                return sum;
+            }
+        }
+        Self::radians(self.to_radians() + other.to_radians())
+    }
+}
+
+impl Neg for Angle {
+    type Output = Angle;
+
+    fn neg(self) -> Angle {
+        match self {
+            Self::Pi { num, den } => Self::pi(-num, den),
+            Self::Radians(value) => Self::radians(-value),
+        }
+    }
+}
+
+// Prints as the parser reads it back: `pi/4`, `-3*pi/8`, or radians.
+impl fmt::Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Pi { num: 0, .. } => write!(f, "0"),
+            Self::Pi { num, den } => {
+                match num {
+                    1 => write!(f, "pi")?,
+                    -1 => write!(f, "-pi")?,
+                    _ => write!(f, "{}*pi", num)?,
+                }
+                if den != 1 {
+                    write!(f, "/{}", den)?;
+                }
+                Ok(())
+            }
+            Self::Radians(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// A number in an angle expression while it is being parsed: `value` times
+// π to the power `pi`, with `value` exact while it can be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AngleTerm {
+    value: Scalar,
+    pi: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    Exact(i64, i64),
+    Float(f64),
+}
+
+impl AngleTerm {
+    pub fn pi() -> Self {
+        Self {
+            value: Scalar::Exact(1, 1),
+            pi: 1,
+        }
+    }
+
+    // A literal such as `3` or `0.125`, exact when it fits.
+    pub fn number(text: &str) -> Option<Self> {
+        let float: f64 = text.parse().ok()?;
+        let (whole, frac) = text.split_once('.').unwrap_or((text, ""));
+        let num = format!("{}{}", whole, frac).parse::<i64>().ok();
+        let den = 10i64.checked_pow(frac.len() as u32);
+        let value = match num.zip(den).and_then(|(num, den)| reduce(num, den)) {
+            Some((num, den)) => Scalar::Exact(num, den),
+            None => Scalar::Float(float),
+        };
+        Some(Self { value, pi: 0 })
+    }
+
+    fn to_f64(self) -> f64 {
+        let value = match self.value {
+            Scalar::Exact(num, den) => num as f64 / den as f64,
+            Scalar::Float(value) => value,
+        };
+        value * PI.powi(self.pi)
+    }
+
+    fn float(self) -> Self {
+        Self {
+            value: Scalar::Float(self.to_f64()),
+            pi: 0,
+        }
+    }
+
+    pub fn plus(self, other: Self) -> Self {
+        if self.pi != other.pi {
+            return self.float().plus(other.float());
+        }
+        let value = match (self.value, other.value) {
+            (Scalar::Exact(a, b), Scalar::Exact(c, d)) => {
+                add_fractions((a, b), (c, d)).map(|(num, den)| Scalar::Exact(num, den))
+            }
+            _ => None,
+        };
+        match value {
+            Some(value) => Self { value, ..self },
+            None => Self {
+                value: Scalar::Float(self.float().to_f64() + other.float().to_f64()),
+                pi: 0,
+            },
+        }
+    }
+
+    pub fn negate(self) -> Self {
+        let value = match self.value {
+            Scalar::Exact(num, den) => match num.checked_neg() {
+                Some(num) => Scalar::Exact(num, den),
+                None => Scalar::Float(-(num as f64) / den as f64),
+            },
+            Scalar::Float(value) => Scalar::Float(-value),
+        };
+        Self { value, ..self }
+    }
+
+    pub fn times(self, other: Self) -> Self {
+        let pi = self.pi + other.pi;
+        let value = match (self.value, other.value) {
+            (Scalar::Exact(a, b), Scalar::Exact(c, d)) => a
+                .checked_mul(c)
+                .zip(b.checked_mul(d))
+                .and_then(|(num, den)| reduce(num, den))
+                .map(|(num, den)| Scalar::Exact(num, den)),
+            _ => None,
+        };
+        match value {
+            Some(value) => Self { value, pi },
+            None => Self {
+                value: Scalar::Float(self.float().to_f64() * other.float().to_f64()),
+                pi: 0,
+            },
+        }
+    }
+
+    // `None` when dividing by zero.
+    pub fn divided_by(self, other: Self) -> Option<Self> {
+        let inverse = match other.value {
+            Scalar::Exact(0, _) => return None,
+            Scalar::Exact(num, den) => match reduce(den, num) {
+                Some((num, den)) => Scalar::Exact(num, den),
+                None => Scalar::Float(den as f64 / num as f64),
+            },
+            Scalar::Float(0.0) => return None,
+            Scalar::Float(value) => Scalar::Float(1.0 / value),
+        };
+        Some(self.times(Self {
+            value: inverse,
+            pi: -other.pi,
+        }))
+    }
+
+    pub fn to_angle(self) -> Angle {
+        match (self.value, self.pi) {
+            (Scalar::Exact(num, den), 1) => Angle::pi(num, den),
+            (Scalar::Exact(0, _), _) => Angle::pi(0, 1),
+            _ => Angle::radians(self.to_f64()),
+        }
+    }
+}
+
+fn add_fractions((a, b): (i64, i64), (c, d): (i64, i64)) -> Option<(i64, i64)> {
+    let num = a.checked_mul(d)?.checked_add(c.checked_mul(b)?)?;
+    reduce(num, b.checked_mul(d)?)
+}
+
+fn reduce(num: i64, den: i64) -> Option<(i64, i64)> {
+    if den == 0 {
+        return None;
+    }
+    let g = i64::try_from(gcd(num.unsigned_abs(), den.unsigned_abs())).ok()?;
+    let sign = den.signum();
+    Some((
+        num.checked_div(g)?.checked_mul(sign)?,
+        den.checked_div(g)?.checked_mul(sign)?,
+    ))
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
@@ -0,0 +1,352 @@
+use std::fmt;
+
+use crate::lexer::annotate::Annotation;
+use crate::lexer::Span;
+
+pub mod angle;
+pub mod print;
+
+pub use angle::Angle;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub pe_enabled: bool,
+    pub attrs: Vec<Attribute>,
+    pub span: Span,
+    // Span of the closing brace, where control falls off the end.
+    pub end_span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub annotation: Annotation,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub attrs: Vec<Attribute>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Let {
+        name: String,
+        init: Expr,
+    },
+    // `qbit q;`, or `qbit q[n];` for an array of `n` qubits.
+    QbitDecl {
+        name: String,
+        size: Option<Expr>,
+    },
+    // `G(q);`, or `CX(c, t);` with the controls first for multi-qubit gates.
+    // Rotations take their angles first, as in `RZ(pi/4, q);`.
+    QOp {
+        gate: String,
+        params: Vec<Angle>,
+        qubits: Vec<QubitRef>,
+    },
+    Measure {
+        target: QubitRef,
+        classical: Option<String>,
+    },
+    Reset {
+        target: QubitRef,
+    },
+    Return(Option<Expr>),
+    Expr(Expr),
+    If {
+        cond: Expr,
+        then_body: Vec<Stmt>,
+        else_body: Option<Vec<Stmt>>,
+    },
+    Block(Vec<Stmt>),
+    // `for var in start..end { body }`, counting up with `end` excluded.
+    For {
+        var: String,
+        start: Expr,
+        end: Expr,
+        body: Vec<Stmt>,
+    },
+}
+
+// A qubit operand: `q`, or an element `q[i]` of a qubit array.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QubitRef {
+    pub name: String,
+    pub index: Option<Expr>,
+}
+
+impl QubitRef {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            index: None,
+        }
+    }
+
+    pub fn for_each_var(&self, f: &mut impl FnMut(&str)) {
+        f(&self.name);
+        if let Some(index) = &self.index {
+            index.for_each_var(f);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Var(String),
+    Call {
+        callee: String,
+        args: Vec<Expr>,
+    },
+    // `span` is where the left operand starts.
+    Binary {
+        op: BinOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        span: Span,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+        }
+    }
+
+    // Binding strength, matching the parser's precedence levels.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 0,
+            BinOp::Shl | BinOp::Shr => 1,
+            BinOp::Add | BinOp::Sub => 2,
+            BinOp::Mul | BinOp::Div => 3,
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl Expr {
+    // Calls `f` on every variable this expression reads.
+    pub fn for_each_var(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Var(name) => f(name),
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    arg.for_each_var(f);
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                left.for_each_var(f);
+                right.for_each_var(f);
+            }
+        }
+    }
+}
+
+impl Expr {
+    // Calls may have quantum side effects, so only call-free expressions
+    // can be dropped or duplicated freely.
+    pub fn has_call(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Var(_) => false,
+            Expr::Call { .. } => true,
+            Expr::Binary { left, right, .. } => left.has_call() || right.has_call(),
+        }
+    }
+}
+
+impl StmtKind {
+    // Calls `f` on every variable or qubit the statement reads, including
+    // inside nested blocks.
+    pub fn for_each_use(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            StmtKind::Let { init: expr, .. }
+            | StmtKind::Return(Some(expr))
+            | StmtKind::Expr(expr) => expr.for_each_var(f),
+            StmtKind::QOp { qubits, .. } => {
+                for qubit in qubits {
+                    qubit.for_each_var(f);
+                }
+            }
+            StmtKind::Measure { target, .. }
+            | StmtKind::Reset { target } => target.for_each_var(f),
+            StmtKind::QbitDecl { size, .. } => {
+                if let Some(size) = size {
+                    size.for_each_var(f);
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                cond.for_each_var(f);
+                for stmt in then_body.iter().chain(else_body.iter().flatten()) {
+                    stmt.kind.for_each_use(f);
+                }
+            }
+            StmtKind::Block(body) => {
+                for stmt in body {
+                    stmt.kind.for_each_use(f);
+                }
+            }
+            StmtKind::For {
+                start, end, body, ..
+            } => {
+                start.for_each_var(f);
+                end.for_each_var(f);
+                for stmt in body {
+                    stmt.kind.for_each_use(f);
+                }
+            }
+            StmtKind::Return(None) => {}
+        }
+    }
+}
+
+impl fmt::Display for QubitRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.index {
+            Some(index) => write!(f, "{}[{}]", self.name, index),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#[{}]", self.annotation)
+    }
+}
+
+// Prints a statement as source, without its attributes. Nested blocks are
+// printed on their own lines.
+impl fmt::Display for StmtKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StmtKind::Let { name, init } => write!(f, "let {} = {};", name, init),
+            StmtKind::QbitDecl { name, size } => match size {
+                Some(size) => write!(f, "qbit {}[{}];", name, size),
+                None => write!(f, "qbit {};", name),
+            },
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits,
+            } => {
+                let operands: Vec<String> = params
+                    .iter()
+                    .map(Angle::to_string)
+                    .chain(qubits.iter().map(QubitRef::to_string))
+                    .collect();
+                write!(f, "{}({});", gate, operands.join(", "))
+            }
+            StmtKind::Measure { target, classical } => match classical {
+                Some(c) => write!(f, "measure {} -> {};", target, c),
+                None => write!(f, "measure {};", target),
+            },
+            StmtKind::Reset { target } => write!(f, "reset {};", target),
+            StmtKind::Return(Some(expr)) => write!(f, "return {};", expr),
+            StmtKind::Return(None) => write!(f, "return;"),
+            StmtKind::Expr(expr) => write!(f, "{};", expr),
+            StmtKind::If { .. } | StmtKind::Block(_) | StmtKind::For { .. } => {
+                let text = print::stmt_to_string(self);
+                f.write_str(text.trim_end())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&print::print_program(self, &mut print::NoNotes))
+    }
+}
+
+// Prints source syntax, with parentheses only where precedence needs them.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(v) => write!(f, "{}", v),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Call { callee, args } => {
+                write!(f, "{}(", callee)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                // Comparisons do not chain, so they need parentheses on
+                // either side of another comparison.
+                let prec = op.precedence();
+                write_operand(f, left, |p| p < prec || p == 0)?;
+                write!(f, " {} ", op)?;
+                write_operand(f, right, |p| p <= prec)
+            }
+        }
+    }
+}
+
+fn write_operand(
+    f: &mut fmt::Formatter<'_>,
+    expr: &Expr,
+    needs_parens: impl Fn(u8) -> bool,
+) -> fmt::Result {
+    match expr {
+        Expr::Binary { op, .. } if needs_parens(op.precedence()) => write!(f, "({})", expr),
+        _ => write!(f, "{}", expr),
+    }
+}
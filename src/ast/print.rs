@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use super::{Function, Program, Stmt, StmtKind};
+
+// Supplies an optional trailing `//` comment for each function header and
+// statement line, e.g. binding times or source spans.
+pub trait Annotate {
+    fn function(&mut self, _func: &Function) -> Option<String> {
+        None
+    }
+
+    fn stmt(&mut self, _stmt: &Stmt) -> Option<String> {
+        None
+    }
+}
+
+pub struct NoNotes;
+
+impl Annotate for NoNotes {}
+
+pub fn print_program(program: &Program, notes: &mut dyn Annotate) -> String {
+    let mut p = Printer {
+        out: String::new(),
+        notes,
+    };
+    for (i, func) in program.functions.iter().enumerate() {
+        if i > 0 {
+            p.out.push('\n');
+        }
+        p.function(func);
+    }
+    p.out
+}
+
+pub(super) fn stmt_to_string(kind: &StmtKind) -> String {
+    let mut p = Printer {
+        out: String::new(),
+        notes: &mut NoNotes,
+    };
+    p.stmt_kind(kind, 0, None);
+    p.out
+}
+
+struct Printer<'a> {
+    out: String,
+    notes: &'a mut dyn Annotate,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, indent: usize, text: &str, note: Option<String>) {
+        let pad = "    ".repeat(indent);
+        let _ = match note {
+            Some(note) => writeln!(self.out, "{}{:<32} // {}", pad, text, note),
+            None => writeln!(self.out, "{}{}", pad, text),
+        };
+    }
+
+    fn function(&mut self, func: &Function) {
+        for attr in &func.attrs {
+            self.line(0, &attr.to_string(), None);
+        }
+        let header = format!("fn {}({}) {{", func.name, func.params.join(", "));
+        let note = self.notes.function(func);
+        self.line(0, &header, note);
+        self.block(&func.body, 1);
+        self.line(0, "}", None);
+    }
+
+    fn block(&mut self, stmts: &[Stmt], indent: usize) {
+        for stmt in stmts {
+            for attr in &stmt.attrs {
+                self.line(indent, &attr.to_string(), None);
+            }
+            let note = self.notes.stmt(stmt);
+            self.stmt_kind(&stmt.kind, indent, note);
+        }
+    }
+
+    fn stmt_kind(&mut self, kind: &StmtKind, indent: usize, note: Option<String>) {
+        match kind {
+            StmtKind::If { .. } => self.if_chain(kind, indent, note, "if"),
+            StmtKind::Block(body) => {
+                self.line(indent, "{", note);
+                self.block(body, indent + 1);
+                self.line(indent, "}", None);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.line(
+                    indent,
+                    &format!("for {} in {}..{} {{", var, start, end),
+                    note,
+                );
+                self.block(body, indent + 1);
+                self.line(indent, "}", None);
+            }
+            _ => self.line(indent, &kind.to_string(), note),
+        }
+    }
+
+    // `else if` is kept on one line when the else branch is a lone `if`.
+    fn if_chain(&mut self, kind: &StmtKind, indent: usize, note: Option<String>, keyword: &str) {
+        let StmtKind::If {
+            cond,
+            then_body,
+            else_body,
+        } = kind
+        else {
+            return;
+        };
+
+        self.line(indent, &format!("{} {} {{", keyword, cond), note);
+        self.block(then_body, indent + 1);
+
+        match else_body.as_deref() {
+            None => self.line(indent, "}", None),
+            Some([stmt]) if stmt.attrs.is_empty() && matches!(stmt.kind, StmtKind::If { .. }) => {
+                let note = self.notes.stmt(stmt);
+                self.if_chain(&stmt.kind, indent, note, "} else if");
+            }
+            Some(body) => {
+                self.line(indent, "} else {", None);
+                self.block(body, indent + 1);
+                self.line(indent, "}", None);
+            }
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::print::{print_program, Annotate};
+use crate::ast::{Attribute, Expr, Function, Program, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+use crate::lexer::annotate::Annotation;
+use crate::lexer::Span;
+
+// Why a value is only known at runtime, traced back to where that started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cause {
+    pub span: Span,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindingTime {
+    Static,
+    Dynamic(Cause),
+}
+
+impl BindingTime {
+    fn dynamic(span: Span, reason: impl Into<String>) -> Self {
+        BindingTime::Dynamic(Cause {
+            span,
+            reason: reason.into(),
+        })
+    }
+
+    pub fn is_static(&self) -> bool {
+        matches!(self, BindingTime::Static)
+    }
+
+    // Least upper bound: dynamic if either side is, keeping the first cause.
+    pub fn join(self, other: BindingTime) -> BindingTime {
+        match self {
+            BindingTime::Static => other,
+            dynamic => dynamic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnBta {
+    pub params: Vec<BindingTime>,
+    pub ret: BindingTime,
+    // Binding time of each statement's value, keyed by statement span.
+    pub stmts: HashMap<Span, BindingTime>,
+}
+
+#[derive(Debug, Default)]
+pub struct Bta {
+    pub functions: HashMap<String, FnBta>,
+    pub diags: Vec<Diagnostic>,
+}
+
+// Offline, monovariant binding-time analysis. Each parameter's binding time
+// is the join over all call sites; functions nobody calls are entry points
+// whose parameters are dynamic unless the function is #[static].
+// `#[dynamic]` forces a value or a function's parameters dynamic, and
+// `#[static]` demands that they be static.
+pub fn analyze(program: &Program) -> Bta {
+    let mut called = HashSet::new();
+    for func in &program.functions {
+        collect_callees(&func.body, &mut called);
+    }
+
+    let mut sigs: HashMap<&str, Sig> = HashMap::new();
+    for func in &program.functions {
+        let entry = !called.contains(func.name.as_str());
+        let forced = has_attr(&func.attrs, &Annotation::Dynamic);
+        let required = has_attr(&func.attrs, &Annotation::Static);
+        let params = func
+            .params
+            .iter()
+            .map(|p| {
+                if forced {
+                    BindingTime::dynamic(func.span, format!("`{}` is #[dynamic]", func.name))
+                } else if entry && !required {
+                    BindingTime::dynamic(
+                        func.span,
+                        format!("`{}` is a parameter of entry function `{}`", p, func.name),
+                    )
+                } else {
+                    BindingTime::Static
+                }
+            })
+            .collect();
+        let sig = Sig {
+            params,
+            ret: BindingTime::Static,
+            required,
+        };
+        sigs.insert(&func.name, sig);
+    }
+
+    // Binding times only ever move from static to dynamic, so this settles.
+    loop {
+        let mut changed = false;
+        for func in &program.functions {
+            let (ret, calls) = {
+                let mut cx = BtaCx::new(&sigs, func);
+                cx.analyze_block(&func.body);
+                (cx.ret, cx.calls)
+            };
+
+            let sig = sigs.get_mut(func.name.as_str()).unwrap();
+            if sig.ret.is_static() && !ret.is_static() {
+                sig.ret = ret;
+                changed = true;
+            }
+            for (callee, args) in calls {
+                let params = &mut sigs.get_mut(callee.as_str()).unwrap().params;
+                for (param, arg) in params.iter_mut().zip(args) {
+                    if param.is_static() && !arg.is_static() {
+                        *param = arg;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut bta = Bta::default();
+    for func in &program.functions {
+        let mut cx = BtaCx::new(&sigs, func);
+        cx.check = true;
+        cx.analyze_block(&func.body);
+        bta.diags.append(&mut cx.diags);
+
+        let sig = &sigs[func.name.as_str()];
+        let result = FnBta {
+            params: sig.params.clone(),
+            ret: sig.ret.clone(),
+            stmts: cx.stmts,
+        };
+        bta.functions.insert(func.name.clone(), result);
+    }
+    bta
+}
+
+struct Sig {
+    params: Vec<BindingTime>,
+    ret: BindingTime,
+    // The function is #[static]: every argument must be static.
+    required: bool,
+}
+
+fn has_attr(attrs: &[Attribute], annotation: &Annotation) -> bool {
+    attrs.iter().any(|a| &a.annotation == annotation)
+}
+
+fn collect_callees<'p>(stmts: &'p [Stmt], out: &mut HashSet<&'p str>) {
+    fn walk<'p>(expr: &'p Expr, out: &mut HashSet<&'p str>) {
+        match expr {
+            Expr::Call { callee, args } => {
+                out.insert(callee);
+                args.iter().for_each(|a| walk(a, out));
+            }
+            Expr::Binary { left, right, .. } => {
+                walk(left, out);
+                walk(right, out);
+            }
+            Expr::Number(_) | Expr::Var(_) => {}
+        }
+    }
+
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Let { init: e, .. } | StmtKind::Return(Some(e)) | StmtKind::Expr(e) => {
+                walk(e, out)
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                walk(cond, out);
+                collect_callees(then_body, out);
+                if let Some(body) = else_body {
+                    collect_callees(body, out);
+                }
+            }
+            StmtKind::Block(body) => collect_callees(body, out),
+            StmtKind::For {
+                start, end, body, ..
+            } => {
+                walk(start, out);
+                walk(end, out);
+                collect_callees(body, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct BtaCx<'a> {
+    sigs: &'a HashMap<&'a str, Sig>,
+    env: HashMap<String, BindingTime>,
+    ret: BindingTime,
+    // Join of the conditions of the enclosing `if`s and loop bounds.
+    control: BindingTime,
+    calls: Vec<(String, Vec<BindingTime>)>,
+    stmts: HashMap<Span, BindingTime>,
+    // Conflicts are only reported on the final pass.
+    check: bool,
+    diags: Vec<Diagnostic>,
+}
+
+impl<'a> BtaCx<'a> {
+    fn new(sigs: &'a HashMap<&'a str, Sig>, func: &Function) -> Self {
+        let env = func
+            .params
+            .iter()
+            .cloned()
+            .zip(sigs[func.name.as_str()].params.iter().cloned())
+            .collect();
+        Self {
+            sigs,
+            env,
+            ret: BindingTime::Static,
+            control: BindingTime::Static,
+            calls: Vec::new(),
+            stmts: HashMap::new(),
+            check: false,
+            diags: Vec::new(),
+        }
+    }
+
+    // Bindings are block scoped, so the environment is restored afterwards.
+    fn analyze_block(&mut self, stmts: &[Stmt]) {
+        let saved = self.env.clone();
+        for stmt in stmts {
+            let bt = self.analyze_stmt(stmt);
+            self.stmts.insert(stmt.span, bt);
+        }
+        self.env = saved;
+    }
+
+    fn analyze_stmt(&mut self, stmt: &Stmt) -> BindingTime {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Let { name, init } => {
+                let bt = self.expr(init, span);
+                let bt = self.apply_attrs(stmt, &format!("`{}`", name), bt);
+                self.env.insert(name.clone(), bt.clone());
+                bt
+            }
+            StmtKind::Return(expr) => {
+                let bt = match expr {
+                    Some(e) => self.expr(e, span),
+                    None => BindingTime::Static,
+                };
+                let bt = self.apply_attrs(stmt, "the returned value", bt);
+                // Returning under a dynamic condition makes the result
+                // dynamic even if each returned value is static.
+                let bt = bt.join(self.control.clone());
+                self.ret = self.ret.clone().join(bt.clone());
+                bt
+            }
+            StmtKind::Expr(expr) => {
+                let bt = self.expr(expr, span);
+                self.apply_attrs(stmt, "this expression", bt)
+            }
+            StmtKind::Measure {
+                target, classical, ..
+            } => {
+                let what = match classical {
+                    Some(c) => format!("`{}`", c),
+                    None => "this measurement".to_string(),
+                };
+                let bt = BindingTime::dynamic(span, "measurement happens at runtime");
+                let bt = self.apply_attrs(stmt, &what, bt);
+                if let Some(c) = classical {
+                    let reason = format!("`{}` is the result of measuring `{}`", c, target);
+                    self.env
+                        .insert(c.clone(), BindingTime::dynamic(span, reason));
+                }
+                bt
+            }
+            StmtKind::QbitDecl { name, .. } => {
+                let reason = format!("`{}` is a qubit", name);
+                self.env
+                    .insert(name.clone(), BindingTime::dynamic(span, reason));
+                BindingTime::dynamic(span, "quantum operation")
+            }
+            StmtKind::QOp { .. } | StmtKind::Reset { .. } => {
+                BindingTime::dynamic(span, "quantum operation")
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let bt = self.expr(cond, span);
+                let bt = self.apply_attrs(stmt, "this condition", bt);
+
+                let saved = self.control.clone();
+                self.control = saved.clone().join(bt.clone());
+                self.analyze_block(then_body);
+                if let Some(body) = else_body {
+                    self.analyze_block(body);
+                }
+                self.control = saved;
+                bt
+            }
+            StmtKind::Block(body) => {
+                self.analyze_block(body);
+                BindingTime::Static
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let bt = self.expr(start, span).join(self.expr(end, span));
+                let bt = self.apply_attrs(stmt, "the loop bound", bt);
+
+                let saved_var = self.env.insert(var.clone(), bt.clone());
+                let saved = self.control.clone();
+                self.control = saved.clone().join(bt.clone());
+                self.analyze_block(body);
+                self.control = saved;
+                match saved_var {
+                    Some(old) => self.env.insert(var.clone(), old),
+                    None => self.env.remove(var),
+                };
+                bt
+            }
+        }
+    }
+
+    // `#[dynamic]` overrides the computed binding time; `#[static]` checks it.
+    fn apply_attrs(&mut self, stmt: &Stmt, what: &str, bt: BindingTime) -> BindingTime {
+        if let Some(attr) = stmt
+            .attrs
+            .iter()
+            .find(|a| a.annotation == Annotation::Dynamic)
+        {
+            return BindingTime::dynamic(attr.span, format!("{} is marked #[dynamic]", what));
+        }
+        if let (Some(attr), BindingTime::Dynamic(cause)) = (
+            stmt.attrs
+                .iter()
+                .find(|a| a.annotation == Annotation::Static),
+            &bt,
+        ) {
+            self.conflict(
+                format!("{} is marked #[static] but is only known at runtime", what),
+                attr.span,
+                cause,
+            );
+        }
+        bt
+    }
+
+    fn conflict(&mut self, message: String, span: Span, cause: &Cause) {
+        if self.check {
+            let diag = Diagnostic::error(message, span).with_note(cause.span, cause.reason.clone());
+            self.diags.push(diag.with_code("binding_time"));
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, span: Span) -> BindingTime {
+        match expr {
+            Expr::Number(_) => BindingTime::Static,
+            Expr::Var(name) => match self.env.get(name) {
+                Some(bt) => bt.clone(),
+                None => BindingTime::dynamic(span, format!("`{}` is not defined here", name)),
+            },
+            Expr::Binary { left, right, .. } => {
+                let l = self.expr(left, span);
+                let r = self.expr(right, span);
+                l.join(r)
+            }
+            Expr::Call { callee, args } => {
+                let args: Vec<BindingTime> = args.iter().map(|a| self.expr(a, span)).collect();
+                let Some(sig) = self.sigs.get(callee.as_str()) else {
+                    let reason = format!("`{}` is not a known function", callee);
+                    return BindingTime::dynamic(span, reason);
+                };
+
+                if sig.required {
+                    let dynamic_arg = args.iter().find_map(|a| match a {
+                        BindingTime::Dynamic(cause) => Some(cause.clone()),
+                        BindingTime::Static => None,
+                    });
+                    if let Some(cause) = dynamic_arg {
+                        self.conflict(
+                            format!(
+                                "`{}` is #[static] but is called with a runtime argument",
+                                callee
+                            ),
+                            span,
+                            &cause,
+                        );
+                    }
+                }
+
+                let ret = sig.ret.clone();
+                self.calls.push((callee.clone(), args));
+                ret
+            }
+        }
+    }
+}
+
+// Renders the program with the binding time of each statement as a
+// trailing comment.
+pub fn render(program: &Program, bta: &Bta) -> String {
+    print_program(program, &mut BtaNotes { bta, current: None })
+}
+
+struct BtaNotes<'a> {
+    bta: &'a Bta,
+    // The function whose body is being printed.
+    current: Option<&'a FnBta>,
+}
+
+impl Annotate for BtaNotes<'_> {
+    fn function(&mut self, func: &Function) -> Option<String> {
+        self.current = self.bta.functions.get(&func.name);
+        let info = self.current?;
+        let mut parts: Vec<String> = func
+            .params
+            .iter()
+            .zip(&info.params)
+            .map(|(p, bt)| format!("{}: {}", p, short(bt)))
+            .collect();
+        parts.push(format!("returns {}", short(&info.ret)));
+        Some(parts.join(", "))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Option<String> {
+        if matches!(stmt.kind, StmtKind::Block(_)) {
+            return None;
+        }
+        Some(match self.current?.stmts.get(&stmt.span)? {
+            BindingTime::Static => "static".to_string(),
+            BindingTime::Dynamic(cause) => {
+                format!("dynamic: {} ({})", cause.reason, cause.span)
+            }
+        })
+    }
+}
+
+fn short(bt: &BindingTime) -> &'static str {
+    if bt.is_static() {
+        "static"
+    } else {
+        "dynamic"
+    }
+}
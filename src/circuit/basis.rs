@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use super::gates::{canonical, BUILTIN};
+use super::rewrite::bind;
+use super::rules::{Bindings, Rule};
+use super::{Op, OpKind, Target};
+
+// Ways to write one gate with others, up to a global phase. Together they
+// make an equivalence graph between the built-in gates, which `translate`
+// searches for the cheapest way into a basis.
+pub const EQUIVALENCES: &[&str] = &[
+    "H(a) => RZ(pi/2, a); SX(a); RZ(pi/2, a)",
+    "H(a) => Z(a); RY(pi/2, a)",
+    "H(a) => RX(pi/2, a); RZ(pi/2, a); RX(pi/2, a)",
+    "X(a) => SX(a); SX(a)",
+    "X(a) => RX(pi, a)",
+    "X(a) => H(a); Z(a); H(a)",
+    "Y(a) => Z(a); X(a)",
+    "Y(a) => RY(pi, a)",
+    "Z(a) => RZ(pi, a)",
+    "Z(a) => S(a); S(a)",
+    "S(a) => RZ(pi/2, a)",
+    "S(a) => T(a); T(a)",
+    "Sdg(a) => RZ(-pi/2, a)",
+    "Sdg(a) => Z(a); S(a)",
+    "Sdg(a) => Tdg(a); Tdg(a)",
+    "T(a) => RZ(pi/4, a)",
+    "Tdg(a) => RZ(-pi/4, a)",
+    "SX(a) => RX(pi/2, a)",
+    "SX(a) => H(a); S(a); H(a)",
+    "SXdg(a) => RX(-pi/2, a)",
+    "SXdg(a) => H(a); Sdg(a); H(a)",
+    "RX(x, a) => H(a); RZ(x, a); H(a)",
+    "RX(x, a) => RZ(pi/2, a); RY(x, a); RZ(-pi/2, a)",
+    "RY(x, a) => SX(a); RZ(x, a); SXdg(a)",
+    "RY(x, a) => RZ(-pi/2, a); RX(x, a); RZ(pi/2, a)",
+    "RZ(x, a) => RX(-pi/2, a); RY(x, a); RX(pi/2, a)",
+    "RZ(0, a) =>",
+    "RZ(pi/4, a) => T(a)",
+    "RZ(-pi/4, a) => Tdg(a)",
+    "RZ(pi/2, a) => S(a)",
+    "RZ(-pi/2, a) => Sdg(a)",
+    "RZ(3*pi/4, a) => S(a); T(a)",
+    "RZ(-3*pi/4, a) => Sdg(a); Tdg(a)",
+    "RZ(pi, a) => Z(a)",
+    "U3(x, y, z, a) => RZ(z, a); RY(x, a); RZ(y, a)",
+    "CX(a, b) => H(b); CZ(a, b); H(b)",
+    "CZ(a, b) => H(b); CX(a, b); H(b)",
+    "SWAP(a, b) => CX(a, b); CX(b, a); CX(a, b)",
+    "CCX(a, b, c) => H(c); CX(b, c); Tdg(c); CX(a, c); T(c); CX(b, c); Tdg(c); CX(a, c); \
+     T(b); T(c); H(c); CX(a, b); T(a); Tdg(b); CX(a, b)",
+];
+
+pub fn equivalences() -> Vec<Rule> {
+    EQUIVALENCES
+        .iter()
+        .map(|text| Rule::parse_decomposition(text).expect("built-in equivalence parses"))
+        .collect()
+}
+
+// Rewrites each gate the target does not run into gates it does, trying
+// the decompositions of a gate from the cheapest, by what its gates cost
+// in the basis, until one gets all the way there.
+pub fn translate(ops: Vec<Op>, target: &Target, rules: &[Rule]) -> Result<Vec<Op>> {
+    let mut search = Search {
+        target,
+        rules,
+        costs: costs(target, rules),
+        open: Vec::new(),
+        cut: false,
+        failed: HashSet::new(),
+    };
+    let mut out = Vec::new();
+    for op in ops {
+        let Some(gates) = search.decompose(&op) else {
+            let basis = target.basis().unwrap_or_default().join(", ");
+            bail!(
+                "{}: cannot express `{}` in the basis {}",
+                op.span,
+                op,
+                basis
+            );
+        };
+        out.extend(gates);
+    }
+    Ok(out)
+}
+
+// What each gate costs at best once translated: one for each gate of the
+// basis it becomes, and ten for each one on more than one qubit. Only
+// decompositions of any angle count, so that `RZ(0, a) =>` does not make
+// RZ free.
+fn costs<'a>(target: &Target, rules: &'a [Rule]) -> HashMap<&'a str, usize> {
+    let mut costs: HashMap<&str, usize> = HashMap::new();
+    for gate in BUILTIN.iter().filter(|g| target.supports(g)) {
+        let cost = if matches!(canonical(gate), "CX" | "CZ" | "SWAP" | "CCX" | "MCX") {
+            10
+        } else {
+            1
+        };
+        costs.insert(canonical(gate), cost);
+    }
+    // Costs only go down, so this stops.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules {
+            let gate = &rule.pattern[0];
+            if gate.params.iter().any(|p| p.name().is_none()) {
+                continue;
+            }
+            let Some(cost) = replacement_cost(rule, &costs) else {
+                continue;
+            };
+            if costs.get(canonical(&gate.name)).is_none_or(|c| cost < *c) {
+                costs.insert(canonical(&gate.name), cost);
+                changed = true;
+            }
+        }
+    }
+    costs
+}
+
+fn replacement_cost(rule: &Rule, costs: &HashMap<&str, usize>) -> Option<usize> {
+    rule.replacement
+        .iter()
+        .map(|g| costs.get(canonical(&g.name)).copied())
+        .sum()
+}
+
+struct Search<'a> {
+    target: &'a Target,
+    rules: &'a [Rule],
+    costs: HashMap<&'a str, usize>,
+    // Gates being decomposed, as their text without qubits: decomposing one
+    // again inside its own decomposition goes in circles. A gate that fails
+    // only because of that may still have a decomposition, so only gates
+    // that failed without `cut` are remembered as failing.
+    open: Vec<String>,
+    cut: bool,
+    failed: HashSet<String>,
+}
+
+impl Search<'_> {
+    fn decompose(&mut self, op: &Op) -> Option<Vec<Op>> {
+        let OpKind::Gate { name, params, .. } = &op.kind else {
+            return Some(vec![op.clone()]);
+        };
+        if self.target.supports(name) {
+            return Some(vec![op.clone()]);
+        }
+        let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+        let key = format!("{}({})", canonical(name), params.join(", "));
+        if self.open.contains(&key) {
+            self.cut = true;
+            return None;
+        }
+        if self.failed.contains(&key) {
+            return None;
+        }
+
+        // Cheapest first. Decompositions into gates with no cost, which get
+        // to the basis for some angles at most, come last.
+        let mut candidates: Vec<(Option<usize>, &Rule, Bindings)> = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let mut bindings = Bindings::default();
+                bind(op, &rule.pattern[0], &mut bindings)
+                    .then(|| (replacement_cost(rule, &self.costs), rule, bindings))
+            })
+            .collect();
+        candidates.sort_by_key(|(cost, _, _)| cost.unwrap_or(usize::MAX));
+
+        let outer = std::mem::replace(&mut self.cut, false);
+        self.open.push(key.clone());
+        let found = candidates.into_iter().find_map(|(_, rule, bindings)| {
+            let mut out = Vec::new();
+            for gate in &rule.replacement {
+                out.extend(self.decompose(&gate.instantiate(&bindings, op.span))?);
+            }
+            Some(out)
+        });
+        self.open.pop();
+        if found.is_none() && !self.cut {
+            self.failed.insert(key);
+        }
+        self.cut |= outer;
+        found
+    }
+}
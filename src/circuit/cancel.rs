@@ -0,0 +1,85 @@
+use super::gates::{commutes, undoes};
+use super::rewrite::{identities, rewrite_identities, runs_on, RuleCounts};
+use super::rotation::merge_rotations;
+use super::rules::Rule;
+use super::swap::recognize_swaps;
+use super::{Dag, NodeId, Op, Target};
+
+// Removes pairs of gates that undo each other, such as `H(a); H(a);` or
+// `S(a); Sdg(a);`, also when the gates between them commute with the first
+// one: gates on other qubits, phase gates across the control of a CX, and
+// X across its target. Rotations are merged the same way (see `rotation`),
+// and runs of gates on one qubit are rewritten by `rewrite::IDENTITIES`.
+pub fn cancel(ops: Vec<Op>) -> Vec<Op> {
+    cancel_counting(
+        ops,
+        &Target::default(),
+        &identities(),
+        &mut RuleCounts::new(),
+    )
+}
+
+// Like `cancel` with `rules` in place of the built-in identities, adding up
+// the gates each rule removed in `counts`. Gates only the target has, such
+// as SWAP, are introduced only for it.
+pub fn cancel_counting(
+    ops: Vec<Op>,
+    target: &Target,
+    rules: &[Rule],
+    counts: &mut RuleCounts,
+) -> Vec<Op> {
+    let rules: Vec<Rule> = rules
+        .iter()
+        .filter(|r| runs_on(r, target))
+        .cloned()
+        .collect();
+    let mut dag = Dag::from_ops(ops);
+    while cancel_inverses(&mut dag)
+        + merge_rotations(&mut dag)
+        + rewrite_identities(&mut dag, &rules, counts)
+        + recognize_swaps(&mut dag, target)
+        > 0
+    {}
+    dag.to_ops()
+}
+
+// Returns the number of pairs removed.
+pub fn cancel_inverses(dag: &mut Dag) -> usize {
+    let mut removed = 0;
+    loop {
+        let mut changed = false;
+        for id in dag.topological_order() {
+            if !dag.contains(id) {
+                continue;
+            }
+            if let Some(partner) = partner(dag, id) {
+                dag.remove(id);
+                dag.remove(partner);
+                removed += 1;
+                changed = true;
+            }
+        }
+        if !changed {
+            return removed;
+        }
+    }
+}
+
+// The later gate that undoes `id`, if `id` commutes with everything in
+// between. Following each of its wires past the gates it commutes with must
+// end at that same gate.
+fn partner(dag: &Dag, id: NodeId) -> Option<NodeId> {
+    let op = dag.op(id);
+    let mut found = None;
+    for wire in dag.wires(id) {
+        let mut at = dag.successor(id, wire)?;
+        while commutes(op, dag.op(at)) && !undoes(op, dag.op(at)) {
+            at = dag.successor(at, wire)?;
+        }
+        if !undoes(op, dag.op(at)) || found.is_some_and(|f| f != at) {
+            return None;
+        }
+        found = Some(at);
+    }
+    found
+}
@@ -0,0 +1,411 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use super::{Op, Wire};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+// A circuit as a DAG: each node is an operation, and each wire it acts on
+// links it to the previous and next operation on that wire. Two operations
+// that share no wire are unordered, so `H(a); X(b); H(a);` has the two `H`s
+// next to each other on `a`.
+#[derive(Debug, Clone, Default)]
+pub struct Dag {
+    // Indexed by `NodeId`; removed nodes are left empty.
+    nodes: Vec<Option<Node>>,
+    first: HashMap<Wire, NodeId>,
+    last: HashMap<Wire, NodeId>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    op: Op,
+    wires: Vec<Wire>,
+    // Per wire, in the same order as `wires`.
+    prev: Vec<Option<NodeId>>,
+    next: Vec<Option<NodeId>>,
+}
+
+impl Dag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ops(ops: impl IntoIterator<Item = Op>) -> Self {
+        let mut dag = Self::new();
+        for op in ops {
+            dag.push(op);
+        }
+        dag
+    }
+
+    // Appends an operation after everything already on its wires.
+    pub fn push(&mut self, op: Op) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let wires = unique_wires(&op);
+        let prev: Vec<Option<NodeId>> = wires.iter().map(|w| self.last.get(w).copied()).collect();
+
+        for (wire, prev) in wires.iter().zip(&prev) {
+            match prev {
+                Some(p) => self.set_next(*p, wire, Some(id)),
+                None => {
+                    self.first.insert(wire.clone(), id);
+                }
+            }
+            self.last.insert(wire.clone(), id);
+        }
+        self.nodes.push(Some(Node {
+            op,
+            next: vec![None; wires.len()],
+            wires,
+            prev,
+        }));
+        id
+    }
+
+    // Takes a node out, joining its neighbours on each wire.
+    pub fn remove(&mut self, id: NodeId) -> Op {
+        let node = self.nodes[id.0].take().expect("node was already removed");
+        for ((wire, prev), next) in node.wires.iter().zip(&node.prev).zip(&node.next) {
+            match prev {
+                Some(p) => self.set_next(*p, wire, *next),
+                None => match next {
+                    Some(n) => {
+                        self.first.insert(wire.clone(), *n);
+                    }
+                    None => {
+                        self.first.remove(wire);
+                    }
+                },
+            }
+            match next {
+                Some(n) => self.set_prev(*n, wire, *prev),
+                None => match prev {
+                    Some(p) => {
+                        self.last.insert(wire.clone(), *p);
+                    }
+                    None => {
+                        self.last.remove(wire);
+                    }
+                },
+            }
+        }
+        node.op
+    }
+
+    // Swaps the operation of a node for one on the same wires.
+    pub fn replace(&mut self, id: NodeId, op: Op) {
+        let node = self.node_mut(id);
+        let wires = unique_wires(&op);
+        assert_eq!(wires, node.wires, "replacement must act on the same wires");
+        node.op = op;
+    }
+
+    // Swaps `ids` for `ops`. The nodes must be given in order and follow
+    // one another on each of their wires, with no path out of them leading
+    // back in, and `ops` may only use their wires.
+    pub fn splice(&mut self, ids: &[NodeId], ops: Vec<Op>) -> Vec<NodeId> {
+        let mut before: HashMap<Wire, Option<NodeId>> = HashMap::new();
+        let mut after: HashMap<Wire, Option<NodeId>> = HashMap::new();
+        for id in ids {
+            for wire in self.wires(*id) {
+                before
+                    .entry(wire.clone())
+                    .or_insert(self.predecessor(*id, wire));
+                after.insert(wire.clone(), self.successor(*id, wire));
+            }
+        }
+        for id in ids {
+            self.remove(*id);
+        }
+
+        let mut added = Vec::new();
+        for op in ops {
+            let id = NodeId(self.nodes.len());
+            let wires = unique_wires(&op);
+            let mut prev = Vec::new();
+            let mut next = Vec::new();
+            for wire in &wires {
+                let p = *before
+                    .get(wire)
+                    .expect("spliced operations must stay on the same wires");
+                let n = after[wire];
+                match p {
+                    Some(p) => self.set_next(p, wire, Some(id)),
+                    None => {
+                        self.first.insert(wire.clone(), id);
+                    }
+                }
+                match n {
+                    Some(n) => self.set_prev(n, wire, Some(id)),
+                    None => {
+                        self.last.insert(wire.clone(), id);
+                    }
+                }
+                before.insert(wire.clone(), Some(id));
+                prev.push(p);
+                next.push(n);
+            }
+            self.nodes.push(Some(Node {
+                op,
+                wires,
+                prev,
+                next,
+            }));
+            added.push(id);
+        }
+        added
+    }
+
+    // Whether no path leaves `ids` and comes back into them, so that they
+    // can be replaced where they are.
+    pub fn is_convex(&self, ids: &[NodeId]) -> bool {
+        let inside: HashSet<NodeId> = ids.iter().copied().collect();
+        let mut stack: Vec<NodeId> = ids
+            .iter()
+            .flat_map(|id| self.successors(*id))
+            .filter(|id| !inside.contains(id))
+            .collect();
+        let mut seen = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            for succ in self.successors(id) {
+                if inside.contains(&succ) {
+                    return false;
+                }
+                stack.push(succ);
+            }
+        }
+        true
+    }
+
+    // Maximal sets of operations that `keep` accepts, connected by their
+    // wires and convex, each in program order.
+    pub fn regions(&self, keep: impl Fn(&Op) -> bool) -> Vec<Vec<NodeId>> {
+        let order = self.topological_order();
+        let mut taken: HashSet<NodeId> = HashSet::new();
+        let mut regions = Vec::new();
+        for id in &order {
+            if taken.contains(id) || !keep(self.op(*id)) {
+                continue;
+            }
+            let mut region = vec![*id];
+            let mut stack = vec![*id];
+            while let Some(at) = stack.pop() {
+                for next in self.successors(at).into_iter().chain(self.predecessors(at)) {
+                    if taken.contains(&next) || region.contains(&next) || !keep(self.op(next)) {
+                        continue;
+                    }
+                    region.push(next);
+                    if self.is_convex(&region) {
+                        stack.push(next);
+                    } else {
+                        region.pop();
+                    }
+                }
+            }
+            taken.extend(&region);
+            regions.push(
+                order
+                    .iter()
+                    .copied()
+                    .filter(|id| region.contains(id))
+                    .collect(),
+            );
+        }
+        regions
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.get(id.0).is_some_and(Option::is_some)
+    }
+
+    pub fn op(&self, id: NodeId) -> &Op {
+        &self.node(id).op
+    }
+
+    pub fn wires(&self, id: NodeId) -> &[Wire] {
+        &self.node(id).wires
+    }
+
+    // The operation before `id` on `wire`, if any.
+    pub fn predecessor(&self, id: NodeId, wire: &Wire) -> Option<NodeId> {
+        let node = self.node(id);
+        node.prev[node.position(wire)?]
+    }
+
+    pub fn successor(&self, id: NodeId, wire: &Wire) -> Option<NodeId> {
+        let node = self.node(id);
+        node.next[node.position(wire)?]
+    }
+
+    // Every operation directly before `id` on any of its wires.
+    pub fn predecessors(&self, id: NodeId) -> Vec<NodeId> {
+        let mut preds: Vec<NodeId> = self.node(id).prev.iter().flatten().copied().collect();
+        preds.sort();
+        preds.dedup();
+        preds
+    }
+
+    pub fn successors(&self, id: NodeId) -> Vec<NodeId> {
+        let mut succs: Vec<NodeId> = self.node(id).next.iter().flatten().copied().collect();
+        succs.sort();
+        succs.dedup();
+        succs
+    }
+
+    pub fn first(&self, wire: &Wire) -> Option<NodeId> {
+        self.first.get(wire).copied()
+    }
+
+    pub fn last(&self, wire: &Wire) -> Option<NodeId> {
+        self.last.get(wire).copied()
+    }
+
+    // The operations on one wire, in order.
+    pub fn on_wire(&self, wire: &Wire) -> Vec<NodeId> {
+        let mut ids = Vec::new();
+        let mut at = self.first(wire);
+        while let Some(id) = at {
+            ids.push(id);
+            at = self.successor(id, wire);
+        }
+        ids
+    }
+
+    // Every wire with an operation on it, sorted.
+    pub fn all_wires(&self) -> Vec<&Wire> {
+        let mut wires: Vec<&Wire> = self.first.keys().collect();
+        wires.sort();
+        wires
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.is_some())
+            .map(|(i, _)| NodeId(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_ids().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Groups operations by how many others must run before them: each
+    // layer only depends on earlier ones, and its operations share no wire.
+    pub fn layers(&self) -> Vec<Vec<NodeId>> {
+        let mut layer_of: HashMap<NodeId, usize> = HashMap::new();
+        let mut layers: Vec<Vec<NodeId>> = Vec::new();
+        for id in self.topological_order() {
+            let layer = self
+                .predecessors(id)
+                .iter()
+                .map(|p| layer_of[p] + 1)
+                .max()
+                .unwrap_or(0);
+            layer_of.insert(id, layer);
+            if layer == layers.len() {
+                layers.push(Vec::new());
+            }
+            layers[layer].push(id);
+        }
+        layers
+    }
+
+    pub fn depth(&self) -> usize {
+        self.layers().len()
+    }
+
+    // Every node after all of its predecessors. Among the nodes that are
+    // ready, the oldest goes first, so an unchanged DAG gives back its
+    // operations in the order they were pushed.
+    pub fn topological_order(&self) -> Vec<NodeId> {
+        let mut waiting: HashMap<NodeId, usize> = self
+            .node_ids()
+            .map(|id| (id, self.predecessors(id).len()))
+            .collect();
+        let mut ready: BTreeSet<NodeId> = waiting
+            .iter()
+            .filter(|(_, n)| **n == 0)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut order = Vec::new();
+        while let Some(id) = ready.pop_first() {
+            order.push(id);
+            for succ in self.successors(id) {
+                let n = waiting.get_mut(&succ).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.insert(succ);
+                }
+            }
+        }
+        order
+    }
+
+    pub fn to_ops(&self) -> Vec<Op> {
+        self.topological_order()
+            .into_iter()
+            .map(|id| self.op(id).clone())
+            .collect()
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().expect("node was removed")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().expect("node was removed")
+    }
+
+    fn set_next(&mut self, id: NodeId, wire: &Wire, next: Option<NodeId>) {
+        let node = self.node_mut(id);
+        let i = node.position(wire).unwrap();
+        node.next[i] = next;
+    }
+
+    fn set_prev(&mut self, id: NodeId, wire: &Wire, prev: Option<NodeId>) {
+        let node = self.node_mut(id);
+        let i = node.position(wire).unwrap();
+        node.prev[i] = prev;
+    }
+}
+
+fn unique_wires(op: &Op) -> Vec<Wire> {
+    let mut wires = Vec::new();
+    for wire in op.wires() {
+        if !wires.contains(&wire) {
+            wires.push(wire);
+        }
+    }
+    wires
+}
+
+impl Node {
+    fn position(&self, wire: &Wire) -> Option<usize> {
+        self.wires.iter().position(|w| w == wire)
+    }
+}
+
+// One layer per line:
+//
+//     0: H(a) X(b)
+//     1: H(a)
+impl fmt::Display for Dag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, layer) in self.layers().iter().enumerate() {
+            let ops: Vec<String> = layer.iter().map(|id| self.op(*id).to_string()).collect();
+            writeln!(f, "{}: {}", i, ops.join(" "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::ast::Angle;
+
+use super::unitary::{compose, matrix, Complex, Matrix2};
+use super::{Dag, Op, OpKind, Target};
+
+// Rotations by less than this are no rotation.
+const EPSILON: f64 = 1e-9;
+
+// Collapses each run of single-qubit gates on one qubit into one
+// `U3(θ, φ, λ)`, or into `RZ(λ); RY(θ); RZ(φ);` on a target that has RZ and
+// RY but no U3, from the ZYZ Euler angles of the product of the run. A run
+// is only replaced when that leaves fewer gates.
+pub fn fuse(ops: Vec<Op>, target: &Target) -> Vec<Op> {
+    let mut dag = Dag::from_ops(ops);
+    fuse_single_qubit(&mut dag, target);
+    dag.to_ops()
+}
+
+// Returns the number of gates removed.
+pub fn fuse_single_qubit(dag: &mut Dag, target: &Target) -> usize {
+    let zyz = if target.supports("U3") {
+        false
+    } else if target.supports("RZ") && target.supports("RY") {
+        true
+    } else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for id in dag.topological_order() {
+        if !dag.contains(id) {
+            continue;
+        }
+        let Some(mut product) = matrix(dag.op(id)) else {
+            continue;
+        };
+        let wire = dag.wires(id)[0].clone();
+        if dag
+            .predecessor(id, &wire)
+            .is_some_and(|p| matrix(dag.op(p)).is_some())
+        {
+            continue;
+        }
+
+        let mut run = vec![id];
+        while let Some(next) = dag.successor(run[run.len() - 1], &wire) {
+            let Some(m) = matrix(dag.op(next)) else {
+                break;
+            };
+            product = compose(&m, &product);
+            run.push(next);
+        }
+
+        let fused = Euler::of(&product).ops(dag.op(id), zyz);
+        if fused.len() >= run.len() {
+            continue;
+        }
+        for (i, node) in run.iter().enumerate() {
+            match fused.get(i) {
+                Some(op) => dag.replace(*node, op.clone()),
+                None => {
+                    dag.remove(*node);
+                }
+            }
+        }
+        removed += run.len() - fused.len();
+    }
+    removed
+}
+
+// A single-qubit unitary as `RZ(φ)·RY(θ)·RZ(λ)`, up to a global phase.
+struct Euler {
+    theta: f64,
+    phi: f64,
+    lambda: f64,
+}
+
+impl Euler {
+    fn of(m: &Matrix2) -> Self {
+        // Dividing out half the phase of the determinant leaves
+        // [[e^(-i(φ+λ)/2)·cos(θ/2), ..], [e^(i(φ-λ)/2)·sin(θ/2), ..]].
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let unphase = Complex::polar(1.0, -det.arg() / 2.0);
+        let (a, b) = (m[0][0] * unphase, m[1][0] * unphase);
+
+        let theta = 2.0 * b.abs().atan2(a.abs());
+        let (phi, lambda) = if b.abs() < EPSILON {
+            (-2.0 * a.arg(), 0.0)
+        } else if a.abs() < EPSILON {
+            (2.0 * b.arg(), 0.0)
+        } else {
+            (b.arg() - a.arg(), -a.arg() - b.arg())
+        };
+        Self { theta, phi, lambda }
+    }
+
+    fn ops(&self, like: &Op, zyz: bool) -> Vec<Op> {
+        let gate = |name: &str, params: Vec<Angle>| Op {
+            kind: OpKind::Gate {
+                name: name.to_string(),
+                params,
+                qubits: like.qubits().to_vec(),
+            },
+            span: like.span,
+        };
+        let turn = Angle::nearest(self.phi + self.lambda);
+        if self.theta < EPSILON && turn.is_zero() {
+            return Vec::new();
+        }
+        if !zyz {
+            let angles = [self.theta, self.phi, self.lambda];
+            return vec![gate("U3", angles.map(Angle::nearest).to_vec())];
+        }
+        if self.theta < EPSILON {
+            return vec![gate("RZ", vec![turn])];
+        }
+        [("RZ", self.lambda), ("RY", self.theta), ("RZ", self.phi)]
+            .into_iter()
+            .map(|(name, angle)| (name, Angle::nearest(angle)))
+            .filter(|(_, angle)| !angle.is_zero())
+            .map(|(name, angle)| gate(name, vec![angle]))
+            .collect()
+    }
+}
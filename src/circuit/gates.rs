@@ -0,0 +1,120 @@
+use crate::ast::QubitRef;
+
+use super::{Op, OpKind, Wire};
+
+pub const BUILTIN: &[&str] = &[
+    "H", "X", "Y", "Z", "S", "Sdg", "T", "Tdg", "SX", "SXdg", "RX", "RY", "RZ", "U3", "CX", "CNOT",
+    "CZ", "CCX", "MCX", "SWAP",
+];
+
+// The basis a gate is diagonal in on one of its qubits: Z for phase gates
+// and the controls of CX/CCX, X for X and the target of CX/CCX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+// The gate that undoes `gate`, when it is a built-in one.
+pub fn inverse(gate: &str) -> Option<&'static str> {
+    let inv = match gate {
+        "H" => "H",
+        "X" => "X",
+        "Y" => "Y",
+        "Z" => "Z",
+        "CX" => "CX",
+        "CNOT" => "CNOT",
+        "CZ" => "CZ",
+        "CCX" => "CCX",
+        "MCX" => "MCX",
+        "SWAP" => "SWAP",
+        "S" => "Sdg",
+        "Sdg" => "S",
+        "T" => "Tdg",
+        "Tdg" => "T",
+        "SX" => "SXdg",
+        "SXdg" => "SX",
+        _ => return None,
+    };
+    Some(inv)
+}
+
+// The name a gate goes by here: `CNOT` is `CX`.
+pub fn canonical(gate: &str) -> &str {
+    match gate {
+        "CNOT" => "CX",
+        _ => gate,
+    }
+}
+
+// Whether `b` undoes `a`: it is the inverse gate on the same qubits, where
+// the two controls of CCX may come in either order, and so may the qubits
+// of SWAP and CZ.
+pub fn undoes(a: &Op, b: &Op) -> bool {
+    let (
+        OpKind::Gate {
+            name: x,
+            params: px,
+            qubits: qx,
+        },
+        OpKind::Gate {
+            name: y,
+            params: py,
+            qubits: qy,
+        },
+    ) = (&a.kind, &b.kind)
+    else {
+        return false;
+    };
+    px.is_empty()
+        && py.is_empty()
+        && inverse(x).map(canonical) == Some(canonical(y))
+        && same_operands(canonical(x), qx, qy)
+}
+
+fn same_operands(gate: &str, a: &[QubitRef], b: &[QubitRef]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let swapped = |i: usize, j: usize| a[i] == b[j] && a[j] == b[i];
+    match gate {
+        "CCX" => a[2] == b[2] && (a[..2] == b[..2] || swapped(0, 1)),
+        "SWAP" | "CZ" => a == b || swapped(0, 1),
+        _ => a == b,
+    }
+}
+
+pub fn axis(gate: &str, operand: usize, operands: usize) -> Option<Axis> {
+    match gate {
+        "X" | "SX" | "SXdg" | "RX" => Some(Axis::X),
+        "Y" | "RY" => Some(Axis::Y),
+        "Z" | "S" | "Sdg" | "T" | "Tdg" | "RZ" => Some(Axis::Z),
+        "CX" | "CNOT" | "CCX" | "MCX" if operand + 1 == operands => Some(Axis::X),
+        "CX" | "CNOT" | "CCX" | "MCX" | "CZ" => Some(Axis::Z),
+        _ => None,
+    }
+}
+
+pub fn axis_on(op: &Op, wire: &Wire) -> Option<Axis> {
+    let OpKind::Gate { name, qubits, .. } = &op.kind else {
+        return None;
+    };
+    let i = op.wires().iter().position(|w| w == wire)?;
+    axis(name, i, qubits.len())
+}
+
+// Whether `a` and `b` can swap places. They do when, on every wire they
+// share, both are diagonal in the same basis: written in that basis, each
+// is then a sum of terms that act on their shared wires as the same
+// projector, and on the rest as operators on disjoint wires.
+pub fn commutes(a: &Op, b: &Op) -> bool {
+    let b_wires = b.wires();
+    a.wires()
+        .iter()
+        .filter(|w| b_wires.contains(w))
+        .all(|w| match (axis_on(a, w), axis_on(b, w)) {
+            (Some(x), Some(y)) => x == y,
+            _ => false,
+        })
+}
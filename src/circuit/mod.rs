@@ -0,0 +1,304 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+use crate::ast::{Angle, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::lexer::Span;
+
+pub mod basis;
+pub mod cancel;
+pub mod dag;
+pub mod fuse;
+pub mod gates;
+pub mod pass;
+pub mod phase;
+pub mod rewrite;
+pub mod rotation;
+pub mod rules;
+pub mod swap;
+pub mod target;
+pub mod toffoli;
+pub mod unitary;
+pub mod zx;
+
+pub use cancel::{cancel, cancel_counting};
+pub use dag::{Dag, NodeId};
+pub use fuse::fuse;
+pub use pass::{Pass, PassManager, PassStats};
+pub use phase::{phase_polynomials, PhaseCounts};
+pub use target::Target;
+
+// Which optimizer runs on the straight-line runs of the residual program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Optimizer {
+    // Peephole rewrites, then phase polynomials.
+    #[default]
+    Peephole,
+    // ZX-calculus simplification, then the peephole pipeline on what it
+    // extracted.
+    Zx,
+}
+
+impl FromStr for Optimizer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "peephole" => Ok(Optimizer::Peephole),
+            "zx" => Ok(Optimizer::Zx),
+            _ => bail!("unknown optimizer `{}` (expected peephole or zx)", s),
+        }
+    }
+}
+
+// A qubit or classical bit, as a wire running through a circuit. Qubits are
+// named by their operand text, so `r[0]` and `r[1]` are different wires.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Wire {
+    Qubit(String),
+    Bit(String),
+}
+
+// One quantum operation of a straight-line run of statements.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Op {
+    pub kind: OpKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpKind {
+    Gate {
+        name: String,
+        params: Vec<Angle>,
+        qubits: Vec<QubitRef>,
+    },
+    Measure {
+        qubit: QubitRef,
+        bit: Option<String>,
+    },
+    Reset {
+        qubit: QubitRef,
+    },
+}
+
+impl Op {
+    pub fn gate(name: impl Into<String>, qubits: Vec<QubitRef>, span: Span) -> Self {
+        Self {
+            kind: OpKind::Gate {
+                name: name.into(),
+                params: Vec::new(),
+                qubits,
+            },
+            span,
+        }
+    }
+
+    // The statement as an operation, if it is a quantum statement without
+    // attributes whose operands name fixed qubits.
+    pub fn from_stmt(stmt: &Stmt) -> Option<Self> {
+        if !stmt.attrs.is_empty() {
+            return None;
+        }
+        let kind = match &stmt.kind {
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits,
+            } => OpKind::Gate {
+                name: gate.clone(),
+                params: params.clone(),
+                qubits: qubits.clone(),
+            },
+            StmtKind::Measure { target, classical } => OpKind::Measure {
+                qubit: target.clone(),
+                bit: classical.clone(),
+            },
+            StmtKind::Reset { target } => OpKind::Reset {
+                qubit: target.clone(),
+            },
+            _ => return None,
+        };
+        let op = Self {
+            kind,
+            span: stmt.span,
+        };
+        let fixed = op
+            .qubits()
+            .iter()
+            .all(|q| matches!(q.index, None | Some(Expr::Number(_))));
+        fixed.then_some(op)
+    }
+
+    pub fn to_stmt(&self) -> Stmt {
+        let kind = match &self.kind {
+            OpKind::Gate {
+                name,
+                params,
+                qubits,
+            } => StmtKind::QOp {
+                gate: name.clone(),
+                params: params.clone(),
+                qubits: qubits.clone(),
+            },
+            OpKind::Measure { qubit, bit } => StmtKind::Measure {
+                target: qubit.clone(),
+                classical: bit.clone(),
+            },
+            OpKind::Reset { qubit } => StmtKind::Reset {
+                target: qubit.clone(),
+            },
+        };
+        Stmt {
+            kind,
+            attrs: Vec::new(),
+            span: self.span,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.kind {
+            OpKind::Gate { name, .. } => name,
+            OpKind::Measure { .. } => "measure",
+            OpKind::Reset { .. } => "reset",
+        }
+    }
+
+    pub fn qubits(&self) -> &[QubitRef] {
+        match &self.kind {
+            OpKind::Gate { qubits, .. } => qubits,
+            OpKind::Measure { qubit, .. } | OpKind::Reset { qubit } => std::slice::from_ref(qubit),
+        }
+    }
+
+    // Qubits in operand order, then the bit a measurement writes.
+    pub fn wires(&self) -> Vec<Wire> {
+        let mut wires: Vec<Wire> = self
+            .qubits()
+            .iter()
+            .map(|q| Wire::Qubit(q.to_string()))
+            .collect();
+        if let OpKind::Measure { bit: Some(bit), .. } = &self.kind {
+            wires.push(Wire::Bit(bit.clone()));
+        }
+        wires
+    }
+}
+
+// Rewrites every maximal run of operations in `stmts`, including those in
+// nested blocks, with `f`. Anything that is not an operation ends a run, and
+// statements with attributes are left alone along with their bodies.
+pub fn map_runs(stmts: &[Stmt], f: &mut impl FnMut(Vec<Op>) -> Vec<Op>) -> Vec<Stmt> {
+    let mut out = Vec::new();
+    let mut run = Vec::new();
+    for stmt in stmts {
+        if let Some(op) = Op::from_stmt(stmt) {
+            run.push(op);
+            continue;
+        }
+        flush(&mut run, &mut out, f);
+        if !stmt.attrs.is_empty() {
+            out.push(stmt.clone());
+            continue;
+        }
+
+        let mut stmt = stmt.clone();
+        match &mut stmt.kind {
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                *then_body = map_runs(then_body, f);
+                if let Some(body) = else_body {
+                    *body = map_runs(body, f);
+                }
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => *body = map_runs(body, f),
+            _ => {}
+        }
+        out.push(stmt);
+    }
+    flush(&mut run, &mut out, f);
+    out
+}
+
+fn flush(run: &mut Vec<Op>, out: &mut Vec<Stmt>, f: &mut impl FnMut(Vec<Op>) -> Vec<Op>) {
+    if !run.is_empty() {
+        out.extend(f(std::mem::take(run)).iter().map(Op::to_stmt));
+    }
+}
+
+// Like `map_runs` over every function, but only for what is evaluated under
+// PE. As in the partial evaluator, a PE toggle on a statement switches it
+// for the rest of the block.
+pub fn map_pe_runs(program: &Program, f: &mut impl FnMut(Vec<Op>) -> Vec<Op>) -> Program {
+    let functions = program
+        .functions
+        .iter()
+        .map(|func| Function {
+            body: map_pe_block(&func.body, func.pe_enabled, f),
+            ..func.clone()
+        })
+        .collect();
+    Program { functions }
+}
+
+fn map_pe_block(stmts: &[Stmt], mut pe: bool, f: &mut impl FnMut(Vec<Op>) -> Vec<Op>) -> Vec<Stmt> {
+    let mut out = Vec::new();
+    let mut run = Vec::new();
+    for stmt in stmts {
+        if let Some(on) = stmt
+            .attrs
+            .iter()
+            .rev()
+            .find_map(|a| a.annotation.pe_toggle())
+        {
+            pe = on;
+        }
+        match Op::from_stmt(stmt) {
+            Some(op) if pe => {
+                run.push(op);
+                continue;
+            }
+            _ => {}
+        }
+        flush(&mut run, &mut out, f);
+
+        let mut stmt = stmt.clone();
+        match &mut stmt.kind {
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                *then_body = map_pe_block(then_body, pe, f);
+                if let Some(body) = else_body {
+                    *body = map_pe_block(body, pe, f);
+                }
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => *body = map_pe_block(body, pe, f),
+            _ => {}
+        }
+        out.push(stmt);
+    }
+    flush(&mut run, &mut out, f);
+    out
+}
+
+impl fmt::Display for Wire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wire::Qubit(name) | Wire::Bit(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// Prints as the statement it stands for, without the trailing `;`.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stmt = self.to_stmt().kind.to_string();
+        write!(f, "{}", stmt.trim_end_matches(';'))
+    }
+}
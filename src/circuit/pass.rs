@@ -0,0 +1,196 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Error, Result};
+
+use crate::ast::Program;
+
+use super::basis::{equivalences, translate};
+use super::rewrite::RuleCounts;
+use super::rules::Rule;
+use super::toffoli::{decompose_toffolis, ToffoliOptions};
+use super::{
+    cancel_counting, fuse, map_pe_runs, phase_polynomials, zx, Dag, Op, OpKind, Optimizer,
+    PhaseCounts, Target,
+};
+
+// One optimization of a straight-line run of quantum operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Zx,
+    Cancel,
+    Phase,
+    Fuse,
+    Toffoli,
+    Basis,
+}
+
+impl Pass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Zx => "zx",
+            Pass::Cancel => "cancel",
+            Pass::Phase => "phase",
+            Pass::Fuse => "fuse",
+            Pass::Toffoli => "toffoli",
+            Pass::Basis => "basis",
+        }
+    }
+}
+
+impl FromStr for Pass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zx" => Ok(Pass::Zx),
+            "cancel" => Ok(Pass::Cancel),
+            "phase" => Ok(Pass::Phase),
+            "fuse" => Ok(Pass::Fuse),
+            "toffoli" => Ok(Pass::Toffoli),
+            "basis" => Ok(Pass::Basis),
+            _ => bail!(
+                "unknown pass `{}` (expected zx, cancel, phase, fuse, toffoli or basis)",
+                s
+            ),
+        }
+    }
+}
+
+// The passes of an optimization level: none at 0, peephole rewrites at 1,
+// then phase polynomials and single-qubit fusion at 2, and the peephole
+// rewrites once more on what those leave at 3. The ZX optimizer runs
+// ZX-calculus simplification before them at every level.
+pub fn preset(level: u8, optimizer: Optimizer) -> Vec<Pass> {
+    let mut passes = match level {
+        0 => return Vec::new(),
+        1 => vec![Pass::Cancel],
+        2 => vec![Pass::Cancel, Pass::Phase, Pass::Fuse],
+        _ => vec![Pass::Cancel, Pass::Phase, Pass::Fuse, Pass::Cancel],
+    };
+    if optimizer == Optimizer::Zx {
+        passes.insert(0, Pass::Zx);
+    }
+    passes
+}
+
+// What one pass did, summed over every run it was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassStats {
+    pub pass: Pass,
+    pub gates_before: usize,
+    pub gates_after: usize,
+    pub depth_before: usize,
+    pub depth_after: usize,
+    pub time: Duration,
+}
+
+// Runs its passes in order on each run of operations, keeping statistics
+// for each, and the counts of the passes that report them.
+pub struct PassManager {
+    passes: Vec<Pass>,
+    target: Target,
+    rules: Vec<Rule>,
+    equivalences: Vec<Rule>,
+    stats: Vec<PassStats>,
+    pub toffoli: ToffoliOptions,
+    pub counts: RuleCounts,
+    pub phases: PhaseCounts,
+}
+
+impl PassManager {
+    pub fn new(passes: Vec<Pass>, target: Target, rules: Vec<Rule>) -> Self {
+        let stats = passes
+            .iter()
+            .map(|pass| PassStats {
+                pass: *pass,
+                gates_before: 0,
+                gates_after: 0,
+                depth_before: 0,
+                depth_after: 0,
+                time: Duration::ZERO,
+            })
+            .collect();
+        Self {
+            passes,
+            target,
+            rules,
+            equivalences: equivalences(),
+            stats,
+            toffoli: ToffoliOptions::default(),
+            counts: RuleCounts::new(),
+            phases: PhaseCounts::new(),
+        }
+    }
+
+    pub fn run(&mut self, mut ops: Vec<Op>) -> Result<Vec<Op>> {
+        for (pass, stats) in self.passes.iter().zip(&mut self.stats) {
+            stats.gates_before += gate_count(&ops);
+            stats.depth_before += Dag::from_ops(ops.clone()).depth();
+            let start = Instant::now();
+            ops = match pass {
+                Pass::Zx => zx::optimize(ops, &self.target),
+                Pass::Cancel => cancel_counting(ops, &self.target, &self.rules, &mut self.counts),
+                Pass::Phase => phase_polynomials(ops, &self.target, &mut self.phases),
+                Pass::Fuse => fuse(ops, &self.target),
+                Pass::Toffoli => decompose_toffolis(ops, &self.target, &self.toffoli)?,
+                Pass::Basis => translate(ops, &self.target, &self.equivalences)?,
+            };
+            stats.time += start.elapsed();
+            stats.gates_after += gate_count(&ops);
+            stats.depth_after += Dag::from_ops(ops.clone()).depth();
+        }
+        Ok(ops)
+    }
+
+    // Runs the passes on the runs of `program` that partial evaluation
+    // applies to, stopping at the first error.
+    pub fn run_program(&mut self, program: &Program) -> Result<Program> {
+        let mut error = None;
+        let program = map_pe_runs(program, &mut |ops| {
+            if error.is_some() {
+                return ops;
+            }
+            self.run(ops.clone()).unwrap_or_else(|e| {
+                error = Some(e);
+                ops
+            })
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(program),
+        }
+    }
+
+    pub fn stats(&self) -> &[PassStats] {
+        &self.stats
+    }
+}
+
+// Measurements and resets are not gates.
+fn gate_count(ops: &[Op]) -> usize {
+    ops.iter()
+        .filter(|op| matches!(op.kind, OpKind::Gate { .. }))
+        .count()
+}
+
+// `cancel: 3 gates removed (40 -> 37), depth 12 -> 10, 1.20ms`.
+impl fmt::Display for PassStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let removed = self.gates_before as i64 - self.gates_after as i64;
+        let plural = if removed == 1 { "" } else { "s" };
+        writeln!(
+            f,
+            "{}: {} gate{} removed ({} -> {}), depth {} -> {}, {:.2?}",
+            self.pass.name(),
+            removed,
+            plural,
+            self.gates_before,
+            self.gates_after,
+            self.depth_before,
+            self.depth_after,
+            self.time
+        )
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+
+use crate::ast::{Angle, QubitRef};
+use crate::lexer::Span;
+
+use super::gates::canonical;
+use super::{Dag, Op, OpKind, Target};
+
+// T-count and CX-count of the regions `phase_polynomials` looked at, before
+// and after.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseCounts {
+    pub t_before: usize,
+    pub t_after: usize,
+    pub cx_before: usize,
+    pub cx_after: usize,
+}
+
+// Resynthesizes each region of CX and phase gates (RZ, T, S, Z and their
+// inverses) from its phase polynomial: the region adds a phase to each
+// parity of its input qubits that a phase gate acts on, then applies a
+// linear map to them. Phases on the same parity are merged, so
+// `CX(a, b); T(b); CX(a, b); CX(a, b); T(b); CX(a, b);` becomes
+// `CX(a, b); S(b); CX(a, b);`. A region is only replaced when neither count
+// goes up and the circuit gets smaller.
+pub fn phase_polynomials(ops: Vec<Op>, target: &Target, counts: &mut PhaseCounts) -> Vec<Op> {
+    let mut dag = Dag::from_ops(ops);
+    for region in dag.regions(in_region) {
+        let before: Vec<Op> = region.iter().map(|id| dag.op(*id).clone()).collect();
+        let Some(after) = resynthesize(&before, target) else {
+            counts.add(&before, &before);
+            continue;
+        };
+        let (t, cx) = (t_count(&after), cx_count(&after));
+        let better = t <= t_count(&before)
+            && cx <= cx_count(&before)
+            && (t < t_count(&before) || cx < cx_count(&before) || after.len() < before.len());
+        if better {
+            counts.add(&before, &after);
+            dag.splice(&region, after);
+        } else {
+            counts.add(&before, &before);
+        }
+    }
+    dag.to_ops()
+}
+
+impl PhaseCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, before: &[Op], after: &[Op]) {
+        self.t_before += t_count(before);
+        self.t_after += t_count(after);
+        self.cx_before += cx_count(before);
+        self.cx_after += cx_count(after);
+    }
+}
+
+// T gates, counting rotations by odd multiples of π/4 as one.
+pub fn t_count(ops: &[Op]) -> usize {
+    ops.iter()
+        .filter(|op| match op.name() {
+            "T" | "Tdg" => true,
+            "RZ" => matches!(phase(op), Some(Angle::Pi { den: 4, .. })),
+            _ => false,
+        })
+        .count()
+}
+
+pub fn cx_count(ops: &[Op]) -> usize {
+    ops.iter().filter(|op| canonical(op.name()) == "CX").count()
+}
+
+// The phase a gate adds to the `|1>` state of its qubit, if it is a phase
+// gate. RZ is one up to a global phase.
+pub fn phase(op: &Op) -> Option<Angle> {
+    let angle = match op.name() {
+        "T" => Angle::pi(1, 4),
+        "Tdg" => Angle::pi(-1, 4),
+        "S" => Angle::pi(1, 2),
+        "Sdg" => Angle::pi(-1, 2),
+        "Z" => Angle::pi(1, 1),
+        "RZ" => match &op.kind {
+            OpKind::Gate { params, .. } => *params.first()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(angle)
+}
+
+fn in_region(op: &Op) -> bool {
+    canonical(op.name()) == "CX" || phase(op).is_some()
+}
+
+// The region again, from its phase polynomial. Parities are bit masks over
+// the region's qubits, so it can have at most 64 of them.
+fn resynthesize(ops: &[Op], target: &Target) -> Option<Vec<Op>> {
+    let mut qubits: Vec<QubitRef> = Vec::new();
+    for op in ops {
+        for q in op.qubits() {
+            if !qubits.contains(q) {
+                qubits.push(q.clone());
+            }
+        }
+    }
+    if qubits.len() > 64 {
+        return None;
+    }
+    let index = |q: &QubitRef| qubits.iter().position(|x| x == q).unwrap();
+
+    let mut parities: Vec<u64> = (0..qubits.len()).map(|i| 1 << i).collect();
+    let mut terms: Vec<(u64, Angle)> = Vec::new();
+    for op in ops {
+        let q = op.qubits();
+        match phase(op) {
+            Some(angle) => {
+                let parity = parities[index(&q[0])];
+                match terms.iter_mut().find(|(p, _)| *p == parity) {
+                    Some((_, sum)) => *sum = *sum + angle,
+                    None => terms.push((parity, angle)),
+                }
+            }
+            None => parities[index(&q[1])] ^= parities[index(&q[0])],
+        }
+    }
+    terms.retain(|(_, angle)| !angle.is_zero());
+
+    let span = ops[0].span;
+    let mut out = Vec::new();
+    let cx = |control: usize, target: usize| {
+        Op::gate(
+            "CX",
+            vec![qubits[control].clone(), qubits[target].clone()],
+            span,
+        )
+    };
+
+    // Each term goes on a qubit made to hold its parity, cheapest first.
+    let mut rows: Vec<u64> = (0..qubits.len()).map(|i| 1 << i).collect();
+    while !terms.is_empty() {
+        let (i, from) = terms
+            .iter()
+            .enumerate()
+            .map(|(i, (parity, _))| (i, combination(&rows, *parity).unwrap()))
+            .min_by_key(|(_, from)| from.count_ones())
+            .unwrap();
+        let (_, angle) = terms.remove(i);
+        let t = 63 - from.leading_zeros() as usize;
+        for c in (0..qubits.len()).filter(|c| *c != t && from >> c & 1 == 1) {
+            rows[t] ^= rows[c];
+            out.push(cx(c, t));
+        }
+        out.extend(phase_gates(angle, &qubits[t], span, target));
+    }
+
+    // Then the linear map, through the identity.
+    let mut steps = to_identity(rows);
+    steps.extend(to_identity(parities).into_iter().rev());
+    out.extend(steps.into_iter().map(|(c, t)| cx(c, t)));
+    Some(out)
+}
+
+// Which rows XOR to `target`, as a mask of row indices.
+fn combination(rows: &[u64], target: u64) -> Option<u64> {
+    // Reduced rows with the rows they are made of, by leading bit.
+    let mut basis: Vec<(u64, u64)> = Vec::new();
+    let reduce = |basis: &[(u64, u64)], mut value: u64, mut from: u64| {
+        for (row, made_of) in basis {
+            if value ^ row < value {
+                value ^= row;
+                from ^= made_of;
+            }
+        }
+        (value, from)
+    };
+    for (i, row) in rows.iter().enumerate() {
+        let (value, from) = reduce(&basis, *row, 1 << i);
+        if value != 0 {
+            let at = basis.partition_point(|(b, _)| b.leading_zeros() < value.leading_zeros());
+            basis.insert(at, (value, from));
+        }
+    }
+    let (value, from) = reduce(&basis, target, 0);
+    (value == 0).then_some(from)
+}
+
+// CXs, as `(control, target)`, that take the linear map `rows` to the
+// identity by Gaussian elimination. `rows` must be invertible.
+fn to_identity(mut rows: Vec<u64>) -> Vec<(usize, usize)> {
+    let mut steps = Vec::new();
+    for col in 0..rows.len() {
+        if rows[col] >> col & 1 == 0 {
+            let pivot = (col + 1..rows.len())
+                .find(|r| rows[*r] >> col & 1 == 1)
+                .expect("CX networks are invertible");
+            rows[col] ^= rows[pivot];
+            steps.push((pivot, col));
+        }
+        for r in 0..rows.len() {
+            if r != col && rows[r] >> col & 1 == 1 {
+                rows[r] ^= rows[col];
+                steps.push((col, r));
+            }
+        }
+    }
+    steps
+}
+
+// Multiples of π/4 as T, S and Z gates when the target has them, anything
+// else as RZ.
+pub fn phase_gates(angle: Angle, qubit: &QubitRef, span: Span, target: &Target) -> Vec<Op> {
+    let named: &[&str] = match angle {
+        Angle::Pi { num, den } if 4 % den == 0 => match num * 4 / den {
+            1 => &["T"],
+            2 => &["S"],
+            3 => &["S", "T"],
+            4 => &["Z"],
+            -1 => &["Tdg"],
+            -2 => &["Sdg"],
+            -3 => &["Sdg", "Tdg"],
+            _ => &[],
+        },
+        _ => &[],
+    };
+    let gate = |name: &str| Op::gate(name, vec![qubit.clone()], span);
+    if !named.is_empty() && (named.iter().all(|g| target.supports(g)) || !target.supports("RZ")) {
+        return named.iter().map(|g| gate(g)).collect();
+    }
+    let mut rz = gate("RZ");
+    if let OpKind::Gate { params, .. } = &mut rz.kind {
+        params.push(angle);
+    }
+    vec![rz]
+}
+
+// `T-count: 7 -> 3` and the same for CX.
+impl fmt::Display for PhaseCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "T-count: {} -> {}", self.t_before, self.t_after)?;
+        writeln!(f, "CX-count: {} -> {}", self.cx_before, self.cx_after)
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+
+use crate::ast::Angle;
+
+use super::gates::canonical;
+use super::rules::{links, Bindings, Rule, Template};
+use super::{Dag, NodeId, Op, OpKind, Target, Wire};
+
+// Identities between runs of single-qubit gates on one qubit. They hold up
+// to a global phase, e.g. `X Z` is iY.
+pub const IDENTITIES: &[&str] = &[
+    "H(a); X(a); H(a) => Z(a)",
+    "H(a); Z(a); H(a) => X(a)",
+    "H(a); Y(a); H(a) => Y(a)",
+    "S(a); S(a) => Z(a)",
+    "Sdg(a); Sdg(a) => Z(a)",
+    "T(a); T(a) => S(a)",
+    "Tdg(a); Tdg(a) => Sdg(a)",
+    "Z(a); S(a) => Sdg(a)",
+    "S(a); Z(a) => Sdg(a)",
+    "Z(a); Sdg(a) => S(a)",
+    "Sdg(a); Z(a) => S(a)",
+    "S(a); Tdg(a) => T(a)",
+    "Tdg(a); S(a) => T(a)",
+    "Sdg(a); T(a) => Tdg(a)",
+    "T(a); Sdg(a) => Tdg(a)",
+    "X(a); Z(a) => Y(a)",
+    "Z(a); X(a) => Y(a)",
+    "X(a); Y(a) => Z(a)",
+    "Y(a); X(a) => Z(a)",
+    "Y(a); Z(a) => X(a)",
+    "Z(a); Y(a) => X(a)",
+];
+
+pub fn identities() -> Vec<Rule> {
+    IDENTITIES
+        .iter()
+        .map(|text| Rule::parse(text).expect("built-in identity parses"))
+        .collect()
+}
+
+// Gates removed by each rule, in the order the rules were first used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleCounts {
+    counts: Vec<(String, usize)>,
+}
+
+impl RuleCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, rule: &Rule, removed: usize) {
+        let name = rule.to_string();
+        match self.counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += removed,
+            None => self.counts.push((name, removed)),
+        }
+    }
+
+    pub fn get(&self, rule: &str) -> usize {
+        let rule = Rule::parse(rule).ok().map(|r| r.to_string());
+        self.counts
+            .iter()
+            .find(|(n, _)| Some(n) == rule.as_ref())
+            .map_or(0, |(_, count)| *count)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|(_, count)| count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+// Rewrites wherever a rule matches until none does, and returns the number
+// of gates removed. Only the qubits of the matched gates order them, so
+// `H(a); X(b); X(a); H(a);` still matches `H(a); X(a); H(a)`.
+pub fn rewrite_identities(dag: &mut Dag, rules: &[Rule], counts: &mut RuleCounts) -> usize {
+    let mut removed = 0;
+    loop {
+        let mut changed = false;
+        for id in dag.topological_order() {
+            if !dag.contains(id) {
+                continue;
+            }
+            let Some((rule, (nodes, bindings))) = rules
+                .iter()
+                .find_map(|rule| Some((rule, matches(dag, id, rule)?)))
+            else {
+                continue;
+            };
+
+            let span = dag.op(id).span;
+            let ops = rule
+                .replacement
+                .iter()
+                .map(|gate| gate.instantiate(&bindings, span))
+                .collect();
+            dag.splice(&nodes, ops);
+            let n = rule.pattern.len() - rule.replacement.len();
+            counts.add(rule, n);
+            removed += n;
+            changed = true;
+        }
+        if !changed {
+            return removed;
+        }
+    }
+}
+
+// Whether the target has every gate a rule can introduce.
+pub fn runs_on(rule: &Rule, target: &Target) -> bool {
+    rule.replacement
+        .iter()
+        .all(|gate| target.supports(&gate.name))
+}
+
+// The nodes the left side of `rule` matches, in the order of the pattern,
+// when its first gate is `id`. The other gates are found from it through
+// the qubits they share, and gates in a row on a qubit in the pattern must
+// be in a row on it in the circuit.
+fn matches(dag: &Dag, id: NodeId, rule: &Rule) -> Option<(Vec<NodeId>, Bindings)> {
+    let pattern = &rule.pattern;
+    let links = links(pattern);
+    let mut bindings = Bindings::default();
+    let mut nodes: Vec<Option<NodeId>> = vec![None; pattern.len()];
+    if !bind(dag.op(id), &pattern[0], &mut bindings) {
+        return None;
+    }
+    nodes[0] = Some(id);
+
+    let mut queue = vec![0];
+    while let Some(i) = queue.pop() {
+        let at = nodes[i]?;
+        for (a, b, qubit) in &links {
+            let (j, forward) = if *a == i && nodes[*b].is_none() {
+                (*b, true)
+            } else if *b == i && nodes[*a].is_none() {
+                (*a, false)
+            } else {
+                continue;
+            };
+            let wire = Wire::Qubit(bindings.qubits[*qubit].to_string());
+            let node = if forward {
+                dag.successor(at, &wire)?
+            } else {
+                dag.predecessor(at, &wire)?
+            };
+            if nodes.contains(&Some(node)) || !bind(dag.op(node), &pattern[j], &mut bindings) {
+                return None;
+            }
+            nodes[j] = Some(node);
+            queue.push(j);
+        }
+    }
+
+    let nodes: Vec<NodeId> = nodes.into_iter().collect::<Option<_>>()?;
+    for (a, b, qubit) in &links {
+        let wire = Wire::Qubit(bindings.qubits[*qubit].to_string());
+        if dag.successor(nodes[*a], &wire) != Some(nodes[*b]) {
+            return None;
+        }
+    }
+    dag.is_convex(&nodes).then_some((nodes, bindings))
+}
+
+// Whether `op` is an instance of `gate`, given the names bound so far,
+// which it extends.
+pub fn bind(op: &Op, gate: &Template, bindings: &mut Bindings) -> bool {
+    let OpKind::Gate {
+        name,
+        params,
+        qubits,
+    } = &op.kind
+    else {
+        return false;
+    };
+    if canonical(name) != canonical(&gate.name)
+        || qubits.len() != gate.qubits.len()
+        || params.len() != gate.params.len()
+    {
+        return false;
+    }
+
+    for (qubit, var) in qubits.iter().zip(&gate.qubits) {
+        match bindings.qubits.get(var) {
+            Some(bound) if bound != qubit => return false,
+            Some(_) => {}
+            // Different names are different qubits.
+            None if bindings.qubits.values().any(|b| b == qubit) => return false,
+            None => {
+                bindings.qubits.insert(var.clone(), qubit.clone());
+            }
+        }
+    }
+    for (angle, expr) in params.iter().zip(&gate.params) {
+        let expected = match expr.name() {
+            Some(name) => match bindings.angles.get(name) {
+                Some(bound) => *bound,
+                None => {
+                    bindings.angles.insert(name.to_string(), *angle);
+                    continue;
+                }
+            },
+            None => expr.constant,
+        };
+        if !same_angle(*angle, expected) {
+            return false;
+        }
+    }
+    true
+}
+
+fn same_angle(a: Angle, b: Angle) -> bool {
+    (a + -b).is_zero()
+}
+
+// One line per rule: `H(a); X(a); H(a) => Z(a): 2 gates removed`.
+impl fmt::Display for RuleCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (rule, count) in &self.counts {
+            let plural = if *count == 1 { "" } else { "s" };
+            writeln!(f, "{}: {} gate{} removed", rule, count, plural)?;
+        }
+        Ok(())
+    }
+}
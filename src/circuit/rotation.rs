@@ -0,0 +1,61 @@
+use crate::ast::Angle;
+
+use super::gates::commutes;
+use super::{Dag, NodeId, Op, OpKind};
+
+// Merges rotations about the same axis of the same qubit, so `RZ(a, q);
+// RZ(b, q);` becomes `RZ(a + b, q);`, also past gates they commute with.
+// Rotations by a multiple of 2π are dropped. Returns the number of gates
+// removed.
+pub fn merge_rotations(dag: &mut Dag) -> usize {
+    let mut removed = 0;
+    for id in dag.topological_order() {
+        if !dag.contains(id) {
+            continue;
+        }
+        let Some(mut angle) = rotation(dag.op(id)) else {
+            continue;
+        };
+        while let Some(next) = next_rotation(dag, id) {
+            angle = angle + rotation(&dag.remove(next)).unwrap();
+            removed += 1;
+        }
+        if angle.is_zero() {
+            dag.remove(id);
+            removed += 1;
+        } else {
+            let mut op = dag.op(id).clone();
+            if let OpKind::Gate { params, .. } = &mut op.kind {
+                params[0] = angle;
+            }
+            dag.replace(id, op);
+        }
+    }
+    removed
+}
+
+fn rotation(op: &Op) -> Option<Angle> {
+    match &op.kind {
+        OpKind::Gate { name, params, .. } if matches!(name.as_str(), "RX" | "RY" | "RZ") => {
+            params.first().copied()
+        }
+        _ => None,
+    }
+}
+
+// The next rotation like `id` on its qubit, if `id` commutes with the gates
+// in between.
+fn next_rotation(dag: &Dag, id: NodeId) -> Option<NodeId> {
+    let op = dag.op(id);
+    let wire = &dag.wires(id)[0];
+    let same = |other: &Op| other.name() == op.name() && other.qubits() == op.qubits();
+
+    let mut at = dag.successor(id, wire)?;
+    while !same(dag.op(at)) {
+        if !commutes(op, dag.op(at)) {
+            return None;
+        }
+        at = dag.successor(at, wire)?;
+    }
+    Some(at)
+}
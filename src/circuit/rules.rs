@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::ast::angle::AngleTerm;
+use crate::ast::{Angle, QubitRef};
+use crate::lexer::Span;
+use crate::parser::{angle_count, check_angles, check_arity};
+
+use super::gates::BUILTIN;
+use super::unitary::{same_up_to_phase, unitary};
+use super::{Op, OpKind, Wire};
+
+// A peephole rewrite such as `H(a); CX(a, b); H(a) => ...`: wherever the
+// gates on the left follow one another on their qubits, they are replaced
+// by the gates on the right. Qubit names stand for any distinct qubits and
+// angle names for any angle, e.g. `RZ(x, a); RZ(y, a) => RZ(x + y, a)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub pattern: Vec<Template>,
+    pub replacement: Vec<Template>,
+}
+
+// One gate of a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub params: Vec<AngleExpr>,
+    pub qubits: Vec<String>,
+}
+
+// An angle of a rule: named angles added or subtracted, plus a constant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AngleExpr {
+    pub names: Vec<(bool, String)>,
+    pub constant: Angle,
+}
+
+// What a rule's names stand for where its left side matched.
+#[derive(Debug, Clone, Default)]
+pub struct Bindings {
+    pub qubits: HashMap<String, QubitRef>,
+    pub angles: HashMap<String, Angle>,
+}
+
+impl Rule {
+    // `G(..); G(..) => G(..)`, with fewer gates on the right than on the
+    // left so that rewriting stops. Soundness is only checked by `check`.
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_sides(text, true)
+    }
+
+    // `G(..) => G(..); G(..)`: one gate on the left, and any number on the
+    // right.
+    pub fn parse_decomposition(text: &str) -> Result<Self> {
+        let rule = Self::parse_sides(text, false)?;
+        if rule.pattern.len() != 1 {
+            bail!("the left side of a decomposition is one gate");
+        }
+        Ok(rule)
+    }
+
+    fn parse_sides(text: &str, shrinks: bool) -> Result<Self> {
+        let Some((lhs, rhs)) = text.split_once("=>") else {
+            bail!("expected `=>` between the two sides of a rule");
+        };
+        let pattern = parse_side(lhs)?;
+        let replacement = parse_side(rhs)?;
+        if pattern.is_empty() {
+            bail!("the left side of a rule needs at least one gate");
+        }
+        if shrinks && replacement.len() >= pattern.len() {
+            bail!("the right side of a rule must have fewer gates than the left");
+        }
+
+        let mut reached = vec![0];
+        let mut i = 0;
+        while i < reached.len() {
+            for (a, b, _) in links(&pattern) {
+                for (from, to) in [(a, b), (b, a)] {
+                    if from == reached[i] && !reached.contains(&to) {
+                        reached.push(to);
+                    }
+                }
+            }
+            i += 1;
+        }
+        if let Some(gate) = (0..pattern.len()).find(|i| !reached.contains(i)) {
+            bail!("`{}` shares no qubit with the other gates", pattern[gate]);
+        }
+
+        let mut qubits: Vec<&str> = Vec::new();
+        let mut angles: Vec<&str> = Vec::new();
+        for gate in &pattern {
+            qubits.extend(gate.qubits.iter().map(String::as_str));
+            for param in &gate.params {
+                match param.name() {
+                    Some(name) => angles.push(name),
+                    None if param.names.is_empty() => {}
+                    None => bail!(
+                        "angles on the left side of a rule are names or constants, found `{}`",
+                        param
+                    ),
+                }
+            }
+        }
+        for gate in &replacement {
+            if let Some(q) = gate.qubits.iter().find(|q| !qubits.contains(&q.as_str())) {
+                bail!("qubit `{}` is not on the left side of the rule", q);
+            }
+            for param in &gate.params {
+                if let Some((_, a)) = param
+                    .names
+                    .iter()
+                    .find(|(_, a)| !angles.contains(&a.as_str()))
+                {
+                    bail!("angle `{}` is not on the left side of the rule", a);
+                }
+            }
+        }
+        Ok(Self {
+            pattern,
+            replacement,
+        })
+    }
+
+    // Fails unless both sides have the same unitary up to a global phase,
+    // tried with a few values for the named angles.
+    pub fn check(&self) -> Result<()> {
+        let mut wires: Vec<Wire> = Vec::new();
+        let mut angles: Vec<&str> = Vec::new();
+        for gate in &self.pattern {
+            for q in &gate.qubits {
+                let wire = Wire::Qubit(q.clone());
+                if !wires.contains(&wire) {
+                    wires.push(wire);
+                }
+            }
+            angles.extend(gate.params.iter().filter_map(AngleExpr::name));
+        }
+        if wires.len() > 8 {
+            bail!("a rule can use at most 8 qubits, found {}", wires.len());
+        }
+
+        for sample in 0..3 {
+            let mut bindings = Bindings::default();
+            for wire in &wires {
+                let name = wire.to_string();
+                bindings.qubits.insert(name.clone(), QubitRef::new(name));
+            }
+            for (i, name) in angles.iter().enumerate() {
+                let value = 0.37 + 1.13 * i as f64 + 2.29 * sample as f64;
+                bindings
+                    .angles
+                    .insert(name.to_string(), Angle::radians(value));
+            }
+            let side = |gates: &[Template]| -> Vec<Op> {
+                gates
+                    .iter()
+                    .map(|g| g.instantiate(&bindings, Span::default()))
+                    .collect()
+            };
+            let lhs = unitary(&side(&self.pattern), &wires);
+            let rhs = unitary(&side(&self.replacement), &wires);
+            let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+                bail!("no unitary to check the rule against");
+            };
+            if !same_up_to_phase(&lhs, &rhs) {
+                bail!("the two sides of the rule are different unitaries");
+            }
+        }
+        Ok(())
+    }
+}
+
+// Rules from a file, one per line, each checked for soundness. Blank lines
+// and `//` comments are skipped.
+pub fn load(path: &Path) -> Result<Vec<Rule>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut rules = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let rule = Rule::parse(line)
+            .and_then(|rule| rule.check().map(|()| rule))
+            .with_context(|| format!("{}:{}", path.display(), i + 1))?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+impl Template {
+    pub fn instantiate(&self, bindings: &Bindings, span: Span) -> Op {
+        Op {
+            kind: OpKind::Gate {
+                name: self.name.clone(),
+                params: self.params.iter().map(|p| p.eval(bindings)).collect(),
+                qubits: self
+                    .qubits
+                    .iter()
+                    .map(|q| bindings.qubits[q].clone())
+                    .collect(),
+            },
+            span,
+        }
+    }
+}
+
+impl AngleExpr {
+    // The name, if the angle is nothing but one name.
+    pub fn name(&self) -> Option<&str> {
+        match self.names.as_slice() {
+            [(false, name)] if self.constant.is_zero() => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn eval(&self, bindings: &Bindings) -> Angle {
+        self.names
+            .iter()
+            .fold(self.constant, |sum, (negated, name)| {
+                let angle = bindings.angles[name];
+                sum + if *negated { -angle } else { angle }
+            })
+    }
+}
+
+// Pairs of gates in a row on a qubit, the earlier one first.
+pub fn links(gates: &[Template]) -> Vec<(usize, usize, &str)> {
+    let mut links = Vec::new();
+    for (i, gate) in gates.iter().enumerate() {
+        for q in &gate.qubits {
+            if let Some(j) = (i + 1..gates.len()).find(|j| gates[*j].qubits.contains(q)) {
+                links.push((i, j, q.as_str()));
+            }
+        }
+    }
+    links
+}
+
+// `;`-separated gates, the last `;` optional.
+fn parse_side(text: &str) -> Result<Vec<Template>> {
+    let mut calls: Vec<&str> = text.split(';').map(str::trim).collect();
+    if calls.last() == Some(&"") {
+        calls.pop();
+    }
+    calls.into_iter().map(parse_gate).collect()
+}
+
+fn parse_gate(text: &str) -> Result<Template> {
+    let Some((name, args)) = text
+        .split_once('(')
+        .and_then(|(name, rest)| Some((name.trim(), rest.strip_suffix(')')?)))
+    else {
+        bail!("expected a gate such as `H(a)`, found `{}`", text);
+    };
+    if !BUILTIN.contains(&name) {
+        bail!("unknown gate `{}`", name);
+    }
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+    let (params, qubits) = args.split_at(angle_count(name).min(args.len()));
+    check_arity(name, qubits.len())?;
+    check_angles(name, params.len())?;
+
+    for (i, q) in qubits.iter().enumerate() {
+        if !is_ident(q) {
+            bail!("expected a qubit name, found `{}`", q);
+        }
+        if qubits[..i].contains(q) {
+            bail!("`{}` uses qubit `{}` twice", name, q);
+        }
+    }
+    Ok(Template {
+        name: name.to_string(),
+        params: params
+            .iter()
+            .map(|p| parse_angle(p))
+            .collect::<Result<_>>()?,
+        qubits: qubits.iter().map(|q| q.to_string()).collect(),
+    })
+}
+
+// Names and constant terms such as `pi/4` or `2*pi/3`, joined by `+` and
+// `-`.
+fn parse_angle(text: &str) -> Result<AngleExpr> {
+    let mut names = Vec::new();
+    let mut constant: Option<AngleTerm> = None;
+    let mut negated = false;
+    let mut rest = text.trim();
+    if let Some(r) = rest.strip_prefix('-') {
+        negated = true;
+        rest = r.trim_start();
+    }
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        if is_ident(term) && term != "pi" {
+            names.push((negated, term.to_string()));
+        } else {
+            let Some(value) = parse_product(term) else {
+                bail!("expected an angle, found `{}`", text.trim());
+            };
+            let value = if negated { value.negate() } else { value };
+            constant = Some(constant.map_or(value, |c| c.plus(value)));
+        }
+        let Some(op) = rest[end..].chars().next() else {
+            break;
+        };
+        negated = op == '-';
+        rest = rest[end + 1..].trim_start();
+    }
+    Ok(AngleExpr {
+        names,
+        constant: constant.map_or(Angle::pi(0, 1), AngleTerm::to_angle),
+    })
+}
+
+fn parse_product(text: &str) -> Option<AngleTerm> {
+    let mut factors = text.split_inclusive(['*', '/']);
+    let factor = |f: &str| match f.trim_end_matches(['*', '/']).trim() {
+        "pi" => Some(AngleTerm::pi()),
+        f => AngleTerm::number(f),
+    };
+    let first = factors.next()?;
+    let mut value = factor(first)?;
+    let mut dividing = first.ends_with('/');
+    for f in factors {
+        value = if dividing {
+            value.divided_by(factor(f)?)?
+        } else {
+            value.times(factor(f)?)
+        };
+        dividing = f.ends_with('/');
+    }
+    Some(value)
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Prints as it is parsed: `H(a); X(a); H(a) => Z(a)`.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |gates: &[Template]| -> String {
+            let gates: Vec<String> = gates.iter().map(Template::to_string).collect();
+            gates.join("; ")
+        };
+        write!(f, "{} =>", side(&self.pattern))?;
+        if !self.replacement.is_empty() {
+            write!(f, " {}", side(&self.replacement))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self
+            .params
+            .iter()
+            .map(AngleExpr::to_string)
+            .chain(self.qubits.iter().cloned())
+            .collect();
+        write!(f, "{}({})", self.name, args.join(", "))
+    }
+}
+
+impl fmt::Display for AngleExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (negated, name)) in self.names.iter().enumerate() {
+            match (i, negated) {
+                (0, false) => write!(f, "{}", name)?,
+                (0, true) => write!(f, "-{}", name)?,
+                (_, false) => write!(f, " + {}", name)?,
+                (_, true) => write!(f, " - {}", name)?,
+            }
+        }
+        if self.names.is_empty() {
+            write!(f, "{}", self.constant)
+        } else if self.constant.is_zero() {
+            Ok(())
+        } else if self.constant.to_radians() < 0.0 {
+            write!(f, " - {}", -self.constant)
+        } else {
+            write!(f, " + {}", self.constant)
+        }
+    }
+}
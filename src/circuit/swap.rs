@@ -0,0 +1,56 @@
+use super::gates::canonical;
+use super::{Dag, NodeId, Op, OpKind, Target};
+
+// Three CNOTs in alternating directions on one pair of qubits, as in
+// `CX(a, b); CX(b, a); CX(a, b);`, swap them. On a target with a SWAP gate
+// they become `SWAP(a, b);`. Returns the number of gates removed.
+pub fn recognize_swaps(dag: &mut Dag, target: &Target) -> usize {
+    if !target.supports("SWAP") {
+        return 0;
+    }
+    let mut removed = 0;
+    for id in dag.topological_order() {
+        if !dag.contains(id) {
+            continue;
+        }
+        let Some((second, third)) = swap_at(dag, id) else {
+            continue;
+        };
+        dag.remove(second);
+        dag.remove(third);
+        let mut op = dag.op(id).clone();
+        if let OpKind::Gate { name, .. } = &mut op.kind {
+            *name = "SWAP".to_string();
+        }
+        dag.replace(id, op);
+        removed += 2;
+    }
+    removed
+}
+
+fn swap_at(dag: &Dag, id: NodeId) -> Option<(NodeId, NodeId)> {
+    // The gate right after `at` on both of its qubits.
+    let next = |at: NodeId| {
+        let wires = dag.wires(at);
+        let n = dag.successor(at, &wires[0])?;
+        (wires.len() == 2 && dag.successor(at, &wires[1]) == Some(n)).then_some(n)
+    };
+    let first = dag.op(id);
+    if !is_cx(first) {
+        return None;
+    }
+    let second = next(id)?;
+    let third = next(second)?;
+
+    let qubits = first.qubits();
+    let flipped = [qubits[1].clone(), qubits[0].clone()];
+    let matches = is_cx(dag.op(second))
+        && dag.op(second).qubits() == flipped
+        && is_cx(dag.op(third))
+        && dag.op(third).qubits() == qubits;
+    matches.then_some((second, third))
+}
+
+fn is_cx(op: &Op) -> bool {
+    matches!(&op.kind, OpKind::Gate { name, .. } if canonical(name) == "CX")
+}
@@ -0,0 +1,35 @@
+use anyhow::{bail, Result};
+
+use super::gates::{canonical, BUILTIN};
+
+// What the circuit is compiled for: the gates it runs natively, or any
+// built-in gate when no basis is given.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Target {
+    basis: Option<Vec<String>>,
+}
+
+impl Target {
+    pub fn with_basis(gates: &[String]) -> Result<Self> {
+        for gate in gates {
+            if !BUILTIN.contains(&gate.as_str()) {
+                bail!("unknown gate `{}` in basis", gate);
+            }
+        }
+        Ok(Self {
+            basis: Some(gates.to_vec()),
+        })
+    }
+
+    // The gates of the basis, if one was given.
+    pub fn basis(&self) -> Option<&[String]> {
+        self.basis.as_deref()
+    }
+
+    pub fn supports(&self, gate: &str) -> bool {
+        match &self.basis {
+            Some(basis) => basis.iter().any(|g| canonical(g) == canonical(gate)),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::ast::{Angle, QubitRef};
+use crate::lexer::Span;
+
+use super::basis::{equivalences, translate};
+use super::gates::{axis_on, canonical, Axis};
+use super::phase::phase_gates;
+use super::{Op, Target, Wire};
+
+// How CCX becomes Clifford+T.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CcxMode {
+    // Exactly, with 7 T gates.
+    #[default]
+    Exact,
+    // Up to a diagonal phase, with 4 T gates, where a later CCX on the same
+    // qubits undoes it and only gates diagonal on those qubits come between,
+    // as when the target is an ancilla used as a control; exactly elsewhere.
+    RelativePhase,
+}
+
+// How MCX becomes CCX and smaller gates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum McxMode {
+    // A ladder of CCX through idle qubits of the run, which are borrowed in
+    // whatever state they are in and left in it. With fewer of them, two
+    // smaller ladders that borrow one idle qubit and each other's qubits;
+    // with none, as without ancillae.
+    #[default]
+    Ancillae,
+    // Phases on every parity of the qubits, with no CCX at all.
+    NoAncillae,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToffoliOptions {
+    pub ccx: CcxMode,
+    pub mcx: McxMode,
+}
+
+impl FromStr for CcxMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(CcxMode::Exact),
+            "relative-phase" => Ok(CcxMode::RelativePhase),
+            _ => bail!(
+                "unknown CCX decomposition `{}` (expected exact or relative-phase)",
+                s
+            ),
+        }
+    }
+}
+
+impl FromStr for McxMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ancillae" => Ok(McxMode::Ancillae),
+            "no-ancillae" => Ok(McxMode::NoAncillae),
+            _ => bail!(
+                "unknown MCX decomposition `{}` (expected ancillae or no-ancillae)",
+                s
+            ),
+        }
+    }
+}
+
+// Decomposes MCX and then CCX, unless the basis of the target has them.
+pub fn decompose_toffolis(
+    ops: Vec<Op>,
+    target: &Target,
+    options: &ToffoliOptions,
+) -> Result<Vec<Op>> {
+    let native = |gate: &str| target.basis().is_some() && target.supports(gate);
+    let ops = if native("MCX") {
+        ops
+    } else {
+        expand_mcx(ops, target, options.mcx)?
+    };
+    if native("CCX") {
+        return Ok(ops);
+    }
+
+    let partners = match options.ccx {
+        CcxMode::Exact => vec![None; ops.len()],
+        CcxMode::RelativePhase => partners(&ops),
+    };
+    let mut undone = vec![false; ops.len()];
+    let mut out = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        if canonical(op.name()) != "CCX" {
+            out.push(op.clone());
+            continue;
+        }
+        let q = op.qubits();
+        let (a, b, t) = (&q[0], &q[1], &q[2]);
+        if let Some(j) = partners[i] {
+            undone[j] = true;
+            out.extend(relative_phase_ccx(a, b, t, op.span, false));
+        } else if undone[i] {
+            out.extend(relative_phase_ccx(a, b, t, op.span, true));
+        } else {
+            out.extend(exact_ccx(a, b, t, op.span));
+        }
+    }
+    Ok(out)
+}
+
+fn expand_mcx(ops: Vec<Op>, target: &Target, mode: McxMode) -> Result<Vec<Op>> {
+    let mut qubits: Vec<QubitRef> = Vec::new();
+    for op in &ops {
+        for q in op.qubits() {
+            if !qubits.contains(q) {
+                qubits.push(q.clone());
+            }
+        }
+    }
+    let mut out = Vec::new();
+    for op in ops {
+        if op.name() != "MCX" {
+            out.push(op);
+            continue;
+        }
+        let (t, controls) = op.qubits().split_last().unwrap();
+        let controls: Vec<&QubitRef> = controls.iter().collect();
+        let idle: Vec<&QubitRef> = qubits.iter().filter(|q| !op.qubits().contains(q)).collect();
+        if controls.len() <= 2 || mode == McxMode::Ancillae && !idle.is_empty() {
+            out.extend(borrowing_mcx(&controls, t, &idle, op.span));
+        } else {
+            let h = Op::gate("H", vec![t.clone()], op.span);
+            out.push(h.clone());
+            out.extend(controlled_z(&op, target)?);
+            out.push(h);
+        }
+    }
+    Ok(out)
+}
+
+// X on `t` controlled by `controls`, borrowing `idle` qubits: a ladder when
+// there are enough of them, and otherwise, after Barenco et al., lemma 7.3,
+// two halves that borrow each other's qubits. One idle qubit is flipped by
+// the first half of the controls, and `t` by the second half and that
+// qubit; doing both twice leaves `t` flipped by all of the controls and the
+// idle qubit as it was.
+fn borrowing_mcx(controls: &[&QubitRef], t: &QubitRef, idle: &[&QubitRef], span: Span) -> Vec<Op> {
+    let gate = |name: &str, qs: &[&QubitRef]| {
+        Op::gate(name, qs.iter().map(|q| (*q).clone()).collect(), span)
+    };
+    match controls {
+        [c] => return vec![gate("CX", &[c, t])],
+        [a, b] => return vec![gate("CCX", &[a, b, t])],
+        _ if idle.len() >= controls.len() - 2 => return ccx_ladder(controls, idle, t, span),
+        _ => {}
+    }
+
+    let anc = idle[0];
+    let (first, second) = controls.split_at(controls.len().div_ceil(2));
+    let upper_controls: Vec<&QubitRef> = second.iter().copied().chain([anc]).collect();
+    let lower_idle: Vec<&QubitRef> = second.iter().copied().chain([t]).collect();
+    let upper = borrowing_mcx(&upper_controls, t, first, span);
+    let lower = borrowing_mcx(first, anc, &lower_idle, span);
+    [&upper, &lower, &upper, &lower]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+// X on `t` controlled by `controls`, m of them, with 4(m - 2) CCX through
+// m - 2 borrowed qubits, after Barenco et al., lemma 7.2: the first half
+// flips `t` by the controls and the ancillae, the second half flips it back
+// by the ancillae alone.
+fn ccx_ladder(controls: &[&QubitRef], ancillae: &[&QubitRef], t: &QubitRef, span: Span) -> Vec<Op> {
+    let m = controls.len();
+    let ccx = |a: &QubitRef, b: &QubitRef, t: &QubitRef| {
+        Op::gate("CCX", vec![a.clone(), b.clone(), t.clone()], span)
+    };
+    // Ancilla k holds the AND of controls up to k + 1 when the one before
+    // it does.
+    let step = |k: usize| ccx(controls[k + 1], ancillae[k - 1], ancillae[k]);
+    let mut out = Vec::new();
+    for _ in 0..2 {
+        out.push(ccx(controls[m - 1], ancillae[m - 3], t));
+        out.extend((1..m - 2).rev().map(step));
+        out.push(ccx(controls[0], controls[1], ancillae[0]));
+        out.extend((1..m - 2).map(step));
+    }
+    out
+}
+
+// Most qubits of an MCX turned into phases; each one more doubles them.
+const MAX_PARITY_QUBITS: usize = 12;
+
+// Z on the last qubit of `op` controlled by the others: the product of n
+// bits is a sum of their parities, each with weight ±2^(1 - n), so it is a
+// phase of ±π/2^(n - 1) on each parity. Fails against `op` when there are
+// too many parities, or the target cannot run such a phase.
+fn controlled_z(op: &Op, target: &Target) -> Result<Vec<Op>> {
+    let (qubits, span) = (op.qubits(), op.span);
+    let n = qubits.len();
+    if n > MAX_PARITY_QUBITS {
+        bail!(
+            "{}: cannot decompose `{}` without ancillae: {} qubits take {} phases",
+            span,
+            op,
+            n,
+            (1u64 << n) - 1
+        );
+    }
+    let smallest = Angle::pi(1, 1 << (n - 1));
+    let probe = phase_gates(smallest, &qubits[0], span, target);
+    if target.basis().is_some() && translate(probe, target, &equivalences()).is_err() {
+        bail!(
+            "{}: cannot decompose `{}` without ancillae: it takes phases of {}, which the basis {} cannot express",
+            span,
+            op,
+            smallest,
+            target.basis().unwrap_or_default().join(", ")
+        );
+    }
+
+    let cx = |c: usize, t: usize| Op::gate("CX", vec![qubits[c].clone(), qubits[t].clone()], span);
+    let mut out = Vec::new();
+    for parity in 1..1u64 << n {
+        let sign = if parity.count_ones() % 2 == 1 { 1 } else { -1 };
+        let angle = Angle::pi(sign, 1 << (n - 1));
+        let top = 63 - parity.leading_zeros() as usize;
+        let others: Vec<usize> = (0..top).filter(|i| parity >> i & 1 == 1).collect();
+        out.extend(others.iter().map(|c| cx(*c, top)));
+        out.extend(phase_gates(angle, &qubits[top], span, target));
+        out.extend(others.iter().rev().map(|c| cx(*c, top)));
+    }
+    Ok(out)
+}
+
+fn exact_ccx(a: &QubitRef, b: &QubitRef, t: &QubitRef, span: Span) -> Vec<Op> {
+    let gates: [(&str, &[&QubitRef]); 15] = [
+        ("H", &[t]),
+        ("CX", &[b, t]),
+        ("Tdg", &[t]),
+        ("CX", &[a, t]),
+        ("T", &[t]),
+        ("CX", &[b, t]),
+        ("Tdg", &[t]),
+        ("CX", &[a, t]),
+        ("T", &[b]),
+        ("T", &[t]),
+        ("H", &[t]),
+        ("CX", &[a, b]),
+        ("T", &[a]),
+        ("Tdg", &[b]),
+        ("CX", &[a, b]),
+    ];
+    ops(&gates, span)
+}
+
+// CCX and a diagonal phase, as in Margolus' gate, or the inverse of that.
+fn relative_phase_ccx(
+    a: &QubitRef,
+    b: &QubitRef,
+    t: &QubitRef,
+    span: Span,
+    inverse: bool,
+) -> Vec<Op> {
+    let mut gates: [(&str, &[&QubitRef]); 9] = [
+        ("H", &[t]),
+        ("T", &[t]),
+        ("CX", &[b, t]),
+        ("Tdg", &[t]),
+        ("CX", &[a, t]),
+        ("T", &[t]),
+        ("CX", &[b, t]),
+        ("Tdg", &[t]),
+        ("H", &[t]),
+    ];
+    if inverse {
+        gates.reverse();
+        for (name, _) in &mut gates {
+            *name = match *name {
+                "T" => "Tdg",
+                "Tdg" => "T",
+                other => other,
+            };
+        }
+    }
+    ops(&gates, span)
+}
+
+fn ops(gates: &[(&str, &[&QubitRef])], span: Span) -> Vec<Op> {
+    gates
+        .iter()
+        .map(|(name, qs)| Op::gate(*name, qs.iter().map(|q| (*q).clone()).collect(), span))
+        .collect()
+}
+
+// For each CCX, the later CCX that undoes its phase, if there is one. Gates
+// in between must be diagonal on the qubits of the pair to commute with the
+// phase.
+fn partners(ops: &[Op]) -> Vec<Option<usize>> {
+    let mut partners = vec![None; ops.len()];
+    let mut taken = vec![false; ops.len()];
+    for i in 0..ops.len() {
+        if canonical(ops[i].name()) != "CCX" || taken[i] {
+            continue;
+        }
+        let wires: Vec<Wire> = ops[i]
+            .qubits()
+            .iter()
+            .map(|q| Wire::Qubit(q.to_string()))
+            .collect();
+        for j in i + 1..ops.len() {
+            let shared: Vec<&Wire> = wires
+                .iter()
+                .filter(|w| ops[j].wires().contains(w))
+                .collect();
+            if shared.is_empty() {
+                continue;
+            }
+            // The phase need not be the same with the controls swapped.
+            let same = canonical(ops[j].name()) == "CCX" && ops[j].qubits() == ops[i].qubits();
+            if same && !taken[j] {
+                partners[i] = Some(j);
+                taken[j] = true;
+                break;
+            }
+            if !shared.iter().all(|w| axis_on(&ops[j], w) == Some(Axis::Z)) {
+                break;
+            }
+        }
+    }
+    partners
+}
@@ -0,0 +1,210 @@
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use super::gates::canonical;
+use super::{Op, OpKind, Wire};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+pub const ONE: Complex = Complex { re: 1.0, im: 0.0 };
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    // `r·e^(iθ)`.
+    pub fn polar(r: f64, theta: f64) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+// A single-qubit gate as a 2x2 matrix, rows first.
+pub type Matrix2 = [[Complex; 2]; 2];
+
+pub const IDENTITY: Matrix2 = [[ONE, ZERO], [ZERO, ONE]];
+
+// `a` after `b`, i.e. the product `a·b`.
+pub fn compose(a: &Matrix2, b: &Matrix2) -> Matrix2 {
+    let mut out = [[ZERO; 2]; 2];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = a[i][0] * b[0][j] + a[i][1] * b[1][j];
+        }
+    }
+    out
+}
+
+// The matrix of a single-qubit gate, or `None` for anything else.
+pub fn matrix(op: &Op) -> Option<Matrix2> {
+    let OpKind::Gate {
+        name,
+        params,
+        qubits,
+    } = &op.kind
+    else {
+        return None;
+    };
+    if qubits.len() != 1 {
+        return None;
+    }
+    let angle = |i: usize| params.get(i).map_or(0.0, |a| a.to_radians());
+    let c = |re: f64, im: f64| Complex::new(re, im);
+    let h = FRAC_1_SQRT_2;
+    let (cos, sin) = ((angle(0) / 2.0).cos(), (angle(0) / 2.0).sin());
+
+    let m = match name.as_str() {
+        "H" => [[c(h, 0.0), c(h, 0.0)], [c(h, 0.0), c(-h, 0.0)]],
+        "X" => [[ZERO, ONE], [ONE, ZERO]],
+        "Y" => [[ZERO, c(0.0, -1.0)], [c(0.0, 1.0), ZERO]],
+        "Z" => [[ONE, ZERO], [ZERO, c(-1.0, 0.0)]],
+        "S" => [[ONE, ZERO], [ZERO, c(0.0, 1.0)]],
+        "Sdg" => [[ONE, ZERO], [ZERO, c(0.0, -1.0)]],
+        "T" => [[ONE, ZERO], [ZERO, c(h, h)]],
+        "Tdg" => [[ONE, ZERO], [ZERO, c(h, -h)]],
+        "SX" => [[c(0.5, 0.5), c(0.5, -0.5)], [c(0.5, -0.5), c(0.5, 0.5)]],
+        "SXdg" => [[c(0.5, -0.5), c(0.5, 0.5)], [c(0.5, 0.5), c(0.5, -0.5)]],
+        "RX" => [[c(cos, 0.0), c(0.0, -sin)], [c(0.0, -sin), c(cos, 0.0)]],
+        "RY" => [[c(cos, 0.0), c(-sin, 0.0)], [c(sin, 0.0), c(cos, 0.0)]],
+        "RZ" => [[c(cos, -sin), ZERO], [ZERO, c(cos, sin)]],
+        "U3" => {
+            let (phi, lambda) = (angle(1), angle(2));
+            [
+                [c(cos, 0.0), -Complex::polar(sin, lambda)],
+                [Complex::polar(sin, phi), Complex::polar(cos, phi + lambda)],
+            ]
+        }
+        _ => return None,
+    };
+    Some(m)
+}
+
+// The unitary of a circuit on `wires`, as its columns: column `i` is the
+// state basis state `i` ends up in, wire `k` being bit `k` of the index.
+// `None` if a gate has no matrix here.
+pub fn unitary(ops: &[Op], wires: &[Wire]) -> Option<Vec<Vec<Complex>>> {
+    let size = 1 << wires.len();
+    let mut columns = Vec::new();
+    for column in 0..size {
+        let mut state = vec![ZERO; size];
+        state[column] = ONE;
+        for op in ops {
+            apply(&mut state, op, wires)?;
+        }
+        columns.push(state);
+    }
+    Some(columns)
+}
+
+fn apply(state: &mut [Complex], op: &Op, wires: &[Wire]) -> Option<()> {
+    let bits = op
+        .wires()
+        .iter()
+        .map(|w| wires.iter().position(|x| x == w))
+        .collect::<Option<Vec<usize>>>()?;
+    let set = |i: usize, bit: usize| i >> bit & 1 == 1;
+    match canonical(op.name()) {
+        "SWAP" => {
+            for i in 0..state.len() {
+                if set(i, bits[0]) && !set(i, bits[1]) {
+                    state.swap(i, i ^ 1 << bits[0] ^ 1 << bits[1]);
+                }
+            }
+        }
+        "CZ" => {
+            for (i, amplitude) in state.iter_mut().enumerate() {
+                if set(i, bits[0]) && set(i, bits[1]) {
+                    *amplitude = -*amplitude;
+                }
+            }
+        }
+        "CX" | "CCX" | "MCX" => {
+            let (target, controls) = bits.split_last()?;
+            for i in 0..state.len() {
+                if !set(i, *target) && controls.iter().all(|c| set(i, *c)) {
+                    state.swap(i, i | 1 << target);
+                }
+            }
+        }
+        _ => {
+            let m = matrix(op)?;
+            for i in 0..state.len() {
+                if set(i, bits[0]) {
+                    continue;
+                }
+                let j = i | 1 << bits[0];
+                let (a, b) = (state[i], state[j]);
+                state[i] = m[0][0] * a + m[0][1] * b;
+                state[j] = m[1][0] * a + m[1][1] * b;
+            }
+        }
+    }
+    Some(())
+}
+
+// Whether two unitaries differ only by a global phase.
+pub fn same_up_to_phase(a: &[Vec<Complex>], b: &[Vec<Complex>]) -> bool {
+    let pairs = || a.iter().flatten().zip(b.iter().flatten());
+    let Some((x, y)) = pairs().max_by(|p, q| p.0.abs().total_cmp(&q.0.abs())) else {
+        return true;
+    };
+    if y.abs() < 1e-9 {
+        return false;
+    }
+    // `x / y`, scaled to length 1.
+    let phase = *x * y.conj();
+    let phase = Complex::new(phase.re / phase.abs(), phase.im / phase.abs());
+    pairs().all(|(x, y)| (*x - phase * *y).abs() < 1e-9)
+}
@@ -0,0 +1,554 @@
+use std::collections::BTreeMap;
+
+use crate::ast::{Angle, QubitRef};
+
+use super::gates::canonical;
+use super::phase::{cx_count, phase, phase_gates, t_count};
+use super::rewrite::{identities, RuleCounts};
+use super::{cancel_counting, Dag, Op, OpKind, Target};
+
+// Optimizes each region of gates a ZX diagram can hold (see `in_region`)
+// by turning it into a graph-like diagram, simplifying that with spider
+// fusion, identity removal, local complementation and pivoting, and
+// extracting a circuit from what is left. The new circuit replaces the
+// region when it has fewer T gates, or as many and fewer CXs or gates.
+pub fn optimize(ops: Vec<Op>, target: &Target) -> Vec<Op> {
+    let mut dag = Dag::from_ops(ops);
+    for region in dag.regions(in_region) {
+        let before: Vec<Op> = region.iter().map(|id| dag.op(*id).clone()).collect();
+        let Some(after) = simplify(&before, target) else {
+            continue;
+        };
+        let cost = |ops: &[Op]| (t_count(ops), cx_count(ops), ops.len());
+        if cost(&after) < cost(&before) {
+            dag.splice(&region, after);
+        }
+    }
+    dag.to_ops()
+}
+
+// The circuit extracted from the simplified diagram of `ops`, whatever its
+// size, or `None` if a gate has no diagram here or extraction gets stuck.
+pub fn simplify(ops: &[Op], target: &Target) -> Option<Vec<Op>> {
+    let mut qubits: Vec<QubitRef> = Vec::new();
+    for op in ops {
+        for q in op.qubits() {
+            if !qubits.contains(q) {
+                qubits.push(q.clone());
+            }
+        }
+    }
+    let mut graph = Graph::from_ops(ops, &qubits)?;
+    graph.simplify();
+    let gates = graph.extract()?;
+
+    let span = ops.first()?.span;
+    let gate = |name: &str, qs: &[usize]| {
+        let qs = qs.iter().map(|q| qubits[*q].clone()).collect();
+        Op::gate(name, qs, span)
+    };
+    let mut out = Vec::new();
+    for g in gates.into_iter().rev() {
+        match g {
+            Gate::H(q) => out.push(gate("H", &[q])),
+            Gate::Phase(q, angle) => out.extend(phase_gates(angle, &qubits[q], span, target)),
+            Gate::Cx(c, t) => out.push(gate("CX", &[c, t])),
+            Gate::Cz(a, b) => {
+                out.push(gate("H", &[b]));
+                out.push(gate("CX", &[a, b]));
+                out.push(gate("H", &[b]));
+            }
+            Gate::Swap(a, b) if target.supports("SWAP") => out.push(gate("SWAP", &[a, b])),
+            Gate::Swap(a, b) => {
+                out.push(gate("CX", &[a, b]));
+                out.push(gate("CX", &[b, a]));
+                out.push(gate("CX", &[a, b]));
+            }
+        }
+    }
+    Some(cancel_counting(
+        out,
+        target,
+        &identities(),
+        &mut RuleCounts::new(),
+    ))
+}
+
+fn in_region(op: &Op) -> bool {
+    matches!(canonical(op.name()), "H" | "X" | "Y" | "RX" | "CX" | "SWAP") || phase(op).is_some()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    Simple,
+    Hadamard,
+}
+
+impl Edge {
+    fn toggled(self) -> Self {
+        match self {
+            Edge::Simple => Edge::Hadamard,
+            Edge::Hadamard => Edge::Simple,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Vertex {
+    Boundary,
+    // A Z spider and its phase.
+    Z(Angle),
+}
+
+// Gates as extraction finds them, from the outputs back, on qubit indices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gate {
+    H(usize),
+    Phase(usize, Angle),
+    Cx(usize, usize),
+    Cz(usize, usize),
+    Swap(usize, usize),
+}
+
+// A ZX diagram of Z spiders and boundaries. Ordered maps keep the
+// extracted circuit the same from one run to the next.
+#[derive(Debug, Clone, Default)]
+struct Graph {
+    // Indexed by vertex; removed vertices are left empty.
+    vertices: Vec<Option<Vertex>>,
+    edges: Vec<BTreeMap<usize, Edge>>,
+    inputs: Vec<usize>,
+    outputs: Vec<usize>,
+}
+
+impl Graph {
+    // X spiders are Z spiders with a Hadamard on each leg, so the diagram
+    // comes out graph-like once spiders joined by simple edges are fused.
+    fn from_ops(ops: &[Op], qubits: &[QubitRef]) -> Option<Self> {
+        let mut g = Graph::default();
+        // Per qubit, the vertex the next gate attaches to and the edge it
+        // attaches with.
+        let mut last: Vec<(usize, Edge)> = Vec::new();
+        for _ in qubits {
+            let input = g.add(Vertex::Boundary);
+            g.inputs.push(input);
+            last.push((input, Edge::Simple));
+        }
+        let pi = Angle::pi(1, 1);
+        let zero = Angle::pi(0, 1);
+
+        for op in ops {
+            let q: Vec<usize> = op
+                .qubits()
+                .iter()
+                .map(|q| qubits.iter().position(|x| x == q))
+                .collect::<Option<_>>()?;
+            let angle = match &op.kind {
+                OpKind::Gate { params, .. } => params.first().copied(),
+                _ => return None,
+            };
+            match canonical(op.name()) {
+                "H" => last[q[0]].1 = last[q[0]].1.toggled(),
+                "X" => {
+                    g.x_spider(&mut last, q[0], pi);
+                }
+                "RX" => {
+                    g.x_spider(&mut last, q[0], angle?);
+                }
+                "Y" => {
+                    g.z_spider(&mut last, q[0], pi);
+                    g.x_spider(&mut last, q[0], pi);
+                }
+                "CX" => {
+                    let control = g.z_spider(&mut last, q[0], zero);
+                    let target = g.x_spider(&mut last, q[1], zero);
+                    g.connect(control, target, Edge::Hadamard);
+                }
+                "SWAP" => last.swap(q[0], q[1]),
+                _ => {
+                    g.z_spider(&mut last, q[0], phase(op)?);
+                }
+            }
+        }
+        for (v, edge) in last {
+            let output = g.add(Vertex::Boundary);
+            g.outputs.push(output);
+            g.connect(v, output, edge);
+        }
+        Some(g)
+    }
+
+    fn add(&mut self, vertex: Vertex) -> usize {
+        self.vertices.push(Some(vertex));
+        self.edges.push(BTreeMap::new());
+        self.vertices.len() - 1
+    }
+
+    fn z_spider(&mut self, last: &mut [(usize, Edge)], q: usize, angle: Angle) -> usize {
+        let v = self.add(Vertex::Z(angle));
+        let (prev, edge) = last[q];
+        self.connect(prev, v, edge);
+        last[q] = (v, Edge::Simple);
+        v
+    }
+
+    fn x_spider(&mut self, last: &mut [(usize, Edge)], q: usize, angle: Angle) -> usize {
+        last[q].1 = last[q].1.toggled();
+        let v = self.z_spider(last, q, angle);
+        last[q].1 = Edge::Hadamard;
+        v
+    }
+
+    fn edge(&self, a: usize, b: usize) -> Option<Edge> {
+        self.edges[a].get(&b).copied()
+    }
+
+    fn set_edge(&mut self, a: usize, b: usize, edge: Option<Edge>) {
+        match edge {
+            Some(edge) => {
+                self.edges[a].insert(b, edge);
+                self.edges[b].insert(a, edge);
+            }
+            None => {
+                self.edges[a].remove(&b);
+                self.edges[b].remove(&a);
+            }
+        }
+    }
+
+    // Adds an edge next to any there already is. Between two Z spiders,
+    // two Hadamard edges cancel, and a Hadamard edge next to a simple one
+    // becomes a π phase once the spiders are fused.
+    fn connect(&mut self, a: usize, b: usize, edge: Edge) {
+        match (self.edge(a, b), edge) {
+            (None, _) => self.set_edge(a, b, Some(edge)),
+            (Some(Edge::Simple), Edge::Simple) => {}
+            (Some(Edge::Hadamard), Edge::Hadamard) => self.set_edge(a, b, None),
+            _ => {
+                self.set_edge(a, b, Some(Edge::Simple));
+                self.add_phase(a, Angle::pi(1, 1));
+            }
+        }
+    }
+
+    fn toggle(&mut self, a: usize, b: usize) {
+        let edge = match self.edge(a, b) {
+            Some(_) => None,
+            None => Some(Edge::Hadamard),
+        };
+        self.set_edge(a, b, edge);
+    }
+
+    fn remove(&mut self, v: usize) {
+        for n in self.neighbours(v) {
+            self.edges[n].remove(&v);
+        }
+        self.edges[v].clear();
+        self.vertices[v] = None;
+    }
+
+    fn neighbours(&self, v: usize) -> Vec<usize> {
+        self.edges[v].keys().copied().collect()
+    }
+
+    fn phase(&self, v: usize) -> Option<Angle> {
+        match self.vertices[v]? {
+            Vertex::Z(angle) => Some(angle),
+            Vertex::Boundary => None,
+        }
+    }
+
+    fn add_phase(&mut self, v: usize, angle: Angle) {
+        if let Some(Vertex::Z(phase)) = &mut self.vertices[v] {
+            *phase = *phase + angle;
+        }
+    }
+
+    fn spiders(&self) -> Vec<usize> {
+        (0..self.vertices.len())
+            .filter(|v| self.phase(*v).is_some())
+            .collect()
+    }
+
+    // A spider whose neighbours are all spiders, joined to it by Hadamard
+    // edges.
+    fn is_interior(&self, v: usize) -> bool {
+        self.phase(v).is_some()
+            && self.edges[v]
+                .iter()
+                .all(|(n, e)| self.phase(*n).is_some() && *e == Edge::Hadamard)
+    }
+
+    fn simplify(&mut self) {
+        while self.fuse_spiders()
+            || self.remove_identities()
+            || self.complement_locally()
+            || self.pivot()
+        {}
+    }
+
+    fn fuse_spiders(&mut self) -> bool {
+        let mut changed = false;
+        for v in self.spiders() {
+            while let Some(w) = self.edges[v]
+                .iter()
+                .find(|(w, e)| **e == Edge::Simple && self.phase(**w).is_some())
+                .map(|(w, _)| *w)
+            {
+                self.set_edge(v, w, None);
+                self.add_phase(v, self.phase(w).unwrap());
+                for (n, edge) in std::mem::take(&mut self.edges[w]) {
+                    self.edges[n].remove(&w);
+                    self.connect(v, n, edge);
+                }
+                self.vertices[w] = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // A phase-free spider between two others is a plain wire.
+    fn remove_identities(&mut self) -> bool {
+        for v in self.spiders() {
+            let neighbours = self.neighbours(v);
+            if self.phase(v).is_some_and(Angle::is_zero)
+                && neighbours.len() == 2
+                && self.is_interior(v)
+            {
+                self.remove(v);
+                self.connect(neighbours[0], neighbours[1], Edge::Simple);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Removes an interior spider with phase ±π/2: its neighbours become
+    // pairwise connected where they were not and vice versa, and lose its
+    // phase.
+    fn complement_locally(&mut self) -> bool {
+        for v in self.spiders() {
+            let Some(Angle::Pi {
+                num: 1 | -1,
+                den: 2,
+            }) = self.phase(v)
+            else {
+                continue;
+            };
+            if !self.is_interior(v) {
+                continue;
+            }
+            let phase = self.phase(v).unwrap();
+            let neighbours = self.neighbours(v);
+            self.remove(v);
+            for (i, a) in neighbours.iter().enumerate() {
+                self.add_phase(*a, -phase);
+                for b in &neighbours[i + 1..] {
+                    self.toggle(*a, *b);
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    // Removes two connected interior spiders with phase 0 or π, toggling
+    // the edges between their three kinds of neighbours: those of only
+    // one, of only the other, and of both.
+    fn pivot(&mut self) -> bool {
+        let pauli = |angle: Option<Angle>| matches!(angle, Some(Angle::Pi { den: 1, .. }));
+        for u in self.spiders() {
+            if !pauli(self.phase(u)) || !self.is_interior(u) {
+                continue;
+            }
+            let Some(v) = self
+                .neighbours(u)
+                .into_iter()
+                .find(|v| pauli(self.phase(*v)) && self.is_interior(*v))
+            else {
+                continue;
+            };
+
+            let (pu, pv) = (self.phase(u).unwrap(), self.phase(v).unwrap());
+            let nu: Vec<usize> = self.neighbours(u).into_iter().filter(|n| *n != v).collect();
+            let nv: Vec<usize> = self.neighbours(v).into_iter().filter(|n| *n != u).collect();
+            let both: Vec<usize> = nu.iter().copied().filter(|n| nv.contains(n)).collect();
+            let only_u: Vec<usize> = nu.iter().copied().filter(|n| !both.contains(n)).collect();
+            let only_v: Vec<usize> = nv.iter().copied().filter(|n| !both.contains(n)).collect();
+            self.remove(u);
+            self.remove(v);
+
+            for (xs, ys) in [(&only_u, &only_v), (&only_u, &both), (&only_v, &both)] {
+                for x in xs {
+                    for y in ys {
+                        self.toggle(*x, *y);
+                    }
+                }
+            }
+            for n in &only_u {
+                self.add_phase(*n, pv);
+            }
+            for n in &only_v {
+                self.add_phase(*n, pu);
+            }
+            for n in &both {
+                self.add_phase(*n, pu + pv + Angle::pi(1, 1));
+            }
+            return true;
+        }
+        false
+    }
+
+    // Puts a phase-free spider between `boundary` and its neighbour, so that
+    // the neighbour is no longer next to a boundary.
+    fn unfuse(&mut self, boundary: usize, neighbour: usize) -> usize {
+        let edge = self.edge(boundary, neighbour).unwrap();
+        self.set_edge(boundary, neighbour, None);
+        let w = self.add(Vertex::Z(Angle::pi(0, 1)));
+        self.set_edge(boundary, w, Some(edge.toggled()));
+        self.set_edge(w, neighbour, Some(Edge::Hadamard));
+        w
+    }
+
+    // Peels gates off the outputs until only a permutation of the inputs
+    // is left. Each round takes the phases and the CZs between the spiders
+    // next to the outputs (the frontier), then eliminates the Hadamard
+    // edges from the frontier to the spiders behind it with CXs until
+    // some frontier spider has only one of them left, which moves the
+    // frontier back past it.
+    fn extract(mut self) -> Option<Vec<Gate>> {
+        let n = self.outputs.len();
+        for b in self.inputs.clone().into_iter().chain(self.outputs.clone()) {
+            let neighbour = *self.neighbours(b).first()?;
+            self.unfuse(b, neighbour);
+        }
+
+        let mut gates = Vec::new();
+        let mut frontier: Vec<usize> = self
+            .outputs
+            .iter()
+            .map(|o| self.neighbours(*o)[0])
+            .collect();
+        loop {
+            for (q, v) in frontier.iter().enumerate() {
+                if self.edge(self.outputs[q], *v) == Some(Edge::Hadamard) {
+                    gates.push(Gate::H(q));
+                    self.set_edge(self.outputs[q], *v, Some(Edge::Simple));
+                }
+                let phase = self.phase(*v)?;
+                if !phase.is_zero() {
+                    gates.push(Gate::Phase(q, phase));
+                    self.vertices[*v] = Some(Vertex::Z(Angle::pi(0, 1)));
+                }
+            }
+            for a in 0..n {
+                for b in a + 1..n {
+                    if self.edge(frontier[a], frontier[b]).is_some() {
+                        gates.push(Gate::Cz(a, b));
+                        self.set_edge(frontier[a], frontier[b], None);
+                    }
+                }
+            }
+
+            // What is behind each frontier spider, with the inputs behind
+            // spiders of their own unless that is all there is.
+            let mut behind: Vec<Vec<usize>> = Vec::new();
+            for (q, v) in frontier.iter().enumerate() {
+                let mut others: Vec<usize> = self
+                    .neighbours(*v)
+                    .into_iter()
+                    .filter(|w| *w != self.outputs[q])
+                    .collect();
+                if others.len() > 1 {
+                    for w in others.iter_mut() {
+                        if self.inputs.contains(w) {
+                            *w = self.unfuse(*w, *v);
+                        }
+                    }
+                    others.sort();
+                }
+                behind.push(others);
+            }
+            let rows: Vec<usize> = (0..n)
+                .filter(|q| !(behind[*q].len() == 1 && self.inputs.contains(&behind[*q][0])))
+                .collect();
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut cols: Vec<usize> = rows.iter().flat_map(|q| behind[*q].clone()).collect();
+            cols.sort();
+            cols.dedup();
+            let mut matrix: Vec<Vec<bool>> = rows
+                .iter()
+                .map(|q| cols.iter().map(|c| behind[*q].contains(c)).collect())
+                .collect();
+
+            // Gaussian elimination, one CX per row operation.
+            let mut pivots: Vec<usize> = Vec::new();
+            for c in 0..cols.len() {
+                let Some(p) = (0..rows.len()).find(|r| !pivots.contains(r) && matrix[*r][c]) else {
+                    continue;
+                };
+                for r in 0..rows.len() {
+                    if r != p && matrix[r][c] {
+                        let pivot_row = matrix[p].clone();
+                        for (x, y) in matrix[r].iter_mut().zip(pivot_row) {
+                            *x ^= y;
+                        }
+                        let (a, b) = (frontier[rows[r]], frontier[rows[p]]);
+                        for w in &cols {
+                            if self.edge(b, *w).is_some() {
+                                self.toggle(a, *w);
+                            }
+                        }
+                        gates.push(Gate::Cx(rows[r], rows[p]));
+                    }
+                }
+                pivots.push(p);
+            }
+
+            let mut moved = false;
+            for (r, q) in rows.iter().enumerate() {
+                let ones: Vec<usize> = (0..cols.len()).filter(|c| matrix[r][*c]).collect();
+                if let [c] = ones[..] {
+                    let (v, w) = (frontier[*q], cols[c]);
+                    self.remove(v);
+                    self.set_edge(self.outputs[*q], w, Some(Edge::Hadamard));
+                    frontier[*q] = w;
+                    moved = true;
+                }
+            }
+            if !moved {
+                return None;
+            }
+        }
+
+        // Each frontier spider is now a wire from some input, perhaps with
+        // a Hadamard on it.
+        let mut from = Vec::new();
+        for (q, v) in frontier.iter().enumerate() {
+            let input = self
+                .neighbours(*v)
+                .into_iter()
+                .find(|w| *w != self.outputs[q])?;
+            if self.edge(*v, input) == Some(Edge::Hadamard) {
+                gates.push(Gate::H(q));
+            }
+            from.push(self.inputs.iter().position(|i| *i == input)?);
+        }
+        // Swaps that bring input `from[q]` to qubit `q`, in program order.
+        let mut holds: Vec<usize> = (0..n).collect();
+        let mut swaps = Vec::new();
+        for (q, input) in from.iter().enumerate() {
+            let k = holds.iter().position(|h| h == input)?;
+            if k != q {
+                swaps.push(Gate::Swap(q, k));
+                holds.swap(q, k);
+            }
+        }
+        gates.extend(swaps.into_iter().rev());
+        Some(gates)
+    }
+}
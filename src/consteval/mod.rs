@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+use crate::ast::{BinOp, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+
+// How constant arithmetic treats results that do not fit in an `i64`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArithMode {
+    // Overflow and out-of-range shifts are compile errors.
+    #[default]
+    Checked,
+    // Results wrap around and shift amounts are masked, as in
+    // `i64::wrapping_*`. Division by zero is still an error.
+    Wrapping,
+}
+
+impl FromStr for ArithMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(ArithMode::Checked),
+            "wrapping" => Ok(ArithMode::Wrapping),
+            _ => bail!(
+                "unknown arithmetic mode `{}` (expected checked or wrapping)",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    DivByZero,
+    Overflow,
+    ShiftOutOfRange(i64),
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithError::DivByZero => write!(f, "attempt to divide by zero"),
+            ArithError::Overflow => write!(f, "arithmetic overflow"),
+            ArithError::ShiftOutOfRange(n) => {
+                write!(f, "shift amount {} is out of range for a 64-bit integer", n)
+            }
+        }
+    }
+}
+
+pub fn eval_binop(op: BinOp, a: i64, b: i64, mode: ArithMode) -> Result<i64, ArithError> {
+    check_rhs(op, b, mode)?;
+
+    let wrapping = mode == ArithMode::Wrapping;
+    let result = match op {
+        BinOp::Add if wrapping => Some(a.wrapping_add(b)),
+        BinOp::Sub if wrapping => Some(a.wrapping_sub(b)),
+        BinOp::Mul if wrapping => Some(a.wrapping_mul(b)),
+        BinOp::Div if wrapping => Some(a.wrapping_div(b)),
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div => a.checked_div(b),
+        BinOp::Shl => Some(a.wrapping_shl(b as u32)),
+        BinOp::Shr => Some(a.wrapping_shr(b as u32)),
+        BinOp::Eq => Some((a == b) as i64),
+        BinOp::Ne => Some((a != b) as i64),
+        BinOp::Lt => Some((a < b) as i64),
+        BinOp::Le => Some((a <= b) as i64),
+        BinOp::Gt => Some((a > b) as i64),
+        BinOp::Ge => Some((a >= b) as i64),
+    };
+    result.ok_or(ArithError::Overflow)
+}
+
+// Errors that only depend on the right operand, so they can be reported
+// even when the left one is not known.
+pub fn check_rhs(op: BinOp, b: i64, mode: ArithMode) -> Result<(), ArithError> {
+    match op {
+        BinOp::Div if b == 0 => Err(ArithError::DivByZero),
+        BinOp::Shl | BinOp::Shr if mode == ArithMode::Checked && !(0..64).contains(&b) => {
+            Err(ArithError::ShiftOutOfRange(b))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Evaluates every expression whose operands are known at compile time and
+// reports the ones that would fail at runtime.
+pub fn check_program(program: &Program, mode: ArithMode) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    for func in &program.functions {
+        let mut cx = ConstCx {
+            mode,
+            env: HashMap::new(),
+            diags: &mut diags,
+        };
+        cx.check_function(func);
+    }
+    diags
+}
+
+struct ConstCx<'a> {
+    mode: ArithMode,
+    env: HashMap<String, i64>,
+    diags: &'a mut Vec<Diagnostic>,
+}
+
+impl ConstCx<'_> {
+    fn check_function(&mut self, func: &Function) {
+        self.check_block(&func.body);
+    }
+
+    // Bindings are block scoped, so the environment is restored afterwards.
+    // Both arms of an `if` are checked, whatever the condition.
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        let saved = self.env.clone();
+        for stmt in stmts {
+            self.check_stmt(stmt);
+        }
+        self.env = saved;
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, init } => match self.eval(init) {
+                Some(v) => {
+                    self.env.insert(name.clone(), v);
+                }
+                None => {
+                    self.env.remove(name);
+                }
+            },
+            StmtKind::Measure { target, classical } => {
+                self.eval_index(target);
+                if let Some(c) = classical {
+                    self.env.remove(c);
+                }
+            }
+            StmtKind::QOp { qubits, .. } => {
+                for qubit in qubits {
+                    self.eval_index(qubit);
+                }
+            }
+            StmtKind::Reset { target } => {
+                self.eval_index(target);
+            }
+            StmtKind::QbitDecl { name, size } => {
+                if let Some(size) = size {
+                    self.eval(size);
+                }
+                self.env.remove(name);
+            }
+            StmtKind::Return(Some(expr)) | StmtKind::Expr(expr) => {
+                self.eval(expr);
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                self.eval(cond);
+                self.check_block(then_body);
+                if let Some(body) = else_body {
+                    self.check_block(body);
+                }
+            }
+            StmtKind::Block(body) => self.check_block(body),
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.eval(start);
+                self.eval(end);
+                let saved = self.env.clone();
+                self.env.remove(var);
+                self.check_block(body);
+                self.env = saved;
+            }
+            StmtKind::Return(None) => {}
+        }
+    }
+
+    fn eval_index(&mut self, target: &QubitRef) {
+        if let Some(index) = &target.index {
+            self.eval(index);
+        }
+    }
+
+    // Returns the value of `expr` if it is known. A failing operation is
+    // reported once, at the operation, and then treated as unknown so it
+    // does not cascade.
+    fn eval(&mut self, expr: &Expr) -> Option<i64> {
+        match expr {
+            Expr::Number(v) => Some(*v),
+            Expr::Var(name) => self.env.get(name).copied(),
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    self.eval(arg);
+                }
+                None
+            }
+            Expr::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let a = self.eval(left);
+                let b = self.eval(right)?;
+
+                let result = match a {
+                    Some(a) => eval_binop(*op, a, b, self.mode),
+                    None => check_rhs(*op, b, self.mode).map(|_| 0),
+                };
+                match result {
+                    Ok(v) => a.map(|_| v),
+                    Err(e) => {
+                        let message = match (e, a) {
+                            (ArithError::Overflow, Some(a)) => format!(
+                                "attempt to compute `{} {} {}`, which would overflow",
+                                a, op, b
+                            ),
+                            _ => format!("{} in `{}`", e, expr),
+                        };
+                        self.diags.push(Diagnostic::error(message, *span));
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Attribute, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::lexer::annotate::Annotation;
+
+#[derive(Debug, Clone)]
+pub struct InlineOptions {
+    // A callee is inlined when its size times the number of calls to it is
+    // at most this, unless an attribute says otherwise.
+    pub threshold: usize,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        Self { threshold: 16 }
+    }
+}
+
+// Replaces calls with the body of the callee, working up the call graph from
+// the leaves so that inlined bodies already have their own calls inlined.
+// `#[inline]` always inlines a function where possible and `#[inline(never)]`
+// never does; recursive functions are never inlined.
+//
+// Only whole-statement calls are inlined: `f(..);`, `let x = f(..);` and
+// `return f(..);`, and only when the callee returns at its end, if at all.
+// Inlined statements keep the spans they have in the callee. A function
+// that was called before and has no calls left afterwards is dropped.
+pub fn inline_program(program: &Program, options: &InlineOptions) -> Program {
+    let graph = CallGraph::new(program);
+    let mut inliner = Inliner {
+        options,
+        graph: &graph,
+        originals: program
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f))
+            .collect(),
+        done: HashMap::new(),
+    };
+
+    for func in &program.functions {
+        inliner.visit(&func.name);
+    }
+
+    let functions: Vec<Function> = program
+        .functions
+        .iter()
+        .map(|f| inliner.done.remove(f.name.as_str()).unwrap())
+        .collect();
+
+    let mut remaining = HashMap::new();
+    for func in &functions {
+        count_calls(&func.body, &mut remaining);
+    }
+    let functions = functions
+        .into_iter()
+        .filter(|f| !graph.calls.contains_key(&f.name) || remaining.contains_key(&f.name))
+        .collect();
+    Program { functions }
+}
+
+struct CallGraph {
+    // How many call sites each called function has.
+    calls: HashMap<String, usize>,
+    callees: HashMap<String, HashSet<String>>,
+}
+
+impl CallGraph {
+    fn new(program: &Program) -> Self {
+        let mut calls = HashMap::new();
+        let mut callees = HashMap::new();
+        for func in &program.functions {
+            let mut mine = HashMap::new();
+            count_calls(&func.body, &mut mine);
+            for (callee, n) in &mine {
+                *calls.entry(callee.clone()).or_insert(0) += n;
+            }
+            callees.insert(func.name.clone(), mine.into_keys().collect());
+        }
+        Self { calls, callees }
+    }
+
+    // Whether `name` can reach itself through calls.
+    fn is_recursive(&self, name: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut work: Vec<&str> = vec![name];
+        while let Some(f) = work.pop() {
+            for callee in self.callees.get(f).into_iter().flatten() {
+                if callee == name {
+                    return true;
+                }
+                if seen.insert(callee.as_str()) {
+                    work.push(callee);
+                }
+            }
+        }
+        false
+    }
+}
+
+struct Inliner<'a> {
+    options: &'a InlineOptions,
+    graph: &'a CallGraph,
+    originals: HashMap<&'a str, &'a Function>,
+    // Functions whose calls have been inlined already.
+    done: HashMap<String, Function>,
+}
+
+impl Inliner<'_> {
+    fn visit(&mut self, name: &str) {
+        let Some(&func) = self.originals.get(name) else {
+            return;
+        };
+        if self.done.contains_key(name) {
+            return;
+        }
+        // Placeholder so cycles stop here; recursive functions are never
+        // inlined, so their body is not needed yet.
+        self.done.insert(name.to_string(), func.clone());
+
+        let mut callees: Vec<&String> = self.graph.callees[name].iter().collect();
+        callees.sort();
+        for callee in callees {
+            self.visit(callee);
+        }
+
+        let mut site = Site {
+            taken: names_in(func),
+        };
+        let body = self.block(&func.body, func.pe_enabled, &mut site);
+        self.done.insert(
+            name.to_string(),
+            Function {
+                body,
+                ..func.clone()
+            },
+        );
+    }
+
+    fn block(&self, stmts: &[Stmt], mut pe: bool, site: &mut Site) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        for stmt in stmts {
+            if let Some(on) = stmt
+                .attrs
+                .iter()
+                .rev()
+                .find_map(|a| a.annotation.pe_toggle())
+            {
+                pe = on;
+            }
+
+            let call = match &stmt.kind {
+                StmtKind::Expr(Expr::Call { callee, args })
+                | StmtKind::Let {
+                    init: Expr::Call { callee, args },
+                    ..
+                }
+                | StmtKind::Return(Some(Expr::Call { callee, args })) => Some((callee, args)),
+                _ => None,
+            };
+            if let Some((callee, args)) = call {
+                if stmt.attrs.is_empty() {
+                    if let Some(inlined) = self.inline_call(stmt, callee, args, pe, site) {
+                        out.extend(inlined);
+                        continue;
+                    }
+                }
+            }
+
+            let kind = match &stmt.kind {
+                StmtKind::If {
+                    cond,
+                    then_body,
+                    else_body,
+                } => StmtKind::If {
+                    cond: cond.clone(),
+                    then_body: self.block(then_body, pe, site),
+                    else_body: else_body.as_ref().map(|b| self.block(b, pe, site)),
+                },
+                StmtKind::Block(body) => StmtKind::Block(self.block(body, pe, site)),
+                StmtKind::For {
+                    var,
+                    start,
+                    end,
+                    body,
+                } => StmtKind::For {
+                    var: var.clone(),
+                    start: start.clone(),
+                    end: end.clone(),
+                    body: self.block(body, pe, site),
+                },
+                kind => kind.clone(),
+            };
+            out.push(Stmt {
+                kind,
+                ..stmt.clone()
+            });
+        }
+        out
+    }
+
+    fn should_inline(&self, callee: &Function) -> bool {
+        if has_attr(&callee.attrs, &Annotation::InlineNever)
+            || self.graph.is_recursive(&callee.name)
+        {
+            return false;
+        }
+        if has_attr(&callee.attrs, &Annotation::Inline) {
+            return true;
+        }
+        let calls = self.graph.calls.get(&callee.name).copied().unwrap_or(0);
+        size(&callee.body) * calls <= self.options.threshold
+    }
+
+    // The statements replacing `stmt`, or `None` to keep the call.
+    fn inline_call(
+        &self,
+        stmt: &Stmt,
+        callee: &str,
+        args: &[Expr],
+        pe: bool,
+        site: &mut Site,
+    ) -> Option<Vec<Stmt>> {
+        let func = self.done.get(callee)?;
+        // Inlining across a `#[pe]`/`#[nope]` boundary would change how the
+        // body is evaluated.
+        if func.params.len() != args.len() || func.pe_enabled != pe || !self.should_inline(func) {
+            return None;
+        }
+
+        let (body, ret) = match func.body.split_last() {
+            Some((
+                Stmt {
+                    kind: StmtKind::Return(ret),
+                    ..
+                },
+                rest,
+            )) => (rest, ret.as_ref()),
+            _ => (func.body.as_slice(), None),
+        };
+        if body.iter().any(|s| returns_inside(&s.kind)) {
+            return None;
+        }
+        let qubit_params = qubit_names(&func.body);
+
+        let mut out = Vec::new();
+        let mut subst = Subst::new();
+        for (param, arg) in func.params.iter().zip(args) {
+            match arg {
+                Expr::Var(_) => {}
+                Expr::Number(_) if !qubit_params.contains(param) => {}
+                _ if qubit_params.contains(param) => return None,
+                _ => {
+                    let fresh = site.fresh(param, callee);
+                    out.push(Stmt {
+                        kind: StmtKind::Let {
+                            name: fresh.clone(),
+                            init: arg.clone(),
+                        },
+                        attrs: Vec::new(),
+                        span: stmt.span,
+                    });
+                    subst.insert(param.clone(), Expr::Var(fresh));
+                    continue;
+                }
+            }
+            subst.insert(param.clone(), arg.clone());
+        }
+
+        out.extend(rename_block(body, &mut subst, callee, site));
+        let ret = ret.map(|e| rename_expr(e, &subst));
+
+        let last = match (&stmt.kind, ret) {
+            (StmtKind::Let { name, .. }, Some(init)) => StmtKind::Let {
+                name: name.clone(),
+                init,
+            },
+            (StmtKind::Let { .. }, None) => return None,
+            (StmtKind::Return(_), ret) => StmtKind::Return(ret),
+            (_, Some(expr)) if expr.has_call() => StmtKind::Expr(expr),
+            _ => return Some(out),
+        };
+        let span = func.body.last().map_or(stmt.span, |s| s.span);
+        out.push(Stmt {
+            kind: last,
+            attrs: Vec::new(),
+            span,
+        });
+        Some(out)
+    }
+}
+
+// Names in use in the function being inlined into, so inlined locals can be
+// given fresh ones.
+struct Site {
+    taken: HashSet<String>,
+}
+
+impl Site {
+    // `t` from `rotate` becomes `t_rotate`, or `t_rotate_2` if that is taken.
+    fn fresh(&mut self, name: &str, callee: &str) -> String {
+        let base = format!("{}_{}", name, callee);
+        let mut fresh = base.clone();
+        let mut n = 2;
+        while self.taken.contains(&fresh) {
+            fresh = format!("{}_{}", base, n);
+            n += 1;
+        }
+        self.taken.insert(fresh.clone());
+        fresh
+    }
+}
+
+// What each name in the callee stands for at the call site.
+type Subst = HashMap<String, Expr>;
+
+fn rename_block(stmts: &[Stmt], subst: &mut Subst, callee: &str, site: &mut Site) -> Vec<Stmt> {
+    stmts
+        .iter()
+        .map(|stmt| {
+            let kind = rename_stmt(&stmt.kind, subst, callee, site);
+            Stmt {
+                kind,
+                ..stmt.clone()
+            }
+        })
+        .collect()
+}
+
+// Bindings are block scoped, so nested blocks get their own copy of `subst`.
+fn rename_stmt(kind: &StmtKind, subst: &mut Subst, callee: &str, site: &mut Site) -> StmtKind {
+    let mut bind = |subst: &mut Subst, name: &str| {
+        let fresh = site.fresh(name, callee);
+        subst.insert(name.to_string(), Expr::Var(fresh.clone()));
+        fresh
+    };
+
+    match kind {
+        StmtKind::Let { name, init } => {
+            let init = rename_expr(init, subst);
+            StmtKind::Let {
+                name: bind(subst, name),
+                init,
+            }
+        }
+        StmtKind::QbitDecl { name, size } => {
+            let size = size.as_ref().map(|e| rename_expr(e, subst));
+            StmtKind::QbitDecl {
+                name: bind(subst, name),
+                size,
+            }
+        }
+        StmtKind::QOp {
+            gate,
+            params,
+            qubits,
+        } => StmtKind::QOp {
+            gate: gate.clone(),
+            params: params.clone(),
+            qubits: qubits.iter().map(|q| rename_qubit(q, subst)).collect(),
+        },
+        StmtKind::Measure { target, classical } => {
+            let target = rename_qubit(target, subst);
+            StmtKind::Measure {
+                target,
+                classical: classical.as_ref().map(|c| bind(subst, c)),
+            }
+        }
+        StmtKind::Reset { target } => StmtKind::Reset {
+            target: rename_qubit(target, subst),
+        },
+        StmtKind::Return(expr) => StmtKind::Return(expr.as_ref().map(|e| rename_expr(e, subst))),
+        StmtKind::Expr(expr) => StmtKind::Expr(rename_expr(expr, subst)),
+        StmtKind::If {
+            cond,
+            then_body,
+            else_body,
+        } => StmtKind::If {
+            cond: rename_expr(cond, subst),
+            then_body: rename_block(then_body, &mut subst.clone(), callee, site),
+            else_body: else_body
+                .as_ref()
+                .map(|b| rename_block(b, &mut subst.clone(), callee, site)),
+        },
+        StmtKind::Block(body) => {
+            StmtKind::Block(rename_block(body, &mut subst.clone(), callee, site))
+        }
+        StmtKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            let start = rename_expr(start, subst);
+            let end = rename_expr(end, subst);
+            let mut inner = subst.clone();
+            let var = bind(&mut inner, var);
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body: rename_block(body, &mut inner, callee, site),
+            }
+        }
+    }
+}
+
+fn rename_expr(expr: &Expr, subst: &Subst) -> Expr {
+    match expr {
+        Expr::Number(_) => expr.clone(),
+        Expr::Var(name) => subst.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        Expr::Call { callee, args } => Expr::Call {
+            callee: callee.clone(),
+            args: args.iter().map(|a| rename_expr(a, subst)).collect(),
+        },
+        Expr::Binary {
+            op,
+            left,
+            right,
+            span,
+        } => Expr::Binary {
+            op: *op,
+            left: Box::new(rename_expr(left, subst)),
+            right: Box::new(rename_expr(right, subst)),
+            span: *span,
+        },
+    }
+}
+
+// Qubit parameters are only inlined with plain variable arguments, so the
+// substitution is always a name here.
+fn rename_qubit(target: &QubitRef, subst: &Subst) -> QubitRef {
+    let name = match subst.get(&target.name) {
+        Some(Expr::Var(name)) => name.clone(),
+        _ => target.name.clone(),
+    };
+    QubitRef {
+        name,
+        index: target.index.as_ref().map(|e| rename_expr(e, subst)),
+    }
+}
+
+fn has_attr(attrs: &[Attribute], annotation: &Annotation) -> bool {
+    attrs.iter().any(|a| &a.annotation == annotation)
+}
+
+fn count_calls(stmts: &[Stmt], out: &mut HashMap<String, usize>) {
+    fn walk(expr: &Expr, out: &mut HashMap<String, usize>) {
+        match expr {
+            Expr::Call { callee, args } => {
+                *out.entry(callee.clone()).or_insert(0) += 1;
+                args.iter().for_each(|a| walk(a, out));
+            }
+            Expr::Binary { left, right, .. } => {
+                walk(left, out);
+                walk(right, out);
+            }
+            Expr::Number(_) | Expr::Var(_) => {}
+        }
+    }
+
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Let { init: e, .. } | StmtKind::Return(Some(e)) | StmtKind::Expr(e) => {
+                walk(e, out)
+            }
+            StmtKind::QbitDecl {
+                size: Some(size), ..
+            } => walk(size, out),
+            StmtKind::QOp { qubits, .. } => {
+                for index in qubits.iter().filter_map(|q| q.index.as_ref()) {
+                    walk(index, out);
+                }
+            }
+            StmtKind::Measure { target, .. } | StmtKind::Reset { target } => {
+                if let Some(index) = &target.index {
+                    walk(index, out);
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                walk(cond, out);
+                count_calls(then_body, out);
+                if let Some(body) = else_body {
+                    count_calls(body, out);
+                }
+            }
+            StmtKind::Block(body) => count_calls(body, out),
+            StmtKind::For {
+                start, end, body, ..
+            } => {
+                walk(start, out);
+                walk(end, out);
+                count_calls(body, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Statements, counting those in nested blocks.
+fn size(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|s| {
+            1 + match &s.kind {
+                StmtKind::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => size(then_body) + else_body.as_deref().map_or(0, size),
+                StmtKind::Block(body) | StmtKind::For { body, .. } => size(body),
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+fn returns_inside(kind: &StmtKind) -> bool {
+    match kind {
+        StmtKind::Return(_) => true,
+        StmtKind::If {
+            then_body,
+            else_body,
+            ..
+        } => then_body
+            .iter()
+            .chain(else_body.iter().flatten())
+            .any(|s| returns_inside(&s.kind)),
+        StmtKind::Block(body) | StmtKind::For { body, .. } => {
+            body.iter().any(|s| returns_inside(&s.kind))
+        }
+        _ => false,
+    }
+}
+
+// Names used as qubit operands anywhere in the body.
+fn qubit_names(stmts: &[Stmt]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::QOp { qubits, .. } => {
+                names.extend(qubits.iter().map(|q| q.name.clone()));
+            }
+            StmtKind::Measure { target, .. } | StmtKind::Reset { target } => {
+                names.insert(target.name.clone());
+            }
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                names.extend(qubit_names(then_body));
+                names.extend(else_body.as_deref().map(qubit_names).unwrap_or_default());
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => names.extend(qubit_names(body)),
+            _ => {}
+        }
+    }
+    names
+}
+
+fn names_in(func: &Function) -> HashSet<String> {
+    let mut names: HashSet<String> = func.params.iter().cloned().collect();
+    fn walk(stmts: &[Stmt], names: &mut HashSet<String>) {
+        for stmt in stmts {
+            stmt.kind.for_each_use(&mut |n| {
+                names.insert(n.to_string());
+            });
+            match &stmt.kind {
+                StmtKind::Let { name, .. } | StmtKind::QbitDecl { name, .. } => {
+                    names.insert(name.clone());
+                }
+                StmtKind::Measure {
+                    classical: Some(c), ..
+                } => {
+                    names.insert(c.clone());
+                }
+                StmtKind::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    walk(then_body, names);
+                    walk(else_body.as_deref().unwrap_or_default(), names);
+                }
+                StmtKind::Block(body) => walk(body, names),
+                StmtKind::For { var, body, .. } => {
+                    names.insert(var.clone());
+                    walk(body, names);
+                }
+                _ => {}
+            }
+        }
+    }
+    walk(&func.body, &mut names);
+    names
+}
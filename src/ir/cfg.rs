@@ -0,0 +1,124 @@
+use super::{BlockId, IrFunction};
+
+// Predecessors of each block, indexed by `BlockId`, in block order.
+pub fn predecessors(func: &IrFunction) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); func.blocks.len()];
+    for (i, block) in func.blocks.iter().enumerate() {
+        for succ in block.term.successors() {
+            if !preds[succ.0].contains(&BlockId(i)) {
+                preds[succ.0].push(BlockId(i));
+            }
+        }
+    }
+    preds
+}
+
+// Blocks reachable from the entry, each before its successors except along
+// back edges.
+pub fn reverse_postorder(func: &IrFunction) -> Vec<BlockId> {
+    let mut visited = vec![false; func.blocks.len()];
+    let mut order = Vec::new();
+    // (block, whether its successors have been pushed)
+    let mut stack = vec![(BlockId(0), false)];
+    while let Some((block, expanded)) = stack.pop() {
+        if expanded {
+            order.push(block);
+            continue;
+        }
+        if visited[block.0] {
+            continue;
+        }
+        visited[block.0] = true;
+        stack.push((block, true));
+        for succ in func.block(block).term.successors().into_iter().rev() {
+            if !visited[succ.0] {
+                stack.push((succ, false));
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+pub struct Dominators {
+    // Immediate dominator of each block; the entry is its own, and blocks
+    // that cannot be reached have none.
+    idom: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    // Cooper, Harvey and Kennedy's iterative algorithm.
+    pub fn new(func: &IrFunction) -> Self {
+        let preds = predecessors(func);
+        let rpo = reverse_postorder(func);
+        let mut index = vec![usize::MAX; func.blocks.len()];
+        for (i, block) in rpo.iter().enumerate() {
+            index[block.0] = i;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; func.blocks.len()];
+        idom[0] = Some(BlockId(0));
+
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while index[a.0] > index[b.0] {
+                    a = idom[a.0].unwrap();
+                }
+                while index[b.0] > index[a.0] {
+                    b = idom[b.0].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &preds[block.0] {
+                    if idom[pred.0].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(current) => intersect(&idom, pred, current),
+                    });
+                }
+                if new_idom != idom[block.0] {
+                    idom[block.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Self { idom }
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idom[block.0].is_some()
+    }
+
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        match self.idom[block.0] {
+            Some(parent) if parent != block => Some(parent),
+            _ => None,
+        }
+    }
+
+    // Whether every path from the entry to `b` passes through `a`.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut block = b;
+        loop {
+            if block == a {
+                return true;
+            }
+            match self.idom(block) {
+                Some(parent) => block = parent,
+                None => return false,
+            }
+        }
+    }
+}
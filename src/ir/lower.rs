@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::ast::{BinOp, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::lexer::Span;
+
+use super::{Block, BlockId, Inst, InstKind, IrFunction, Module, Terminator, Type, Value};
+
+// Parameter and return types of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sig {
+    pub params: Vec<Type>,
+    pub ret: Option<Type>,
+}
+
+// Lowers every function to SSA form. Bindings are block scoped and never
+// reassigned, so the only values that need a phi are loop counters.
+pub fn lower_program(program: &Program) -> Result<Module> {
+    let sigs = signatures(program);
+    let functions = program
+        .functions
+        .iter()
+        .map(|func| lower_function(func, &sigs))
+        .collect::<Result<_>>()?;
+    Ok(Module { functions })
+}
+
+// Source parameters are untyped: one is a qubit (or qubit array) if the
+// body uses it as a gate operand, or passes it on to a parameter that is
+// one. This repeats until no parameter changes.
+pub fn signatures(program: &Program) -> HashMap<String, Sig> {
+    let mut sigs: HashMap<String, Sig> = program
+        .functions
+        .iter()
+        .map(|f| {
+            let sig = Sig {
+                params: vec![Type::Int; f.params.len()],
+                ret: None,
+            };
+            (f.name.clone(), sig)
+        })
+        .collect();
+
+    loop {
+        let mut changed = false;
+        for func in &program.functions {
+            let mut uses = HashMap::new();
+            qubit_uses(&func.body, &sigs, &mut uses);
+            let sig = sigs.get_mut(&func.name).unwrap();
+            for (param, ty) in func.params.iter().zip(sig.params.iter_mut()) {
+                if let Some(&used) = uses.get(param.as_str()) {
+                    if *ty != used {
+                        *ty = used;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // Return types depend on those of the functions called, so they settle
+    // the same way.
+    loop {
+        let mut changed = false;
+        for func in &program.functions {
+            let mut qubits: HashMap<&str, Type> = func
+                .params
+                .iter()
+                .zip(&sigs[&func.name].params)
+                .filter(|(_, ty)| **ty != Type::Int)
+                .map(|(p, ty)| (p.as_str(), *ty))
+                .collect();
+            let ret = return_type(&func.body, &mut qubits, &sigs);
+            let sig = sigs.get_mut(&func.name).unwrap();
+            if sig.ret != ret {
+                sig.ret = ret;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    sigs
+}
+
+fn qubit_uses<'p>(
+    stmts: &'p [Stmt],
+    sigs: &HashMap<String, Sig>,
+    out: &mut HashMap<&'p str, Type>,
+) {
+    fn call_args<'p>(
+        expr: &'p Expr,
+        sigs: &HashMap<String, Sig>,
+        out: &mut HashMap<&'p str, Type>,
+    ) {
+        match expr {
+            Expr::Call { callee, args } => {
+                for (i, arg) in args.iter().enumerate() {
+                    let ty = sigs.get(callee).and_then(|s| s.params.get(i));
+                    match (arg, ty) {
+                        (Expr::Var(name), Some(ty)) if *ty != Type::Int => {
+                            out.insert(name, *ty);
+                        }
+                        _ => call_args(arg, sigs, out),
+                    }
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                call_args(left, sigs, out);
+                call_args(right, sigs, out);
+            }
+            Expr::Number(_) | Expr::Var(_) => {}
+        }
+    }
+
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::QOp { qubits, .. } => {
+                for target in qubits {
+                    out.insert(&target.name, operand_type(target));
+                }
+            }
+            StmtKind::Measure { target, .. } | StmtKind::Reset { target } => {
+                out.insert(&target.name, operand_type(target));
+            }
+            StmtKind::Let { init: e, .. } | StmtKind::Return(Some(e)) | StmtKind::Expr(e) => {
+                call_args(e, sigs, out)
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                call_args(cond, sigs, out);
+                qubit_uses(then_body, sigs, out);
+                qubit_uses(else_body.as_deref().unwrap_or_default(), sigs, out);
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => qubit_uses(body, sigs, out),
+            StmtKind::QbitDecl { .. } | StmtKind::Return(None) => {}
+        }
+    }
+}
+
+// An element `r[i]` is read from an array.
+fn operand_type(target: &QubitRef) -> Type {
+    match target.index {
+        Some(_) => Type::QReg,
+        None => Type::Qubit,
+    }
+}
+
+// `return q;` of a qubit returns the handle, as does returning a call that
+// returns one; any other value is an int.
+fn return_type<'p>(
+    stmts: &'p [Stmt],
+    qubits: &mut HashMap<&'p str, Type>,
+    sigs: &HashMap<String, Sig>,
+) -> Option<Type> {
+    let call_type = |callee: &str| sigs.get(callee).map_or(Some(Type::Int), |s| s.ret);
+    let mut ret = None;
+    for stmt in stmts {
+        let found = match &stmt.kind {
+            StmtKind::QbitDecl { name, size } => {
+                let ty = if size.is_some() {
+                    Type::QReg
+                } else {
+                    Type::Qubit
+                };
+                qubits.insert(name, ty);
+                None
+            }
+            StmtKind::Let {
+                name,
+                init: Expr::Call { callee, .. },
+            } => {
+                if let Some(ty) = call_type(callee).filter(|ty| *ty != Type::Int) {
+                    qubits.insert(name, ty);
+                }
+                None
+            }
+            StmtKind::Return(Some(Expr::Call { callee, .. })) => call_type(callee),
+            StmtKind::Return(Some(Expr::Var(name))) => {
+                Some(qubits.get(name.as_str()).copied().unwrap_or(Type::Int))
+            }
+            StmtKind::Return(Some(_)) => Some(Type::Int),
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => return_type(then_body, qubits, sigs)
+                .or_else(|| return_type(else_body.as_deref().unwrap_or_default(), qubits, sigs)),
+            StmtKind::Block(body) | StmtKind::For { body, .. } => return_type(body, qubits, sigs),
+            _ => None,
+        };
+        ret = ret.or(found);
+    }
+    ret
+}
+
+type Env = HashMap<String, Value>;
+
+fn lower_function(func: &Function, sigs: &HashMap<String, Sig>) -> Result<IrFunction> {
+    let sig = &sigs[&func.name];
+    let mut b = Builder {
+        sigs,
+        types: Vec::new(),
+        blocks: Vec::new(),
+        current: None,
+        span: func.span,
+    };
+
+    let entry = b.new_block();
+    b.current = Some(entry);
+
+    let mut env = Env::new();
+    let mut params = Vec::new();
+    for (name, ty) in func.params.iter().zip(&sig.params) {
+        let value = b.value(*ty);
+        env.insert(name.clone(), value);
+        params.push(value);
+    }
+
+    b.lower_block(&func.body, &env)
+        .map_err(|e| e.context(format!("in function `{}`", func.name)))?;
+    if let Some(end) = b.current {
+        b.terminate(end, Terminator::Return(None));
+    }
+
+    let blocks = b
+        .blocks
+        .into_iter()
+        .map(|(insts, term)| Block {
+            insts,
+            term: term.unwrap_or(Terminator::Return(None)),
+        })
+        .collect();
+    Ok(IrFunction {
+        name: func.name.clone(),
+        params,
+        ret: sig.ret,
+        types: b.types,
+        blocks,
+        span: func.span,
+    })
+}
+
+struct Builder<'a> {
+    sigs: &'a HashMap<String, Sig>,
+    types: Vec<Type>,
+    blocks: Vec<(Vec<Inst>, Option<Terminator>)>,
+    // The block being appended to; `None` after a `return`, where the rest
+    // of the source block is unreachable.
+    current: Option<BlockId>,
+    // The statement being lowered.
+    span: Span,
+}
+
+impl Builder<'_> {
+    fn value(&mut self, ty: Type) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() - 1)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn terminate(&mut self, block: BlockId, term: Terminator) {
+        self.blocks[block.0].1 = Some(term);
+    }
+
+    fn push(&mut self, kind: InstKind, ty: Option<Type>) -> Option<Value> {
+        let result = ty.map(|ty| self.value(ty));
+        let block = self.current.expect("no block to append to");
+        self.blocks[block.0].0.push(Inst {
+            result,
+            kind,
+            span: self.span,
+        });
+        result
+    }
+
+    fn emit(&mut self, kind: InstKind, ty: Type) -> Value {
+        self.push(kind, Some(ty)).unwrap()
+    }
+
+    fn lower_block(&mut self, stmts: &[Stmt], env: &Env) -> Result<()> {
+        let mut env = env.clone();
+        for stmt in stmts {
+            if self.current.is_none() {
+                break;
+            }
+            self.span = stmt.span;
+            self.lower_stmt(stmt, &mut env)?;
+        }
+        Ok(())
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt, env: &mut Env) -> Result<()> {
+        match &stmt.kind {
+            StmtKind::Let { name, init } => {
+                let value = self.int(init, env)?;
+                env.insert(name.clone(), value);
+            }
+            StmtKind::QbitDecl { name, size } => {
+                let (size, ty) = match size {
+                    Some(size) => (Some(self.int(size, env)?), Type::QReg),
+                    None => (None, Type::Qubit),
+                };
+                let value = self.emit(InstKind::QAlloc { size }, ty);
+                env.insert(name.clone(), value);
+            }
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits,
+            } => {
+                let qubits = qubits
+                    .iter()
+                    .map(|q| self.qubit(q, env))
+                    .collect::<Result<_>>()?;
+                self.push(
+                    InstKind::Gate {
+                        gate: gate.clone(),
+                        params: params.clone(),
+                        qubits,
+                    },
+                    None,
+                );
+            }
+            StmtKind::Measure { target, classical } => {
+                let qubit = self.qubit(target, env)?;
+                let bit = self.emit(InstKind::Measure { qubit }, Type::Int);
+                if let Some(c) = classical {
+                    env.insert(c.clone(), bit);
+                }
+            }
+            StmtKind::Reset { target } => {
+                let qubit = self.qubit(target, env)?;
+                self.push(InstKind::Reset { qubit }, None);
+            }
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(e) => Some(self.expr(e, env)?),
+                    None => None,
+                };
+                let block = self.current.take().unwrap();
+                self.terminate(block, Terminator::Return(value));
+            }
+            StmtKind::Expr(Expr::Call { callee, args }) => {
+                self.call(callee, args, env)?;
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr, env)?;
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => self.lower_if(cond, then_body, else_body.as_deref(), env)?,
+            StmtKind::Block(body) => self.lower_block(body, env)?,
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => self.lower_for(var, start, end, body, env)?,
+        }
+        Ok(())
+    }
+
+    fn lower_if(
+        &mut self,
+        cond: &Expr,
+        then_body: &[Stmt],
+        else_body: Option<&[Stmt]>,
+        env: &Env,
+    ) -> Result<()> {
+        let cond = self.int(cond, env)?;
+        let from = self.current.unwrap();
+        let then_block = self.new_block();
+
+        let Some(else_body) = else_body else {
+            let join = self.new_block();
+            self.terminate(
+                from,
+                Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block: join,
+                },
+            );
+            self.current = Some(then_block);
+            self.lower_block(then_body, env)?;
+            if let Some(end) = self.current {
+                self.terminate(end, Terminator::Jump(join));
+            }
+            self.current = Some(join);
+            return Ok(());
+        };
+
+        let else_block = self.new_block();
+        self.terminate(
+            from,
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            },
+        );
+        self.current = Some(then_block);
+        self.lower_block(then_body, env)?;
+        let then_end = self.current;
+        self.current = Some(else_block);
+        self.lower_block(else_body, env)?;
+        let else_end = self.current;
+
+        // Both arms returned: nothing follows.
+        if then_end.is_none() && else_end.is_none() {
+            self.current = None;
+            return Ok(());
+        }
+        let join = self.new_block();
+        for end in [then_end, else_end].into_iter().flatten() {
+            self.terminate(end, Terminator::Jump(join));
+        }
+        self.current = Some(join);
+        Ok(())
+    }
+
+    //     jmp header
+    // header:
+    //     %i = phi [pre: %start], [latch: %next]
+    //     %c = lt %i, %end
+    //     br %c, body, exit
+    fn lower_for(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        body: &[Stmt],
+        env: &Env,
+    ) -> Result<()> {
+        let start = self.int(start, env)?;
+        let end = self.int(end, env)?;
+        let pre = self.current.unwrap();
+        let header = self.new_block();
+        let body_block = self.new_block();
+        let exit = self.new_block();
+        self.terminate(pre, Terminator::Jump(header));
+
+        self.current = Some(header);
+        let counter = self.emit(InstKind::Phi(vec![(pre, start)]), Type::Int);
+        let cond = self.emit(
+            InstKind::Binary {
+                op: BinOp::Lt,
+                lhs: counter,
+                rhs: end,
+            },
+            Type::Int,
+        );
+        self.terminate(
+            header,
+            Terminator::Branch {
+                cond,
+                then_block: body_block,
+                else_block: exit,
+            },
+        );
+
+        let mut body_env = env.clone();
+        body_env.insert(var.to_string(), counter);
+        self.current = Some(body_block);
+        self.lower_block(body, &body_env)?;
+
+        if let Some(latch) = self.current {
+            let one = self.emit(InstKind::Const(1), Type::Int);
+            let next = self.emit(
+                InstKind::Binary {
+                    op: BinOp::Add,
+                    lhs: counter,
+                    rhs: one,
+                },
+                Type::Int,
+            );
+            self.terminate(latch, Terminator::Jump(header));
+            if let InstKind::Phi(incoming) = &mut self.blocks[header.0].0[0].kind {
+                incoming.push((latch, next));
+            }
+        }
+        self.current = Some(exit);
+        Ok(())
+    }
+
+    fn qubit(&mut self, target: &QubitRef, env: &Env) -> Result<Value> {
+        let Some(&handle) = env.get(&target.name) else {
+            bail!("{}: qubit `{}` is not defined", self.span, target.name);
+        };
+        let ty = self.types[handle.0];
+        match &target.index {
+            None if ty == Type::Qubit => Ok(handle),
+            Some(index) if ty == Type::QReg => {
+                let index = self.int(index, env)?;
+                Ok(self.emit(InstKind::QIndex { reg: handle, index }, Type::Qubit))
+            }
+            _ => bail!(
+                "{}: `{}` is {}, which cannot be used as `{}`",
+                self.span,
+                target.name,
+                article(ty),
+                target
+            ),
+        }
+    }
+
+    fn int(&mut self, expr: &Expr, env: &Env) -> Result<Value> {
+        let value = self.expr(expr, env)?;
+        let ty = self.types[value.0];
+        if ty != Type::Int {
+            bail!(
+                "{}: expected an integer, found {} in `{}`",
+                self.span,
+                article(ty),
+                expr
+            );
+        }
+        Ok(value)
+    }
+
+    fn expr(&mut self, expr: &Expr, env: &Env) -> Result<Value> {
+        match expr {
+            Expr::Number(v) => Ok(self.emit(InstKind::Const(*v), Type::Int)),
+            Expr::Var(name) => match env.get(name) {
+                Some(&value) => Ok(value),
+                None => bail!("{}: `{}` is not defined", self.span, name),
+            },
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                let lhs = self.int(left, env)?;
+                let rhs = self.int(right, env)?;
+                Ok(self.emit(InstKind::Binary { op: *op, lhs, rhs }, Type::Int))
+            }
+            Expr::Call { callee, args } => match self.call(callee, args, env)? {
+                Some(value) => Ok(value),
+                None => bail!("{}: `{}` does not return a value", self.span, callee),
+            },
+        }
+    }
+
+    // Calls to functions outside the program are assumed to return an int.
+    fn call(&mut self, callee: &str, args: &[Expr], env: &Env) -> Result<Option<Value>> {
+        let args = args
+            .iter()
+            .map(|a| self.expr(a, env))
+            .collect::<Result<Vec<_>>>()?;
+        let ret = match self.sigs.get(callee) {
+            Some(sig) => sig.ret,
+            None => Some(Type::Int),
+        };
+        let kind = InstKind::Call {
+            callee: callee.to_string(),
+            args,
+        };
+        Ok(self.push(kind, ret))
+    }
+}
+
+fn article(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => "an integer",
+        Type::Qubit => "a qubit",
+        Type::QReg => "a qubit array",
+    }
+}
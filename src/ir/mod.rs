@@ -0,0 +1,281 @@
+use std::fmt;
+
+use crate::ast::{Angle, BinOp};
+use crate::lexer::Span;
+
+pub mod cfg;
+pub mod lower;
+pub mod opt;
+pub mod verify;
+
+pub use lower::lower_program;
+pub use opt::optimize_module;
+pub use verify::verify;
+
+// An SSA value, printed as `%n`. Each is defined exactly once, by a
+// function parameter or an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+// A basic block, printed as `bbn`. Block `bb0` is the entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    // A handle to one qubit.
+    Qubit,
+    // A handle to a qubit array, indexed with `qindex`.
+    QReg,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstKind {
+    Const(i64),
+    Binary { op: BinOp, lhs: Value, rhs: Value },
+    Call { callee: String, args: Vec<Value> },
+    // A fresh qubit in |0>, or an array of `size` of them.
+    QAlloc { size: Option<Value> },
+    QIndex { reg: Value, index: Value },
+    Gate {
+        gate: String,
+        params: Vec<Angle>,
+        qubits: Vec<Value>,
+    },
+    // Yields the classical outcome, 0 or 1.
+    Measure { qubit: Value },
+    Reset { qubit: Value },
+    // One incoming value per predecessor; only at the start of a block.
+    Phi(Vec<(BlockId, Value)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub result: Option<Value>,
+    pub kind: InstKind,
+    // The statement this was lowered from.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    // Takes `then_block` when `cond` is nonzero.
+    Branch {
+        cond: Value,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return(Option<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrFunction {
+    pub name: String,
+    pub params: Vec<Value>,
+    pub ret: Option<Type>,
+    // Type of every value, indexed by `Value`.
+    pub types: Vec<Type>,
+    // Indexed by `BlockId`.
+    pub blocks: Vec<Block>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub functions: Vec<IrFunction>,
+}
+
+impl InstKind {
+    // Calls `f` on every value the instruction reads.
+    pub fn for_each_operand(&self, mut f: impl FnMut(Value)) {
+        match self {
+            InstKind::Const(_) | InstKind::QAlloc { size: None } => {}
+            InstKind::Binary { lhs, rhs, .. } => {
+                f(*lhs);
+                f(*rhs);
+            }
+            InstKind::Call { args, .. } => args.iter().copied().for_each(f),
+            InstKind::QAlloc { size: Some(size) } => f(*size),
+            InstKind::QIndex { reg, index } => {
+                f(*reg);
+                f(*index);
+            }
+            InstKind::Gate { qubits, .. } => qubits.iter().copied().for_each(f),
+            InstKind::Measure { qubit } | InstKind::Reset { qubit } => f(*qubit),
+            InstKind::Phi(incoming) => incoming.iter().for_each(|(_, v)| f(*v)),
+        }
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+}
+
+impl IrFunction {
+    pub fn type_of(&self, value: Value) -> Type {
+        self.types[value.0]
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0]
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Int => "int",
+            Type::Qubit => "qubit",
+            Type::QReg => "qreg",
+        })
+    }
+}
+
+fn op_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Shl => "shl",
+        BinOp::Shr => "shr",
+        BinOp::Eq => "eq",
+        BinOp::Ne => "ne",
+        BinOp::Lt => "lt",
+        BinOp::Le => "le",
+        BinOp::Gt => "gt",
+        BinOp::Ge => "ge",
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for InstKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstKind::Const(v) => write!(f, "const {}", v),
+            InstKind::Binary { op, lhs, rhs } => write!(f, "{} {}, {}", op_name(*op), lhs, rhs),
+            InstKind::Call { callee, args } => write!(f, "call {}({})", callee, join(args)),
+            InstKind::QAlloc { size: None } => write!(f, "qalloc"),
+            InstKind::QAlloc { size: Some(size) } => write!(f, "qalloc {}", size),
+            InstKind::QIndex { reg, index } => write!(f, "qindex {}, {}", reg, index),
+            InstKind::Gate {
+                gate,
+                params,
+                qubits,
+            } => {
+                write!(f, "gate {}", gate)?;
+                if !params.is_empty() {
+                    let params: Vec<String> = params.iter().map(Angle::to_string).collect();
+                    write!(f, "({})", params.join(", "))?;
+                }
+                write!(f, " {}", join(qubits))
+            }
+            InstKind::Measure { qubit } => write!(f, "measure {}", qubit),
+            InstKind::Reset { qubit } => write!(f, "reset {}", qubit),
+            InstKind::Phi(incoming) => {
+                let arms: Vec<String> = incoming
+                    .iter()
+                    .map(|(block, value)| format!("[{}: {}]", block, value))
+                    .collect();
+                write!(f, "phi {}", arms.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jmp {}", target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => write!(f, "br {}, {}, {}", cond, then_block, else_block),
+            Terminator::Return(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Return(None) => write!(f, "ret"),
+        }
+    }
+}
+
+// fn main(%0: int) -> int {
+// bb0:
+//     %1: int = const 1
+//     %2: int = add %0, %1
+//     ret %2
+// }
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", p, self.type_of(*p)))
+            .collect();
+        write!(f, "fn {}({})", self.name, params.join(", "))?;
+        if let Some(ret) = self.ret {
+            write!(f, " -> {}", ret)?;
+        }
+        writeln!(f, " {{")?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i))?;
+            for inst in &block.insts {
+                match inst.result {
+                    Some(v) => writeln!(f, "    {}: {} = {}", v, self.type_of(v), inst.kind)?,
+                    None => writeln!(f, "    {}", inst.kind)?,
+                }
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
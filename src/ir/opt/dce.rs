@@ -0,0 +1,53 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{InstKind, IrFunction, Terminator, Value};
+
+use super::has_effects;
+
+// Removes instructions whose results are never needed. Quantum operations
+// and calls are always kept, whether or not anything reads their result;
+// everything they, branches and returns read is live, and so on
+// transitively, which also catches dead cycles through phis.
+pub fn run(func: &mut IrFunction) -> bool {
+    let mut live: HashSet<Value> = HashSet::new();
+    let mut work: Vec<Value> = Vec::new();
+
+    for block in &func.blocks {
+        for inst in &block.insts {
+            if has_effects(&inst.kind) {
+                inst.kind.for_each_operand(|v| work.push(v));
+                work.extend(inst.result);
+            }
+        }
+        match &block.term {
+            Terminator::Branch { cond, .. } => work.push(*cond),
+            Terminator::Return(Some(v)) => work.push(*v),
+            Terminator::Jump(_) | Terminator::Return(None) => {}
+        }
+    }
+
+    let defs: HashMap<Value, &InstKind> = func
+        .blocks
+        .iter()
+        .flat_map(|b| &b.insts)
+        .filter_map(|inst| Some((inst.result?, &inst.kind)))
+        .collect();
+    while let Some(v) = work.pop() {
+        if !live.insert(v) {
+            continue;
+        }
+        if let Some(kind) = defs.get(&v) {
+            kind.for_each_operand(|operand| work.push(operand));
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let before = block.insts.len();
+        block.insts.retain(|inst| {
+            has_effects(&inst.kind) || inst.result.is_some_and(|r| live.contains(&r))
+        });
+        changed |= block.insts.len() != before;
+    }
+    changed
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::ast::BinOp;
+use crate::ir::cfg::Dominators;
+use crate::ir::{BlockId, InstKind, IrFunction, Value};
+
+use super::{for_each_operand_mut, has_effects, replace_uses};
+
+// Global value numbering over the dominator tree: a pure instruction that
+// computes the same thing as one in a dominating position is replaced by
+// it. Quantum operations and calls are never merged, so two measurements of
+// the same qubit stay two measurements.
+pub fn run(func: &mut IrFunction) -> bool {
+    let doms = Dominators::new(func);
+    let mut children = vec![Vec::new(); func.blocks.len()];
+    for b in 1..func.blocks.len() {
+        if let Some(parent) = doms.idom(BlockId(b)) {
+            children[parent.0].push(BlockId(b));
+        }
+    }
+
+    let mut gvn = Gvn {
+        func,
+        children: &children,
+        same: HashMap::new(),
+    };
+    gvn.visit(BlockId(0), HashMap::new());
+
+    let same = gvn.same;
+    if same.is_empty() {
+        return false;
+    }
+    for block in &mut func.blocks {
+        block
+            .insts
+            .retain(|inst| !inst.result.is_some_and(|r| same.contains_key(&r)));
+    }
+    replace_uses(func, &same);
+    true
+}
+
+struct Gvn<'a> {
+    func: &'a IrFunction,
+    children: &'a [Vec<BlockId>],
+    // Redundant value to the one that replaces it.
+    same: HashMap<Value, Value>,
+}
+
+impl Gvn<'_> {
+    // `table` maps the key of each instruction available here to its value.
+    fn visit(&mut self, block: BlockId, mut table: HashMap<String, Value>) {
+        for inst in &self.func.block(block).insts {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            if has_effects(&inst.kind) {
+                continue;
+            }
+            let key = self.key(block, &inst.kind);
+            match table.get(&key) {
+                Some(&existing) => {
+                    self.same.insert(result, existing);
+                }
+                None => {
+                    table.insert(key, result);
+                }
+            }
+        }
+        for &child in &self.children[block.0] {
+            self.visit(child, table.clone());
+        }
+    }
+
+    // The instruction text with operands already numbered, and commutative
+    // operands in a fixed order.
+    fn key(&self, block: BlockId, kind: &InstKind) -> String {
+        let mut kind = kind.clone();
+        for_each_operand_mut(&mut kind, |v| {
+            while let Some(&next) = self.same.get(v) {
+                *v = next;
+            }
+        });
+        if let InstKind::Binary { op, lhs, rhs } = &mut kind {
+            if is_commutative(*op) && *lhs > *rhs {
+                std::mem::swap(lhs, rhs);
+            }
+        }
+        // Phis only agree within one block.
+        if let InstKind::Phi(incoming) = &mut kind {
+            incoming.sort();
+            return format!("{} in {}", kind, block);
+        }
+        kind.to_string()
+    }
+}
+
+fn is_commutative(op: BinOp) -> bool {
+    matches!(op, BinOp::Add | BinOp::Mul | BinOp::Eq | BinOp::Ne)
+}
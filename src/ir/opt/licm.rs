@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::BinOp;
+use crate::ir::cfg::{predecessors, Dominators};
+use crate::ir::{BlockId, InstKind, IrFunction, Terminator, Value};
+
+use super::has_effects;
+
+// Hoists instructions whose operands do not change inside a loop into the
+// block that jumps to the loop header. Only constants, arithmetic and qubit
+// indexing move, and quantum operations must run once per iteration.
+// Arithmetic that can trap and indexing, which is bounds-checked, only move
+// when they would have run anyway: the loop is known to be entered, and they
+// come first in the block it is entered through.
+pub fn run(func: &mut IrFunction) -> bool {
+    let mut changed = false;
+    for (header, body) in loops(func) {
+        let Some(preheader) = preheader(func, header, &body) else {
+            continue;
+        };
+        let first = entered(func, header, preheader);
+
+        // Values defined inside the loop, shrinking as instructions move.
+        let mut inside: HashSet<Value> = body
+            .iter()
+            .flat_map(|b| &func.block(*b).insts)
+            .filter_map(|inst| inst.result)
+            .collect();
+
+        loop {
+            let mut hoisted = None;
+            'find: for &b in &body {
+                for (i, inst) in func.block(b).insts.iter().enumerate() {
+                    if !can_hoist(&inst.kind) {
+                        continue;
+                    }
+                    let insts = &func.block(b).insts;
+                    if may_fail(&inst.kind)
+                        && (first != Some(b) || insts[..i].iter().any(|x| has_effects(&x.kind)))
+                    {
+                        continue;
+                    }
+                    let mut invariant = true;
+                    inst.kind
+                        .for_each_operand(|v| invariant &= !inside.contains(&v));
+                    if invariant {
+                        hoisted = Some((b, i));
+                        break 'find;
+                    }
+                }
+            }
+            let Some((b, i)) = hoisted else {
+                break;
+            };
+            let inst = func.blocks[b.0].insts.remove(i);
+            inside.remove(&inst.result.unwrap());
+            func.blocks[preheader.0].insts.push(inst);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn can_hoist(kind: &InstKind) -> bool {
+    matches!(
+        kind,
+        InstKind::Const(_) | InstKind::Binary { .. } | InstKind::QIndex { .. }
+    )
+}
+
+fn may_fail(kind: &InstKind) -> bool {
+    match kind {
+        InstKind::QIndex { .. } => true,
+        InstKind::Binary { op, .. } => !matches!(
+            op,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        ),
+        _ => false,
+    }
+}
+
+// The block the loop body is entered through, when the header is a counted
+// `i < end` test that passes the first time: the counter starts at a
+// constant below a constant end.
+fn entered(func: &IrFunction, header: BlockId, preheader: BlockId) -> Option<BlockId> {
+    let Terminator::Branch {
+        cond, then_block, ..
+    } = func.block(header).term
+    else {
+        return None;
+    };
+    let defs: HashMap<Value, &InstKind> = func
+        .blocks
+        .iter()
+        .flat_map(|b| &b.insts)
+        .filter_map(|inst| Some((inst.result?, &inst.kind)))
+        .collect();
+    let constant = |v: &Value| match defs.get(v) {
+        Some(InstKind::Const(n)) => Some(*n),
+        _ => None,
+    };
+
+    let Some(InstKind::Binary {
+        op: BinOp::Lt,
+        lhs,
+        rhs,
+    }) = defs.get(&cond)
+    else {
+        return None;
+    };
+    let Some(InstKind::Phi(incoming)) = defs.get(lhs) else {
+        return None;
+    };
+    let start = incoming
+        .iter()
+        .find(|(from, _)| *from == preheader)
+        .and_then(|(_, v)| constant(v))?;
+    (start < constant(rhs)?).then_some(then_block)
+}
+
+// Natural loops: for each back edge to a header that dominates its source,
+// the header and every block that reaches the source without passing
+// through the header.
+fn loops(func: &IrFunction) -> Vec<(BlockId, Vec<BlockId>)> {
+    let doms = Dominators::new(func);
+    let preds = predecessors(func);
+    let mut loops = Vec::new();
+
+    for (b, block) in func.blocks.iter().enumerate() {
+        for header in block.term.successors() {
+            let latch = BlockId(b);
+            if !doms.dominates(header, latch) {
+                continue;
+            }
+            let mut body = vec![header];
+            let mut work = vec![latch];
+            while let Some(block) = work.pop() {
+                if body.contains(&block) {
+                    continue;
+                }
+                body.push(block);
+                work.extend(preds[block.0].iter().copied());
+            }
+            body.sort();
+            loops.push((header, body));
+        }
+    }
+    loops
+}
+
+// The single block outside the loop that enters it, if it does nothing but
+// jump to the header.
+fn preheader(func: &IrFunction, header: BlockId, body: &[BlockId]) -> Option<BlockId> {
+    let outside: Vec<BlockId> = predecessors(func)[header.0]
+        .iter()
+        .copied()
+        .filter(|p| !body.contains(p))
+        .collect();
+    match outside[..] {
+        [pre] if func.block(pre).term == Terminator::Jump(header) => Some(pre),
+        _ => None,
+    }
+}
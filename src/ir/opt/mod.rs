@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use super::cfg::{predecessors, Dominators};
+use super::{BlockId, InstKind, IrFunction, Module, Terminator, Value};
+
+pub mod dce;
+pub mod gvn;
+pub mod licm;
+pub mod propagate;
+
+// Runs the scalar passes over every function until none of them changes
+// anything.
+pub fn optimize_module(module: &mut Module) {
+    for func in &mut module.functions {
+        optimize(func);
+    }
+}
+
+pub fn optimize(func: &mut IrFunction) {
+    // Each round strictly shrinks or hoists something, so this is only a
+    // guard against a pass that keeps undoing another.
+    for _ in 0..32 {
+        let mut changed = propagate::run(func);
+        changed |= gvn::run(func);
+        changed |= licm::run(func);
+        changed |= dce::run(func);
+        if !changed {
+            break;
+        }
+    }
+}
+
+// Instructions that must stay even when nothing reads their result, and
+// must not be moved or merged: anything touching qubits, and calls, which
+// may do so.
+pub fn has_effects(kind: &InstKind) -> bool {
+    matches!(
+        kind,
+        InstKind::Call { .. }
+            | InstKind::QAlloc { .. }
+            | InstKind::Gate { .. }
+            | InstKind::Measure { .. }
+            | InstKind::Reset { .. }
+    )
+}
+
+// Rewrites every use of a key in `map` to its value, following chains.
+pub fn replace_uses(func: &mut IrFunction, map: &HashMap<Value, Value>) {
+    if map.is_empty() {
+        return;
+    }
+    let resolve = |mut v: Value| {
+        while let Some(&next) = map.get(&v) {
+            v = next;
+        }
+        v
+    };
+
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            for_each_operand_mut(&mut inst.kind, |v| *v = resolve(*v));
+        }
+        match &mut block.term {
+            Terminator::Branch { cond, .. } => *cond = resolve(*cond),
+            Terminator::Return(Some(v)) => *v = resolve(*v),
+            Terminator::Jump(_) | Terminator::Return(None) => {}
+        }
+    }
+}
+
+pub fn for_each_operand_mut(kind: &mut InstKind, mut f: impl FnMut(&mut Value)) {
+    match kind {
+        InstKind::Const(_) | InstKind::QAlloc { size: None } => {}
+        InstKind::Binary { lhs, rhs, .. } => {
+            f(lhs);
+            f(rhs);
+        }
+        InstKind::Call { args, .. } => args.iter_mut().for_each(f),
+        InstKind::QAlloc { size: Some(size) } => f(size),
+        InstKind::QIndex { reg, index } => {
+            f(reg);
+            f(index);
+        }
+        InstKind::Gate { qubits, .. } => qubits.iter_mut().for_each(f),
+        InstKind::Measure { qubit } | InstKind::Reset { qubit } => f(qubit),
+        InstKind::Phi(incoming) => incoming.iter_mut().for_each(|(_, v)| f(v)),
+    }
+}
+
+// Drops blocks the entry cannot reach, along with the phi inputs that came
+// from them, and renumbers the rest in order.
+pub fn remove_unreachable(func: &mut IrFunction) -> bool {
+    let doms = Dominators::new(func);
+    let keep: Vec<bool> = (0..func.blocks.len())
+        .map(|b| doms.is_reachable(BlockId(b)))
+        .collect();
+    if keep.iter().all(|k| *k) {
+        return false;
+    }
+
+    let mut renumber = HashMap::new();
+    for (old, _) in keep.iter().enumerate().filter(|(_, k)| **k) {
+        renumber.insert(BlockId(old), BlockId(renumber.len()));
+    }
+
+    let blocks = std::mem::take(&mut func.blocks);
+    for (block, _) in blocks.into_iter().zip(&keep).filter(|(_, k)| **k) {
+        let mut block = block;
+        for inst in &mut block.insts {
+            if let InstKind::Phi(incoming) = &mut inst.kind {
+                incoming.retain(|(pred, _)| renumber.contains_key(pred));
+                for (pred, _) in incoming.iter_mut() {
+                    *pred = renumber[pred];
+                }
+            }
+        }
+        match &mut block.term {
+            Terminator::Jump(target) => *target = renumber[target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => {
+                *then_block = renumber[then_block];
+                *else_block = renumber[else_block];
+            }
+            Terminator::Return(_) => {}
+        }
+        func.blocks.push(block);
+    }
+    true
+}
+
+// Drops the phi inputs of `to` that came from `from`, once that edge is
+// gone.
+pub fn remove_edge(func: &mut IrFunction, from: BlockId, to: BlockId) {
+    if predecessors(func)[to.0].contains(&from) {
+        return;
+    }
+    for inst in &mut func.blocks[to.0].insts {
+        if let InstKind::Phi(incoming) = &mut inst.kind {
+            incoming.retain(|(pred, _)| *pred != from);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::BinOp;
+use crate::consteval::{eval_binop, ArithMode};
+use crate::ir::{BlockId, InstKind, IrFunction, Terminator, Value};
+
+use super::{remove_edge, remove_unreachable, replace_uses};
+
+// Constant and copy propagation. Operations on constants are folded, unless
+// they would fail at runtime; `x + 0`, `x * 1` and phis whose inputs are all
+// the same value become copies of that value; branches on a constant become
+// jumps, and the blocks that leaves unreachable are dropped.
+pub fn run(func: &mut IrFunction) -> bool {
+    let mut changed = false;
+    loop {
+        let consts: HashMap<Value, i64> = func
+            .blocks
+            .iter()
+            .flat_map(|b| &b.insts)
+            .filter_map(|inst| match inst.kind {
+                InstKind::Const(v) => Some((inst.result?, v)),
+                _ => None,
+            })
+            .collect();
+
+        let mut round = false;
+        let mut copies = HashMap::new();
+        for block in &mut func.blocks {
+            for inst in &mut block.insts {
+                let Some(result) = inst.result else {
+                    continue;
+                };
+                match &inst.kind {
+                    InstKind::Binary { op, lhs, rhs } => {
+                        let (a, b) = (consts.get(lhs).copied(), consts.get(rhs).copied());
+                        if let (Some(a), Some(b)) = (a, b) {
+                            if let Ok(v) = eval_binop(*op, a, b, ArithMode::Checked) {
+                                inst.kind = InstKind::Const(v);
+                                round = true;
+                                continue;
+                            }
+                        }
+                        if let Some(same) = identity(*op, *lhs, *rhs, a, b) {
+                            copies.insert(result, same);
+                        }
+                    }
+                    InstKind::Phi(incoming) => {
+                        let mut inputs: Vec<Value> = incoming
+                            .iter()
+                            .map(|(_, v)| *v)
+                            .filter(|v| *v != result)
+                            .collect();
+                        inputs.sort();
+                        inputs.dedup();
+                        let values: HashSet<Option<i64>> =
+                            inputs.iter().map(|v| consts.get(v).copied()).collect();
+
+                        if let [only] = inputs[..] {
+                            copies.insert(result, only);
+                        } else if let [Some(v)] = values.into_iter().collect::<Vec<_>>()[..] {
+                            inst.kind = InstKind::Const(v);
+                            round = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if !copies.is_empty() {
+            for block in &mut func.blocks {
+                block
+                    .insts
+                    .retain(|inst| !inst.result.is_some_and(|r| copies.contains_key(&r)));
+            }
+            replace_uses(func, &copies);
+            round = true;
+        }
+
+        let mut folded = Vec::new();
+        for (b, block) in func.blocks.iter_mut().enumerate() {
+            let Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } = block.term
+            else {
+                continue;
+            };
+            let Some(&v) = consts.get(&cond) else {
+                continue;
+            };
+            let (taken, dropped) = if v != 0 {
+                (then_block, else_block)
+            } else {
+                (else_block, then_block)
+            };
+            block.term = Terminator::Jump(taken);
+            folded.push((b, dropped));
+        }
+        for (b, dropped) in &folded {
+            remove_edge(func, BlockId(*b), *dropped);
+        }
+        if !folded.is_empty() {
+            remove_unreachable(func);
+            round = true;
+        }
+
+        // Phis folded to constants go after the remaining phis.
+        for block in &mut func.blocks {
+            block
+                .insts
+                .sort_by_key(|inst| !matches!(inst.kind, InstKind::Phi(_)));
+        }
+
+        if !round {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+// The operand `op` leaves unchanged, if the other is its identity element.
+fn identity(op: BinOp, lhs: Value, rhs: Value, a: Option<i64>, b: Option<i64>) -> Option<Value> {
+    match (op, a, b) {
+        (BinOp::Add, Some(0), _) | (BinOp::Mul, Some(1), _) => Some(rhs),
+        (BinOp::Add | BinOp::Sub | BinOp::Shl | BinOp::Shr, _, Some(0))
+        | (BinOp::Mul | BinOp::Div, _, Some(1)) => Some(lhs),
+        _ => None,
+    }
+}
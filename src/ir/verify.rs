@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use super::cfg::{predecessors, Dominators};
+use super::{BlockId, InstKind, IrFunction, Module, Terminator, Type, Value};
+
+// Checks the invariants the rest of the IR code relies on: every value is
+// defined once and before its uses, phis sit at the start of a block with
+// one incoming value per predecessor, and operands have the right types.
+pub fn verify(module: &Module) -> Result<()> {
+    let sigs: HashMap<&str, &IrFunction> = module
+        .functions
+        .iter()
+        .map(|f| (f.name.as_str(), f))
+        .collect();
+    for func in &module.functions {
+        verify_function(func, &sigs)?;
+    }
+    Ok(())
+}
+
+// Where a value is defined: its block and instruction index, or no index for
+// a parameter.
+type Def = (BlockId, Option<usize>);
+
+fn verify_function(func: &IrFunction, sigs: &HashMap<&str, &IrFunction>) -> Result<()> {
+    let name = &func.name;
+    let ty = |value: Value| -> Result<Type> {
+        match func.types.get(value.0) {
+            Some(ty) => Ok(*ty),
+            None => bail!("`{}`: {} has no type", name, value),
+        }
+    };
+
+    if func.blocks.is_empty() {
+        bail!("`{}` has no entry block", name);
+    }
+    for (i, block) in func.blocks.iter().enumerate() {
+        for succ in block.term.successors() {
+            if succ.0 >= func.blocks.len() {
+                bail!("`{}`: {} jumps to missing block {}", name, BlockId(i), succ);
+            }
+            if succ.0 == 0 {
+                bail!("`{}`: {} jumps back to the entry block", name, BlockId(i));
+            }
+        }
+    }
+
+    let mut defs: HashMap<Value, Def> = HashMap::new();
+    let mut define = |value: Value, def: Def| -> Result<()> {
+        ty(value)?;
+        if defs.insert(value, def).is_some() {
+            bail!("`{}`: {} is defined more than once", name, value);
+        }
+        Ok(())
+    };
+    for &param in &func.params {
+        define(param, (BlockId(0), None))?;
+    }
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, inst) in block.insts.iter().enumerate() {
+            if let Some(result) = inst.result {
+                define(result, (BlockId(b), Some(i)))?;
+            }
+        }
+    }
+
+    let preds = predecessors(func);
+    let doms = Dominators::new(func);
+    let available = |value: Value, block: BlockId, at: usize| -> Result<()> {
+        let Some(&(def_block, def_at)) = defs.get(&value) else {
+            bail!(
+                "`{}`: {} uses {}, which is never defined",
+                name,
+                block,
+                value
+            );
+        };
+        let ok = if def_block == block {
+            def_at.is_none_or(|d| d < at)
+        } else {
+            doms.dominates(def_block, block)
+        };
+        if !ok {
+            bail!("`{}`: {} uses {} before it is defined", name, block, value);
+        }
+        Ok(())
+    };
+    let expect = |value: Value, want: Type, what: &str| -> Result<()> {
+        let found = ty(value)?;
+        if found != want {
+            bail!(
+                "`{}`: {} of {} is {}, expected {}",
+                name,
+                what,
+                value,
+                found,
+                want
+            );
+        }
+        Ok(())
+    };
+
+    for (b, block) in func.blocks.iter().enumerate() {
+        let id = BlockId(b);
+        if !doms.is_reachable(id) {
+            continue;
+        }
+        let mut in_phis = true;
+
+        for (i, inst) in block.insts.iter().enumerate() {
+            let what = format!("`{}` in {}", inst.kind, id);
+            if let InstKind::Phi(incoming) = &inst.kind {
+                if !in_phis {
+                    bail!("`{}`: {} has a phi after other instructions", name, id);
+                }
+                let mut from: Vec<BlockId> = incoming.iter().map(|(b, _)| *b).collect();
+                from.sort();
+                let mut expected = preds[b].clone();
+                expected.sort();
+                if from != expected {
+                    bail!(
+                        "`{}`: {} does not have one value per predecessor",
+                        name,
+                        what
+                    );
+                }
+                let result_ty = ty(inst.result.unwrap())?;
+                for (pred, value) in incoming {
+                    // Read at the end of the predecessor.
+                    let end = func.block(*pred).insts.len();
+                    available(*value, *pred, end)?;
+                    expect(*value, result_ty, &what)?;
+                }
+                continue;
+            }
+            in_phis = false;
+
+            let mut operands = Vec::new();
+            inst.kind.for_each_operand(|v| operands.push(v));
+            for value in operands {
+                available(value, id, i)?;
+            }
+
+            let result_ty = inst.result.map(ty).transpose()?;
+            match &inst.kind {
+                InstKind::Const(_) => {}
+                InstKind::Binary { lhs, rhs, .. } => {
+                    expect(*lhs, Type::Int, &what)?;
+                    expect(*rhs, Type::Int, &what)?;
+                }
+                InstKind::Call { callee, args } => {
+                    if let Some(sig) = sigs.get(callee.as_str()) {
+                        if sig.params.len() != args.len() {
+                            bail!("`{}`: {} has the wrong number of arguments", name, what);
+                        }
+                        for (arg, param) in args.iter().zip(&sig.params) {
+                            expect(*arg, sig.type_of(*param), &what)?;
+                        }
+                        if result_ty != sig.ret {
+                            bail!("`{}`: result of {} does not match `{}`", name, what, callee);
+                        }
+                    }
+                }
+                InstKind::QAlloc { size } => {
+                    if let Some(size) = size {
+                        expect(*size, Type::Int, &what)?;
+                    }
+                }
+                InstKind::QIndex { reg, index } => {
+                    expect(*reg, Type::QReg, &what)?;
+                    expect(*index, Type::Int, &what)?;
+                }
+                InstKind::Gate { qubits, .. } => {
+                    for qubit in qubits {
+                        expect(*qubit, Type::Qubit, &what)?;
+                    }
+                }
+                InstKind::Measure { qubit }
+                | InstKind::Reset { qubit } => expect(*qubit, Type::Qubit, &what)?,
+                InstKind::Phi(_) => {}
+            }
+
+            let expected = match &inst.kind {
+                InstKind::Gate { .. } | InstKind::Reset { .. } => None,
+                InstKind::Call { .. } => result_ty,
+                InstKind::QAlloc { size: None } | InstKind::QIndex { .. } => Some(Type::Qubit),
+                InstKind::QAlloc { size: Some(_) } => Some(Type::QReg),
+                _ => Some(Type::Int),
+            };
+            if result_ty != expected {
+                bail!("`{}`: {} has the wrong result type", name, what);
+            }
+        }
+
+        let end = block.insts.len();
+        match &block.term {
+            Terminator::Branch { cond, .. } => {
+                available(*cond, id, end)?;
+                expect(*cond, Type::Int, &format!("the branch in {}", id))?;
+            }
+            Terminator::Return(Some(value)) => {
+                available(*value, id, end)?;
+                if func.ret != Some(ty(*value)?) {
+                    bail!(
+                        "`{}`: {} returns {}, which does not match its type",
+                        name,
+                        id,
+                        value
+                    );
+                }
+            }
+            Terminator::Jump(_) | Terminator::Return(None) => {}
+        }
+    }
+    Ok(())
+}
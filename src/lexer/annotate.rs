@@ -1,19 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Annotation {
-    PartialEval,
+    // `#[pe]`, or `#[pe(fuel = N)]` to give evaluation its own step budget.
+    PartialEval { fuel: Option<u64> },
     NoPartialEval,
     Static,
     Dynamic,
+    Inline,
+    InlineNever,
+    Allow(Vec<String>),
+    Warn(Vec<String>),
+    Deny(Vec<String>),
 }
 
-impl Annotation {
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s {
-            "pe" => Some(Self::PartialEval),
+impl FromStr for Annotation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let annotation = match s {
+            "pe" => Some(Self::PartialEval { fuel: None }),
             "nope" => Some(Self::NoPartialEval),
             "static" => Some(Self::Static),
             "dynamic" => Some(Self::Dynamic),
+            "inline" => Some(Self::Inline),
+            _ if s.replace(' ', "") == "inline(never)" => Some(Self::InlineNever),
+            _ if s.starts_with("pe") => Self::pe_fuel(s),
+            _ => Self::lint_level(s),
+        };
+        match annotation {
+            Some(annotation) => Ok(annotation),
+            None => bail!("unknown attribute #[{}]", s),
+        }
+    }
+}
+
+impl Annotation {
+    fn pe_fuel(s: &str) -> Option<Self> {
+        let compact = s.replace(' ', "");
+        let n = compact.strip_prefix("pe(fuel=")?.strip_suffix(')')?;
+        Some(Self::PartialEval {
+            fuel: Some(n.parse().ok()?),
+        })
+    }
+
+    // `allow(a, b)`, `warn(a)` and `deny(a)` carry a list of lint names.
+    fn lint_level(s: &str) -> Option<Self> {
+        let open = s.find('(')?;
+        let inner = s[open + 1..].trim_end().strip_suffix(')')?;
+        let names: Vec<String> = inner
+            .split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+
+        match s[..open].trim() {
+            "allow" => Some(Self::Allow(names)),
+            "warn" => Some(Self::Warn(names)),
+            "deny" => Some(Self::Deny(names)),
+            _ => None,
+        }
+    }
+
+    // Whether this annotation switches partial evaluation on or off.
+    pub fn pe_toggle(&self) -> Option<bool> {
+        match self {
+            Self::PartialEval { .. } | Self::Static => Some(true),
+            Self::NoPartialEval | Self::Dynamic => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        match self {
+            Self::PartialEval { fuel } => *fuel,
             _ => None,
         }
     }
+
+    // `#[static]` and `#[dynamic]` also seed binding-time analysis.
+    pub fn is_binding_time(&self) -> bool {
+        matches!(self, Self::Static | Self::Dynamic)
+    }
+
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Self::PartialEval { .. } => "pe",
+            Self::NoPartialEval => "nope",
+            Self::Static => "static",
+            Self::Dynamic => "dynamic",
+            Self::Inline | Self::InlineNever => "inline",
+            Self::Allow(_) => "allow",
+            Self::Warn(_) => "warn",
+            Self::Deny(_) => "deny",
+        }
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow(names) | Self::Warn(names) | Self::Deny(names) => {
+                write!(f, "{}({})", self.keyword(), names.join(", "))
+            }
+            Self::InlineNever => write!(f, "inline(never)"),
+            Self::PartialEval { fuel: Some(n) } => write!(f, "pe(fuel = {})", n),
+            _ => write!(f, "{}", self.keyword()),
+        }
+    }
 }
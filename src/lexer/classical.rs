@@ -18,8 +18,14 @@ pub fn lex_identifier(lex: &mut Lexer, first: char) -> Token {
         "return" => Token::Return,
         "qbit" => Token::Qbit,
         "measure" => Token::Measure,
+        "reset" => Token::Reset,
+        "if" => Token::If,
+        "else" => Token::Else,
+        "for" => Token::For,
+        "in" => Token::In,
 
-        "H" | "X" | "Y" | "Z" | "CX" | "CNOT" | "CCX" => Token::Gate(s),
+        "H" | "X" | "Y" | "Z" | "S" | "Sdg" | "T" | "Tdg" | "SX" | "SXdg" | "RX" | "RY" | "RZ"
+        | "U3" | "CX" | "CNOT" | "CZ" | "CCX" | "MCX" | "SWAP" => Token::Gate(s),
 
         _ => Token::Ident(s),
     }
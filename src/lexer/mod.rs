@@ -3,14 +3,15 @@ pub mod classical;
 pub mod quantum;
 pub mod annotate;
 
-pub use token::Token;
+pub use token::{Span, Token};
 
 pub struct Lexer {
     src: Vec<char>,
     pos: usize,
+    line: usize,
+    col: usize,
     pub pe_enabled: bool,
-    gate_buffer: Vec<Token>,
-    unread: Option<Token>,
+    unread: Vec<(Token, Span)>,
 }
 
 impl Lexer {
@@ -18,9 +19,10 @@ impl Lexer {
         Self {
             src: input.chars().collect(),
             pos: 0,
+            line: 1,
+            col: 1,
             pe_enabled: true,
-            gate_buffer: Vec::new(),
-            unread: None,
+            unread: Vec::new(),
         }
     }
 
@@ -29,40 +31,67 @@ impl Lexer {
         self.src.get(self.pos).copied()
     }
 
+    pub fn peek_second(&self) -> Option<char> {
+        self.src.get(self.pos + 1).copied()
+    }
+
     pub fn next_char(&mut self) -> Option<char> {
         let ch = self.peek()?;
         self.pos += 1;
+        if ch == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
         Some(ch)
     }
 
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    // Skips whitespace and `//` line comments.
     fn skip_ws(&mut self) {
         while let Some(c) = self.peek() {
             if c.is_whitespace() {
                 self.next_char();
+            } else if c == '/' && self.src.get(self.pos + 1) == Some(&'/') {
+                while let Some(c) = self.next_char() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
             } else {
                 break;
             }
         }
     }
 
-    fn unread_token(&mut self, t: Token) {
-        self.unread = Some(t);
+    // Tokens are pushed back in reverse order of reading, so the last one
+    // unread is the next one returned.
+    fn unread_token(&mut self, t: (Token, Span)) {
+        self.unread.push(t);
     }
 
    
-    fn next_raw_token(&mut self) -> Token {
-        if let Some(t) = self.unread.take() {
+    fn next_raw_token(&mut self) -> (Token, Span) {
+        if let Some(t) = self.unread.pop() {
             return t;
         }
 
         self.skip_ws();
 
+        let span = self.span();
         let c = match self.next_char() {
             Some(c) => c,
-            None => return Token::EOF,
+            None => return (Token::EOF, span),
         };
 
-        match c {
+        let tok = match c {
             // Attribute: #[pe], #[nope], #[static], #[dynamic]
             '#' => {
                 if self.peek() == Some('[') {
@@ -90,6 +119,8 @@ impl Lexer {
             ')' => Token::RParen,
             '{' => Token::LBrace,
             '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
             '+' => Token::Plus,
             '*' => Token::Star,
             '/' => Token::Slash,
@@ -105,13 +136,54 @@ impl Lexer {
                 }
             }
 
+            '<' => match self.peek() {
+                Some('<') => {
+                    self.next_char();
+                    Token::Shl
+                }
+                Some('=') => {
+                    self.next_char();
+                    Token::Le
+                }
+                _ => Token::Lt,
+            },
+
+            '>' => match self.peek() {
+                Some('>') => {
+                    self.next_char();
+                    Token::Shr
+                }
+                Some('=') => {
+                    self.next_char();
+                    Token::Ge
+                }
+                _ => Token::Gt,
+            },
+
+            '=' if self.peek() == Some('=') => {
+                self.next_char();
+                Token::EqEq
+            }
+
+            '!' if self.peek() == Some('=') => {
+                self.next_char();
+                Token::NotEq
+            }
+
             '=' => Token::Assign,
 
+            '.' if self.peek() == Some('.') => {
+                self.next_char();
+                Token::DotDot
+            }
+
             other => Token::Unknown(other.to_string()),
-        }
+        };
+
+        (tok, span)
     }
 
-    fn read_gate_call_or_gate(&mut self, gname: String) -> Token {
+    fn read_gate_call_or_gate(&mut self, gname: String, span: Span) -> (Token, Span) {
         let t1 = self.next_raw_token();
         let t2 = self.next_raw_token();
         let t3 = self.next_raw_token();
@@ -122,103 +194,44 @@ impl Lexer {
             Token::Ident(qname),
             Token::RParen,
             Token::Semicolon,
-        ) = (&t1, &t2, &t3, &t4)
+        ) = (&t1.0, &t2.0, &t3.0, &t4.0)
         {
-            Token::QOp {
+            let qop = Token::QOp {
                 gate: gname,
                 target: qname.clone(),
-            }
+            };
+            (qop, span)
         } else {
             self.unread_token(t4);
             self.unread_token(t3);
             self.unread_token(t2);
             self.unread_token(t1);
-            Token::Gate(gname)
-        }
-    }
-
-    fn next_raw_or_qop(&mut self) -> Token {
-        let t = self.next_raw_token();
-        if let Token::Gate(gname) = t {
-            self.read_gate_call_or_gate(gname)
-        } else {
-            t
+            (Token::Gate(gname), span)
         }
     }
 
-  
     pub fn next_token(&mut self) -> Token {
-        if !self.gate_buffer.is_empty() {
-            return self.gate_buffer.remove(0);
-        }
-
-        loop {
-            let t0 = self.next_raw_token();
-
-            match t0 {
-                Token::Attr(name) => {
-                    match name.as_str() {
-                        "pe" | "static" => self.pe_enabled = true,
-                        "nope" | "dynamic" => self.pe_enabled = false,
-                        _ => {}
-                    }
-                    continue;
-                }
+        self.next_spanned().0
+    }
 
-                Token::Gate(gname) => {
-                    let first = self.read_gate_call_or_gate(gname);
-
-                    if let Token::QOp { .. } = first {
-                        if self.pe_enabled {
-                            self.gate_buffer.push(first);
-                            quantum::quantum_reduce(&mut self.gate_buffer);
-
-                            loop {
-                                let t_next = self.next_raw_or_qop();
-
-                                match t_next {
-                                    Token::QOp { .. } if self.pe_enabled => {
-                                        self.gate_buffer.push(t_next);
-                                        quantum::quantum_reduce(&mut self.gate_buffer);
-                                    }
-
-                                    Token::Attr(name) => {
-                                        match name.as_str() {
-                                            "pe" | "static" => self.pe_enabled = true,
-                                            "nope" | "dynamic" => self.pe_enabled = false,
-                                            _ => {}
-                                        }
-                                        break;
-                                    }
-
-                                    other => {
-                                        if other != Token::EOF {
-                                            self.unread_token(other);
-                                        }
-                                        break;
-                                    }
-                                }
-                            }
-
-                          
-                            if !self.gate_buffer.is_empty() {
-                                return self.gate_buffer.remove(0);
-                            } else {
-                                // All gates cancelled out.
-                                continue;
-                            }
-                        } else {
-                            // PE disabled: emit QOp directly, no cancellation.
-                            return first;
-                        }
-                    } else {
-                        return first;
-                    }
+    // Like `next_token`, but also returns where the token starts.
+    pub fn next_spanned(&mut self) -> (Token, Span) {
+        let (t0, span) = self.next_raw_token();
+        match t0 {
+            // Attributes toggle PE here and are then handed on to the
+            // parser, which attaches them to the next item or statement.
+            Token::Attr(name) => {
+                let toggle = name
+                    .parse::<annotate::Annotation>()
+                    .ok()
+                    .and_then(|a| a.pe_toggle());
+                if let Some(on) = toggle {
+                    self.pe_enabled = on;
                 }
-
-               
-                other => return other,
+                (Token::Attr(name), span)
             }
+            Token::Gate(gname) => self.read_gate_call_or_gate(gname, span),
+            other => (other, span),
         }
     }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Ident(String),
@@ -8,6 +10,11 @@ pub enum Token {
     Return,
     Qbit,
     Measure,
+    Reset,
+    If,
+    Else,
+    For,
+    In,
 
     Gate(String),
     QOp { gate: String, target: String },
@@ -18,6 +25,8 @@ pub enum Token {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Plus,
     Minus,
     Star,
@@ -25,8 +34,31 @@ pub enum Token {
     Comma,
     Semicolon,
     Arrow,
+    DotDot,
+    Shl,
+    Shr,
+    EqEq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
     Assign,
 
     EOF,
     Unknown(String),
 }
+
+
+// Source position of a token: 1-based line and column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
@@ -1,2 +1,11 @@
+pub mod ast;
+pub mod bta;
+pub mod circuit;
+pub mod consteval;
+pub mod diag;
+pub mod inline;
+pub mod ir;
 pub mod lexer;
-pub mod parser;
\ No newline at end of file
+pub mod lint;
+pub mod parser;
+pub mod pe;
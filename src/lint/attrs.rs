@@ -0,0 +1,128 @@
+use crate::ast::{Attribute, Function, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+use crate::lexer::annotate::Annotation;
+
+use super::{lints_named, Lint, LintCx};
+
+// Checks the attributes themselves: `#[pe]`-style toggles that do not change
+// anything, `#[inline]` on statements, and lint names in `#[allow(...)]`
+// that do not exist.
+pub fn check(cx: &mut LintCx, func: &Function) {
+    check_lint_names(cx, &func.attrs);
+    check_overridden(cx, &func.attrs);
+
+    let mut pe = func.pe_enabled;
+    check_block(cx, &func.body, &mut pe);
+}
+
+fn check_block(cx: &mut LintCx, stmts: &[Stmt], pe: &mut bool) {
+    for stmt in stmts {
+        cx.push_attrs(&stmt.attrs);
+        check_lint_names(cx, &stmt.attrs);
+        check_overridden(cx, &stmt.attrs);
+        for attr in &stmt.attrs {
+            if matches!(
+                attr.annotation,
+                Annotation::Inline | Annotation::InlineNever
+            ) {
+                cx.emit(
+                    Lint::UnusedAttributes,
+                    attr.span,
+                    format!(
+                        "#[{}] has no effect on a statement; it only applies to functions",
+                        attr.annotation
+                    ),
+                );
+            }
+        }
+
+        // Inside a body a toggle holds until the next one, so restating the
+        // current mode is a no-op. `#[static]`/`#[dynamic]` also constrain
+        // binding times, and `#[pe(fuel = N)]` sets a budget, so they are
+        // never useless.
+        if let Some((attr, on)) = last_toggle(&stmt.attrs) {
+            let useful = attr.annotation.is_binding_time() || attr.annotation.fuel().is_some();
+            if on == *pe && !useful {
+                let mode = if on { "enabled" } else { "disabled" };
+                cx.emit(
+                    Lint::UnusedAttributes,
+                    attr.span,
+                    format!(
+                        "#[{}] has no effect: partial evaluation is already {}",
+                        attr.annotation.keyword(),
+                        mode
+                    ),
+                );
+            }
+            *pe = on;
+        }
+
+        // A toggle inside a nested block ends with that block.
+        match &stmt.kind {
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                check_block(cx, then_body, &mut pe.clone());
+                if let Some(body) = else_body {
+                    check_block(cx, body, &mut pe.clone());
+                }
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => {
+                check_block(cx, body, &mut pe.clone())
+            }
+            _ => {}
+        }
+        cx.pop_attrs();
+    }
+}
+
+fn last_toggle(attrs: &[Attribute]) -> Option<(&Attribute, bool)> {
+    attrs
+        .iter()
+        .rev()
+        .find_map(|a| a.annotation.pe_toggle().map(|on| (a, on)))
+}
+
+// A toggle followed by another toggle on the same item never takes effect.
+fn check_overridden(cx: &mut LintCx, attrs: &[Attribute]) {
+    let toggles: Vec<&Attribute> = attrs
+        .iter()
+        .filter(|a| a.annotation.pe_toggle().is_some())
+        .collect();
+
+    for pair in toggles.windows(2) {
+        if pair[0].annotation.is_binding_time() {
+            continue;
+        }
+        let diag = Diagnostic::warning(
+            format!(
+                "#[{}] has no effect: it is overridden by #[{}]",
+                pair[0].annotation.keyword(),
+                pair[1].annotation.keyword()
+            ),
+            pair[0].span,
+        )
+        .with_note(pair[1].span, "overridden here");
+        let level = cx.level(Lint::UnusedAttributes);
+        cx.emit_at(Lint::UnusedAttributes, level, diag);
+    }
+}
+
+fn check_lint_names(cx: &mut LintCx, attrs: &[Attribute]) {
+    for attr in attrs {
+        let names = match &attr.annotation {
+            Annotation::Allow(names) | Annotation::Warn(names) | Annotation::Deny(names) => names,
+            _ => continue,
+        };
+        for name in names {
+            if lints_named(name).is_none() {
+                cx.diags.push(Diagnostic::warning(
+                    format!("unknown lint `{}`", name),
+                    attr.span,
+                ));
+            }
+        }
+    }
+}
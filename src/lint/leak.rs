@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expr, Function, QubitRef, Stmt, StmtKind};
+use crate::circuit::gates::undoes;
+use crate::circuit::{Op, OpKind};
+use crate::diag::Diagnostic;
+use crate::lexer::Span;
+
+use super::{Level, Lint, LintCx};
+
+// Where a locally declared qubit is in its lifecycle.
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    // |0> after `qbit`, or collapsed by `measure`, or `reset`.
+    Clean,
+    // Gates applied since the last clean point, with the operand of this
+    // qubit they were applied to (`q` or `q[i]`). A gate that undoes the
+    // last one, on the same operands, pops it, so an uncomputed qubit ends
+    // up clean.
+    Dirty {
+        gates: Vec<(Op, String)>,
+        last: Span,
+    },
+    // Handed to a call; nothing is known about it afterwards.
+    Escaped {
+        last: Span,
+    },
+    // Branches disagree about the state.
+    Unknown {
+        last: Span,
+    },
+    Returned,
+}
+
+#[derive(Debug, Clone)]
+struct Qubit {
+    decl: Span,
+    level: Level,
+    state: State,
+}
+
+type Qubits = HashMap<String, Qubit>;
+
+// Forward dataflow over the body: warns when some exit can be reached while
+// a qubit declared in this function is neither clean nor returned. States
+// from the two arms of an `if` are joined where they meet again.
+pub fn check(cx: &mut LintCx, func: &Function) {
+    let mut leak = LeakCx {
+        cx,
+        func,
+        reported: HashSet::new(),
+    };
+
+    let mut qubits = Qubits::new();
+    if !leak.walk_block(&func.body, &mut qubits) {
+        leak.check_exit(&qubits, func.end_span);
+    }
+}
+
+struct LeakCx<'a, 'b> {
+    cx: &'a mut LintCx<'b>,
+    func: &'a Function,
+    // Declarations already warned about, so each qubit is reported once.
+    reported: HashSet<Span>,
+}
+
+impl LeakCx<'_, '_> {
+    // Returns whether every path through the block leaves the function.
+    fn walk_block(&mut self, stmts: &[Stmt], qubits: &mut Qubits) -> bool {
+        for stmt in stmts {
+            self.cx.push_attrs(&stmt.attrs);
+            let exited = self.step(stmt, qubits);
+            self.cx.pop_attrs();
+            if exited {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn step(&mut self, stmt: &Stmt, qubits: &mut Qubits) -> bool {
+        match &stmt.kind {
+            // An array is tracked as a whole.
+            StmtKind::QbitDecl { name, .. } => {
+                let qubit = Qubit {
+                    decl: stmt.span,
+                    level: self.cx.level(Lint::QubitLeak),
+                    state: State::Clean,
+                };
+                qubits.insert(name.clone(), qubit);
+            }
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits: operands,
+            } => {
+                let op = Op {
+                    kind: OpKind::Gate {
+                        name: gate.clone(),
+                        params: params.clone(),
+                        qubits: operands.clone(),
+                    },
+                    span: stmt.span,
+                };
+                for target in operands {
+                    if let Some(q) = qubits.get_mut(&target.name) {
+                        apply_gate(&mut q.state, &op, target);
+                    }
+                }
+            }
+            StmtKind::Measure { target, .. } | StmtKind::Reset { target } => {
+                if let Some(q) = qubits.get_mut(&target.name) {
+                    clear(&mut q.state, target);
+                }
+            }
+            StmtKind::Let { init: expr, .. } | StmtKind::Expr(expr) => {
+                escape_args(qubits, expr, stmt.span);
+            }
+            StmtKind::Return(expr) => {
+                match expr {
+                    Some(Expr::Var(name)) => {
+                        if let Some(q) = qubits.get_mut(name) {
+                            q.state = State::Returned;
+                        }
+                    }
+                    Some(expr) => escape_args(qubits, expr, stmt.span),
+                    None => {}
+                }
+                self.check_exit(qubits, stmt.span);
+                return true;
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                escape_args(qubits, cond, stmt.span);
+
+                let mut then_qubits = qubits.clone();
+                let then_exits = self.walk_block(then_body, &mut then_qubits);
+                let else_exits = match else_body {
+                    Some(body) => self.walk_block(body, qubits),
+                    None => false,
+                };
+
+                match (then_exits, else_exits) {
+                    (true, true) => return true,
+                    (true, false) => {}
+                    (false, true) => *qubits = then_qubits,
+                    (false, false) => join(qubits, then_qubits),
+                }
+            }
+            StmtKind::Block(body) => return self.walk_block(body, qubits),
+            // Every iteration does the same thing to `q[i]`, so the body is
+            // walked once. A loop that runs zero times touches nothing, so
+            // assuming it ran keeps paired loops like `H(q[i])` followed by
+            // `measure q[i]` clean.
+            StmtKind::For { body, .. } => {
+                let mut body_qubits = qubits.clone();
+                if !self.walk_block(body, &mut body_qubits) {
+                    *qubits = body_qubits;
+                }
+            }
+        }
+        false
+    }
+
+    fn check_exit(&mut self, qubits: &Qubits, exit: Span) {
+        let mut leaked: Vec<(&String, &Qubit)> = qubits
+            .iter()
+            .filter(|(_, q)| !self.reported.contains(&q.decl))
+            .collect();
+        leaked.sort_by_key(|(_, q)| q.decl);
+
+        for (name, q) in leaked {
+            let (last, what) = match &q.state {
+                State::Dirty { last, .. } => (*last, "last modified here"),
+                State::Escaped { last } => (*last, "passed to a call here"),
+                State::Unknown { last } => (*last, "modified on some paths here"),
+                State::Clean | State::Returned => continue,
+            };
+            self.reported.insert(q.decl);
+
+            let diag = Diagnostic::warning(
+                format!(
+                    "qubit `{}` can leave `{}` in an unknown state; measure, reset or uncompute it first",
+                    name, self.func.name
+                ),
+                q.decl,
+            )
+            .with_note(last, what)
+            .with_note(exit, "function exits here");
+            self.cx.emit_at(Lint::QubitLeak, q.level, diag);
+        }
+    }
+}
+
+fn join(into: &mut Qubits, other: Qubits) {
+    for (name, q) in other {
+        match into.get_mut(&name) {
+            Some(mine) if mine.decl == q.decl => {
+                if mine.state != q.state {
+                    let last = last_span(&mine.state).or(last_span(&q.state));
+                    mine.state = match last {
+                        Some(last) => State::Unknown { last },
+                        None => State::Clean,
+                    };
+                }
+            }
+            // Declared in only one arm: keep the path that has it.
+            _ => {
+                into.insert(name, q);
+            }
+        }
+    }
+}
+
+fn last_span(state: &State) -> Option<Span> {
+    match state {
+        State::Dirty { last, .. } | State::Escaped { last } | State::Unknown { last } => {
+            Some(*last)
+        }
+        State::Clean | State::Returned => None,
+    }
+}
+
+fn apply_gate(state: &mut State, op: &Op, target: &QubitRef) {
+    let span = op.span;
+    let applied = (op.clone(), target.to_string());
+    match state {
+        State::Clean => {
+            *state = State::Dirty {
+                gates: vec![applied],
+                last: span,
+            };
+        }
+        State::Dirty { gates, last } => {
+            if gates
+                .last()
+                .is_some_and(|(prev, on)| *on == applied.1 && undoes(prev, op))
+            {
+                gates.pop();
+            } else {
+                gates.push(applied);
+            }
+            *last = span;
+            if gates.is_empty() {
+                *state = State::Clean;
+            }
+        }
+        State::Escaped { last } | State::Unknown { last } => *last = span,
+        State::Returned => {}
+    }
+}
+
+// Measuring or resetting a whole qubit cleans it; for one array element,
+// only the gates applied to that same element are forgotten.
+fn clear(state: &mut State, target: &QubitRef) {
+    match (state.clone(), &target.index) {
+        (State::Dirty { mut gates, last }, Some(_)) => {
+            let operand = target.to_string();
+            gates.retain(|(_, on)| *on != operand);
+            *state = if gates.is_empty() {
+                State::Clean
+            } else {
+                State::Dirty { gates, last }
+            };
+        }
+        _ => *state = State::Clean,
+    }
+}
+
+// Qubits passed to a call may be left in any state by the callee.
+fn escape_args(qubits: &mut Qubits, expr: &Expr, span: Span) {
+    expr.for_each_var(&mut |name| {
+        if let Some(q) = qubits.get_mut(name) {
+            if q.state != State::Returned {
+                q.state = State::Escaped { last: span };
+            }
+        }
+    });
+}
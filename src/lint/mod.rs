@@ -0,0 +1,182 @@
+pub mod attrs;
+pub mod leak;
+pub mod unreachable;
+pub mod unused;
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::ast::{Attribute, Function, Program};
+use crate::diag::{Diagnostic, Severity};
+use crate::lexer::annotate::Annotation;
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariables,
+    UnusedQubits,
+    UnusedMeasurements,
+    UnreachableCode,
+    UnusedAttributes,
+    QubitLeak,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedVariables,
+        Lint::UnusedQubits,
+        Lint::UnusedMeasurements,
+        Lint::UnreachableCode,
+        Lint::UnusedAttributes,
+        Lint::QubitLeak,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariables => "unused_variables",
+            Lint::UnusedQubits => "unused_qubits",
+            Lint::UnusedMeasurements => "unused_measurements",
+            Lint::UnreachableCode => "unreachable_code",
+            Lint::UnusedAttributes => "unused_attributes",
+            Lint::QubitLeak => "qubit_leak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|l| l.name() == name)
+    }
+
+    pub fn default_level(self) -> Level {
+        Level::Warn
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// Lint levels set from the command line. `warnings` names every lint.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, level: Level) -> Result<()> {
+        match lints_named(name) {
+            Some(lints) => {
+                for lint in lints {
+                    self.levels.insert(lint, level);
+                }
+                Ok(())
+            }
+            None => bail!("unknown lint `{}`", name),
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level())
+    }
+}
+
+pub(crate) fn lints_named(name: &str) -> Option<Vec<Lint>> {
+    if name == "warnings" {
+        Some(Lint::ALL.to_vec())
+    } else {
+        Lint::from_name(name).map(|l| vec![l])
+    }
+}
+
+// Tracks the lint levels in effect while walking a function: the command
+// line config, overridden by `#[allow]`/`#[warn]`/`#[deny]` on the function
+// and then on each enclosing statement.
+pub struct LintCx<'a> {
+    config: &'a LintConfig,
+    scopes: Vec<Vec<(Lint, Level)>>,
+    pub diags: Vec<Diagnostic>,
+}
+
+impl<'a> LintCx<'a> {
+    pub fn new(config: &'a LintConfig) -> Self {
+        Self {
+            config,
+            scopes: Vec::new(),
+            diags: Vec::new(),
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        for scope in self.scopes.iter().rev() {
+            if let Some((_, level)) = scope.iter().rev().find(|(l, _)| *l == lint) {
+                return *level;
+            }
+        }
+        self.config.level(lint)
+    }
+
+    pub fn push_attrs(&mut self, attrs: &[Attribute]) {
+        let mut scope = Vec::new();
+        for attr in attrs {
+            let (names, level) = match &attr.annotation {
+                Annotation::Allow(names) => (names, Level::Allow),
+                Annotation::Warn(names) => (names, Level::Warn),
+                Annotation::Deny(names) => (names, Level::Deny),
+                _ => continue,
+            };
+            // Unknown names are reported once by the `attrs` pass.
+            for lints in names.iter().filter_map(|n| lints_named(n)) {
+                scope.extend(lints.into_iter().map(|l| (l, level)));
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    pub fn pop_attrs(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub fn emit(&mut self, lint: Lint, span: Span, message: impl Into<String>) {
+        let level = self.level(lint);
+        self.emit_at(lint, level, Diagnostic::warning(message, span));
+    }
+
+    // Emits `diag` at a level captured earlier, for lints that are only
+    // decided after walking past the statement they point at.
+    pub fn emit_at(&mut self, lint: Lint, level: Level, mut diag: Diagnostic) {
+        diag.severity = match level {
+            Level::Allow => return,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        self.diags.push(diag.with_code(lint.name()));
+    }
+}
+
+pub fn check_program(program: &Program, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut cx = LintCx::new(config);
+    for func in &program.functions {
+        check_function(&mut cx, func);
+    }
+    cx.diags.sort_by_key(|d| d.span);
+    cx.diags
+}
+
+fn check_function(cx: &mut LintCx, func: &Function) {
+    cx.push_attrs(&func.attrs);
+    unused::check(cx, func);
+    unreachable::check(cx, func);
+    attrs::check(cx, func);
+    leak::check(cx, func);
+    cx.pop_attrs();
+}
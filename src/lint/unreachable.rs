@@ -0,0 +1,58 @@
+use crate::ast::{Function, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+use crate::lexer::Span;
+
+use super::{Lint, LintCx};
+
+// Warns once per block, at the first statement after one that always
+// returns: a `return`, or an `if` whose arms both return.
+pub fn check(cx: &mut LintCx, func: &Function) {
+    check_block(cx, &func.body);
+}
+
+// Returns the span of the statement that makes the rest of the block
+// unreachable, if any.
+fn check_block(cx: &mut LintCx, stmts: &[Stmt]) -> Option<Span> {
+    for (i, stmt) in stmts.iter().enumerate() {
+        cx.push_attrs(&stmt.attrs);
+        let diverges = check_stmt(cx, stmt);
+        cx.pop_attrs();
+
+        let Some(exit) = diverges else {
+            continue;
+        };
+        if let Some(next) = stmts.get(i + 1) {
+            cx.push_attrs(&next.attrs);
+            let level = cx.level(Lint::UnreachableCode);
+            cx.pop_attrs();
+
+            let diag = Diagnostic::warning("unreachable statement", next.span)
+                .with_note(exit, "any code following this `return` is unreachable");
+            cx.emit_at(Lint::UnreachableCode, level, diag);
+        }
+        return Some(exit);
+    }
+    None
+}
+
+fn check_stmt(cx: &mut LintCx, stmt: &Stmt) -> Option<Span> {
+    match &stmt.kind {
+        StmtKind::Return(_) => Some(stmt.span),
+        StmtKind::If {
+            then_body,
+            else_body,
+            ..
+        } => {
+            let then_exit = check_block(cx, then_body);
+            let else_exit = else_body.as_ref().and_then(|b| check_block(cx, b));
+            then_exit.and(else_exit).map(|_| stmt.span)
+        }
+        StmtKind::Block(body) => check_block(cx, body),
+        // The body may run zero times.
+        StmtKind::For { body, .. } => {
+            check_block(cx, body);
+            None
+        }
+        _ => None,
+    }
+}
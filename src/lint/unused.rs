@@ -0,0 +1,130 @@
+use crate::ast::{Expr, Function, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+use crate::lexer::Span;
+
+use super::{Level, Lint, LintCx};
+
+struct Binding {
+    name: String,
+    lint: Lint,
+    span: Span,
+    level: Level,
+    used: bool,
+    in_scope: bool,
+}
+
+// Flags `let` bindings, qubits and measured bits that are never read.
+// Bindings are kept in declaration order, so a lookup from the back finds
+// the one a use refers to even when names are shadowed. Leaving a block
+// takes its bindings out of scope.
+pub fn check(cx: &mut LintCx, func: &Function) {
+    let mut bindings: Vec<Binding> = Vec::new();
+    walk_block(cx, &func.body, &mut bindings);
+
+    for b in bindings {
+        if b.used || b.name.starts_with('_') {
+            continue;
+        }
+        let message = match b.lint {
+            Lint::UnusedQubits => format!("qubit `{}` is declared but never used", b.name),
+            Lint::UnusedMeasurements => {
+                format!("measurement result `{}` is never read", b.name)
+            }
+            _ => format!("unused variable `{}`", b.name),
+        };
+        cx.emit_at(b.lint, b.level, Diagnostic::warning(message, b.span));
+    }
+}
+
+fn walk_block(cx: &mut LintCx, stmts: &[Stmt], bindings: &mut Vec<Binding>) {
+    let start = bindings.len();
+    for stmt in stmts {
+        cx.push_attrs(&stmt.attrs);
+        walk_stmt(cx, stmt, bindings);
+        cx.pop_attrs();
+    }
+    for b in &mut bindings[start..] {
+        b.in_scope = false;
+    }
+}
+
+fn walk_stmt(cx: &mut LintCx, stmt: &Stmt, bindings: &mut Vec<Binding>) {
+    let bind = |cx: &LintCx, bindings: &mut Vec<Binding>, name: &str, lint: Lint| {
+        bindings.push(Binding {
+            name: name.to_string(),
+            lint,
+            span: stmt.span,
+            level: cx.level(lint),
+            used: false,
+            in_scope: true,
+        });
+    };
+
+    match &stmt.kind {
+        StmtKind::Let { name, init } => {
+            use_expr(bindings, init);
+            bind(cx, bindings, name, Lint::UnusedVariables);
+        }
+        StmtKind::QbitDecl { name, size } => {
+            if let Some(size) = size {
+                use_expr(bindings, size);
+            }
+            bind(cx, bindings, name, Lint::UnusedQubits);
+        }
+        StmtKind::QOp { qubits, .. } => {
+            for qubit in qubits {
+                qubit.for_each_var(&mut |name| use_name(bindings, name));
+            }
+        }
+        StmtKind::Reset { target } => {
+            target.for_each_var(&mut |name| use_name(bindings, name));
+        }
+        StmtKind::Measure { target, classical } => {
+            target.for_each_var(&mut |name| use_name(bindings, name));
+            if let Some(c) = classical {
+                bind(cx, bindings, c, Lint::UnusedMeasurements);
+            }
+        }
+        StmtKind::Return(Some(expr)) | StmtKind::Expr(expr) => use_expr(bindings, expr),
+        StmtKind::Return(None) => {}
+        StmtKind::If {
+            cond,
+            then_body,
+            else_body,
+        } => {
+            use_expr(bindings, cond);
+            walk_block(cx, then_body, bindings);
+            if let Some(body) = else_body {
+                walk_block(cx, body, bindings);
+            }
+        }
+        StmtKind::Block(body) => walk_block(cx, body, bindings),
+        StmtKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            use_expr(bindings, start);
+            use_expr(bindings, end);
+            let counter = bindings.len();
+            bind(cx, bindings, var, Lint::UnusedVariables);
+            walk_block(cx, body, bindings);
+            bindings[counter].in_scope = false;
+        }
+    }
+}
+
+fn use_name(bindings: &mut [Binding], name: &str) {
+    if let Some(b) = bindings
+        .iter_mut()
+        .rev()
+        .find(|b| b.in_scope && b.name == name)
+    {
+        b.used = true;
+    }
+}
+
+fn use_expr(bindings: &mut [Binding], expr: &Expr) {
+    expr.for_each_var(&mut |name| use_name(bindings, name));
+}
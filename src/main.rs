@@ -1,34 +1,345 @@
-mod lexer;
-
-use lexer::{Lexer, Token};
-use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser as _, ValueEnum};
+
+use qxad::bta;
+use qxad::circuit::rewrite;
+use qxad::circuit::toffoli::{CcxMode, McxMode, ToffoliOptions};
+use qxad::circuit::{self, Dag, Optimizer, Pass, PassManager, Target};
+use qxad::consteval::{self, ArithMode};
+use qxad::inline::{self, InlineOptions};
+use qxad::ir;
+use qxad::lexer::{Lexer, Token};
+use qxad::lint::{self, Level, LintConfig};
+use qxad::parser::Parser;
+use qxad::pe::{self, PeOptions};
+
+#[derive(clap::Parser)]
+#[command(
+    name = "qxad",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print an intermediate form of the program instead of the token stream
+    #[arg(long, value_name = "KIND")]
+    emit: Option<Emit>,
+
+    // Absent only when a subcommand is given.
+    #[command(flatten)]
+    options: Option<Options>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Partially evaluate the program and print what is left of it
+    Pe {
+        /// What to print
+        #[arg(long, value_name = "KIND", default_value = "residual")]
+        emit: PeEmit,
+
+        #[command(flatten)]
+        options: Options,
+    },
+}
+
+#[derive(clap::Args)]
+struct Options {
+    /// Source file to compile
+    file: PathBuf,
+
+    /// Report LINT as a warning (`warnings` for all lints)
+    #[arg(short = 'W', value_name = "LINT")]
+    warn: Vec<String>,
+
+    /// Report LINT as an error (`warnings` for all lints)
+    #[arg(short = 'D', value_name = "LINT")]
+    deny: Vec<String>,
+
+    /// Overflow semantics for constant arithmetic: checked or wrapping
+    #[arg(long, value_name = "MODE", default_value = "checked")]
+    arith: ArithMode,
+
+    /// Most specialized copies the partial evaluator makes of one function
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    max_specializations: usize,
+
+    /// Most iterations of one loop the partial evaluator unrolls
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    unroll_budget: usize,
+
+    /// Statements the partial evaluator may evaluate in one function
+    #[arg(long, value_name = "N", default_value_t = 10_000)]
+    pe_fuel: u64,
+
+    /// Inline a function when its size times its number of calls is at most N
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    inline_threshold: usize,
+
+    /// Gates the target runs natively, comma-separated (default: all)
+    #[arg(long, value_name = "GATES", value_delimiter = ',')]
+    basis: Vec<String>,
+
+    /// Peephole rewrite rules to use besides the built-in ones, one per line
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+
+    /// Circuit optimizer: peephole or zx [default: peephole]
+    #[arg(long, value_name = "OPTIMIZER")]
+    opt: Option<Optimizer>,
+
+    /// Optimization level, from 0 (no passes) to 3 [default: 2]
+    #[arg(
+        short = 'O',
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(u8).range(0..=3)
+    )]
+    opt_level: Option<u8>,
+
+    /// Passes to run instead of those of the optimization level,
+    /// comma-separated: zx, cancel, phase, fuse, toffoli or basis
+    #[arg(long, value_name = "PASSES", value_delimiter = ',')]
+    passes: Vec<Pass>,
+
+    /// CCX decomposition: exact (7 T gates) or relative-phase (4 T gates
+    /// where a later CCX undoes the phase); decomposes CCX even without
+    /// --basis [default: exact]
+    #[arg(long, value_name = "MODE")]
+    toffoli: Option<CcxMode>,
+
+    /// MCX decomposition: ancillae (a CCX ladder through idle qubits) or
+    /// no-ancillae; decomposes MCX even without --basis [default: ancillae]
+    #[arg(long, value_name = "MODE")]
+    mcx: Option<McxMode>,
+
+    /// Print the gates and depth each pass removed, and the time it took
+    #[arg(long)]
+    stats: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PeEmit {
+    /// The residual program, each statement noted with where it came from
+    Residual,
+    /// How many gates each rewrite rule removed from the residual program,
+    /// and the T-count and CX-count before and after phase polynomials
+    Rewrites,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    /// The token stream, without checking the program
+    Tokens,
+    /// The program annotated with binding times
+    Bta,
+    /// The residual program after partial evaluation, each statement noted
+    /// with where it came from
+    Residual,
+    /// How many gates each rewrite rule removed from the residual program,
+    /// and the T-count and CX-count before and after phase polynomials
+    Rewrites,
+    /// The program after inlining calls
+    Inlined,
+    /// Each straight-line run of quantum operations, as layers of its DAG
+    Circuit,
+    /// The SSA intermediate representation
+    Ir,
+    /// The SSA intermediate representation after scalar optimizations
+    IrOpt,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Some(Command::Pe { emit, options }) => {
+            let emit = match emit {
+                PeEmit::Residual => Emit::Residual,
+                PeEmit::Rewrites => Emit::Rewrites,
+            };
+            run(options, Some(emit))
+        }
+        None => run(cli.options.as_ref().unwrap(), cli.emit),
+    };
 
-    if args.len() <= 1 {
-        eprintln!("Usage: qxad <file.qxd>");
-        return;
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
     }
+}
 
-    let path = &args[1];
-    let p = Path::new(path);
-
-    if p.extension().and_then(|s| s.to_str()) != Some("qxd") {
-        eprintln!("Error: expected a .qxd source file, got {}", path);
-        return;
+// Returns whether compilation succeeded without errors.
+fn run(cli: &Options, emit: Option<Emit>) -> Result<bool> {
+    let path = &cli.file;
+    if path.extension().and_then(|s| s.to_str()) != Some("qxd") {
+        bail!("expected a .qxd source file, got {}", path.display());
     }
 
-    let src = fs::read_to_string(path).expect("could not read .qxd file");
+    let src =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
     let mut lex = Lexer::new(&src);
 
-    loop {
-        let tok = lex.next_token();
-        println!("{:?}", tok);
-        if tok == Token::EOF {
-            break;
+    // Only the residual program goes through the passes.
+    if !matches!(emit, Some(Emit::Residual | Emit::Rewrites)) {
+        let pipeline = [
+            ("-O", cli.opt_level.is_some()),
+            ("--opt", cli.opt.is_some()),
+            ("--passes", !cli.passes.is_empty()),
+            ("--toffoli", cli.toffoli.is_some()),
+            ("--mcx", cli.mcx.is_some()),
+            ("--stats", cli.stats),
+        ];
+        if let Some((flag, _)) = pipeline.iter().find(|(_, given)| *given) {
+            bail!(
+                "{} only applies with `--emit residual`, `--emit rewrites` or `qxad pe`",
+                flag
+            );
         }
     }
+
+    // Without `--emit`, the token stream is printed as it always was, and
+    // the program is then checked.
+    if emit.is_none() || emit == Some(Emit::Tokens) {
+        let mut tokens = Lexer::new(&src);
+        loop {
+            let tok = tokens.next_token();
+            println!("{:?}", tok);
+            if tok == Token::EOF {
+                break;
+            }
+        }
+        if emit.is_some() {
+            return Ok(true);
+        }
+    }
+
+    // Group names go first so that `-D warnings -W unused_qubits` keeps
+    // the one named lint a warning.
+    let mut levels: Vec<(&String, Level)> = cli
+        .warn
+        .iter()
+        .map(|n| (n, Level::Warn))
+        .chain(cli.deny.iter().map(|n| (n, Level::Deny)))
+        .collect();
+    levels.sort_by_key(|(name, _)| name.as_str() != "warnings");
+
+    let mut config = LintConfig::new();
+    for (name, level) in levels {
+        config.set(name, level)?;
+    }
+
+    let target = if cli.basis.is_empty() {
+        Target::default()
+    } else {
+        Target::with_basis(&cli.basis)?
+    };
+    let mut rules = rewrite::identities();
+    if let Some(path) = &cli.rules {
+        rules.extend(circuit::rules::load(path)?);
+    }
+
+    let program = Parser::new(&mut lex).parse_program()?;
+    let bta = bta::analyze(&program);
+    let mut diags = consteval::check_program(&program, cli.arith);
+    match emit {
+        Some(Emit::Bta) => print!("{}", bta::render(&program, &bta)),
+        Some(Emit::Ir) => {
+            let module = ir::lower_program(&program)?;
+            ir::verify(&module)?;
+            print!("{}", module);
+        }
+        Some(Emit::IrOpt) => {
+            let mut module = ir::lower_program(&program)?;
+            ir::verify(&module)?;
+            ir::optimize_module(&mut module);
+            ir::verify(&module)?;
+            print!("{}", module);
+        }
+        Some(Emit::Circuit) => {
+            for func in &program.functions {
+                circuit::map_runs(&func.body, &mut |ops| {
+                    println!("{} at {}:", func.name, ops[0].span);
+                    print!("{}", Dag::from_ops(ops.clone()));
+                    ops
+                });
+            }
+        }
+        Some(Emit::Inlined) => {
+            let options = InlineOptions {
+                threshold: cli.inline_threshold,
+            };
+            print!("{}", inline::inline_program(&program, &options));
+        }
+        Some(Emit::Residual | Emit::Rewrites) => {
+            let options = PeOptions {
+                arith: cli.arith,
+                max_specializations: cli.max_specializations,
+                unroll_budget: cli.unroll_budget,
+                fuel: cli.pe_fuel,
+            };
+            let (residual, pe_diags) = pe::partial_evaluate(&program, &options);
+            // Gates outside the basis are translated at every level, Toffoli
+            // gates first so that the other passes see what they become.
+            // Asking for a decomposition mode asks for the decomposition.
+            let decompose = cli.toffoli.is_some() || cli.mcx.is_some();
+            let passes = if cli.passes.is_empty() {
+                let mut passes =
+                    circuit::pass::preset(cli.opt_level.unwrap_or(2), cli.opt.unwrap_or_default());
+                let unsupported = target.basis().is_some()
+                    && (!target.supports("CCX") || !target.supports("MCX"));
+                if decompose || unsupported {
+                    passes.insert(0, Pass::Toffoli);
+                }
+                if target.basis().is_some() {
+                    passes.push(Pass::Basis);
+                }
+                passes
+            } else {
+                if decompose && !cli.passes.contains(&Pass::Toffoli) {
+                    bail!("--toffoli and --mcx only apply when `--passes` includes toffoli");
+                }
+                cli.passes.clone()
+            };
+            let mut manager = PassManager::new(passes, target, rules);
+            manager.toffoli = ToffoliOptions {
+                ccx: cli.toffoli.unwrap_or_default(),
+                mcx: cli.mcx.unwrap_or_default(),
+            };
+            let residual = manager.run_program(&residual)?;
+            if emit == Some(Emit::Rewrites) {
+                print!("{}{}", manager.counts, manager.phases);
+            } else {
+                print!("{}", pe::render_residual(&residual, &bta));
+            }
+            if cli.stats {
+                for stats in manager.stats() {
+                    eprint!("{}", stats);
+                }
+            }
+            diags.extend(pe_diags);
+        }
+        _ => {}
+    }
+
+    diags.extend(bta.diags);
+    diags.extend(lint::check_program(&program, &config));
+    diags.sort_by_key(|d| d.span);
+
+    let errors = diags.iter().filter(|d| d.is_error()).count();
+    for d in &diags {
+        eprintln!("{}: {}", path.display(), d);
+    }
+    if errors > 0 {
+        eprintln!("error: aborting due to {} previous error(s)", errors);
+    }
+    Ok(errors == 0)
 }
@@ -2,20 +2,26 @@ use std::mem;
 
 use anyhow::{bail, Result};
 
+use crate::ast::angle::AngleTerm;
 use crate::ast::*;
-use crate::lexer::{Lexer, Token};
+use crate::lexer::annotate::Annotation;
+use crate::lexer::{Lexer, Span, Token};
 
 pub struct Parser<'a> {
     lexer: &'a mut Lexer,
     lookahead: Token,
+    lookahead_span: Span,
+    prev_span: Span,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: &'a mut Lexer) -> Self {
-        let first = lexer.next_token();
+        let (first, span) = lexer.next_spanned();
         Self {
             lexer,
             lookahead: first,
+            lookahead_span: span,
+            prev_span: span,
         }
     }
 
@@ -23,14 +29,34 @@ impl<'a> Parser<'a> {
         let mut functions = Vec::new();
 
         while !matches!(self.lookahead, Token::EOF) {
-            functions.push(self.parse_function()?);
+            let attrs = self.parse_attrs()?;
+            if matches!(self.lookahead, Token::EOF) {
+                bail!("expected item after attributes");
+            }
+            functions.push(self.parse_function(attrs)?);
         }
 
         Ok(Program { functions })
     }
 
     fn bump(&mut self) -> Token {
-        mem::replace(&mut self.lookahead, self.lexer.next_token())
+        let (next, span) = self.lexer.next_spanned();
+        self.prev_span = mem::replace(&mut self.lookahead_span, span);
+        mem::replace(&mut self.lookahead, next)
+    }
+
+    fn parse_attrs(&mut self) -> Result<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        while let Token::Attr(name) = self.current() {
+            let span = self.lookahead_span;
+            let annotation = match name.parse::<Annotation>() {
+                Ok(a) => a,
+                Err(e) => bail!("{}: {}", span, e),
+            };
+            self.bump();
+            attrs.push(Attribute { annotation, span });
+        }
+        Ok(attrs)
     }
 
     fn expect_token(&mut self, expected: &Token) -> Result<Token> {
@@ -51,20 +77,12 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expect_number(&mut self) -> Result<String> {
-        let tok = self.bump();
-        if let Token::Number(s) = tok {
-            Ok(s)
-        } else {
-            bail!("expected number literal, found {:?}", tok);
-        }
-    }
-
     fn current(&self) -> &Token {
         &self.lookahead
     }
 
-    fn parse_function(&mut self) -> Result<Function> {
+    fn parse_function(&mut self, attrs: Vec<Attribute>) -> Result<Function> {
+        let span = self.lookahead_span;
         match self.bump() {
             Token::Fn => {}
             other => bail!("expected 'fn', found {:?}", other),
@@ -90,14 +108,24 @@ impl<'a> Parser<'a> {
 
         self.expect_token(&Token::RParen)?;
 
-        let pe_enabled = self.lexer.pe_enabled;
+        // Only the function's own attributes count; the lexer's flag would
+        // carry a `#[nope]` over into the functions after it.
+        let pe_enabled = attrs
+            .iter()
+            .rev()
+            .find_map(|a| a.annotation.pe_toggle())
+            .unwrap_or(true);
         let body = self.parse_block()?;
+        let end_span = self.prev_span;
 
         Ok(Function {
             name,
             params,
             body,
             pe_enabled,
+            attrs,
+            span,
+            end_span,
         })
     }
 
@@ -106,53 +134,217 @@ impl<'a> Parser<'a> {
         let mut stmts = Vec::new();
 
         while !matches!(self.current(), Token::RBrace | Token::EOF) {
-            let stmt = self.parse_stmt()?;
-            stmts.push(stmt);
+            let attrs = self.parse_attrs()?;
+            if matches!(self.current(), Token::RBrace | Token::EOF) {
+                bail!("{}: expected statement after attributes", self.lookahead_span);
+            }
+            let span = self.lookahead_span;
+            let kind = self.parse_stmt()?;
+            stmts.push(Stmt { kind, attrs, span });
         }
 
         self.expect_token(&Token::RBrace)?;
         Ok(stmts)
     }
 
-    fn parse_stmt(&mut self) -> Result<Stmt> {
+    fn parse_stmt(&mut self) -> Result<StmtKind> {
         match self.current() {
             Token::Let => self.parse_let_stmt(),
             Token::Qbit => self.parse_qbit_decl(),
             Token::Return => self.parse_return_stmt(),
             Token::Measure => self.parse_measure_stmt(),
+            Token::Reset => self.parse_reset_stmt(),
             Token::QOp { .. } => self.parse_qop_stmt(),
+            Token::Gate(_) => self.parse_gate_stmt(),
+            Token::If => self.parse_if_stmt(),
+            Token::For => self.parse_for_stmt(),
+            Token::LBrace => Ok(StmtKind::Block(self.parse_block()?)),
             _ => self.parse_expr_stmt(),
         }
     }
 
-    fn parse_let_stmt(&mut self) -> Result<Stmt> {
+    fn parse_if_stmt(&mut self) -> Result<StmtKind> {
+        self.bump();
+        let cond = self.parse_expr()?;
+        let then_body = self.parse_block()?;
+
+        let mut else_body = None;
+        if matches!(self.current(), Token::Else) {
+            self.bump();
+            if matches!(self.current(), Token::If) {
+                let span = self.lookahead_span;
+                let kind = self.parse_if_stmt()?;
+                else_body = Some(vec![Stmt {
+                    kind,
+                    attrs: Vec::new(),
+                    span,
+                }]);
+            } else {
+                else_body = Some(self.parse_block()?);
+            }
+        }
+
+        Ok(StmtKind::If {
+            cond,
+            then_body,
+            else_body,
+        })
+    }
+
+    fn parse_for_stmt(&mut self) -> Result<StmtKind> {
+        self.bump();
+        let var = self.expect_ident()?;
+        self.expect_token(&Token::In)?;
+        let start = self.parse_expr()?;
+        self.expect_token(&Token::DotDot)?;
+        let end = self.parse_expr()?;
+        let body = self.parse_block()?;
+        Ok(StmtKind::For {
+            var,
+            start,
+            end,
+            body,
+        })
+    }
+
+    fn parse_let_stmt(&mut self) -> Result<StmtKind> {
         self.bump();
         let name = self.expect_ident()?;
         self.expect_token(&Token::Assign)?;
         let init = self.parse_expr()?;
         self.expect_token(&Token::Semicolon)?;
-        Ok(Stmt::Let { name, init })
+        Ok(StmtKind::Let { name, init })
     }
 
-    fn parse_qbit_decl(&mut self) -> Result<Stmt> {
+    fn parse_qbit_decl(&mut self) -> Result<StmtKind> {
         self.bump();
         let name = self.expect_ident()?;
+        let size = self.parse_index()?;
         self.expect_token(&Token::Semicolon)?;
-        Ok(Stmt::QbitDecl { name })
+        Ok(StmtKind::QbitDecl { name, size })
     }
 
-    fn parse_qop_stmt(&mut self) -> Result<Stmt> {
+    // An optional `[expr]` suffix.
+    fn parse_index(&mut self) -> Result<Option<Expr>> {
+        if !matches!(self.current(), Token::LBracket) {
+            return Ok(None);
+        }
+        self.bump();
+        let index = self.parse_expr()?;
+        self.expect_token(&Token::RBracket)?;
+        Ok(Some(index))
+    }
+
+    fn parse_qubit_ref(&mut self) -> Result<QubitRef> {
+        let name = self.expect_ident()?;
+        let index = self.parse_index()?;
+        Ok(QubitRef { name, index })
+    }
+
+    fn parse_qop_stmt(&mut self) -> Result<StmtKind> {
         let tok = self.bump();
         if let Token::QOp { gate, target } = tok {
-            Ok(Stmt::QOp { gate, target })
+            check_arity(&gate, 1)?;
+            check_angles(&gate, 0)?;
+            Ok(StmtKind::QOp {
+                gate,
+                params: Vec::new(),
+                qubits: vec![QubitRef::new(target)],
+            })
         } else {
             bail!("expected quantum operation, found {:?}", tok);
         }
     }
 
-    fn parse_measure_stmt(&mut self) -> Result<Stmt> {
+    // The lexer only folds `G(q);` into a single token, so gates on array
+    // elements and multi-qubit gates arrive here as separate tokens.
+    fn parse_gate_stmt(&mut self) -> Result<StmtKind> {
+        let gate = match self.bump() {
+            Token::Gate(gate) => gate,
+            other => bail!("expected gate, found {:?}", other),
+        };
+        self.expect_token(&Token::LParen)?;
+        let mut params = Vec::new();
+        for _ in 0..angle_count(&gate) {
+            params.push(self.parse_angle()?.to_angle());
+            self.expect_token(&Token::Comma)?;
+        }
+        let mut qubits = vec![self.parse_qubit_ref()?];
+        while matches!(self.current(), Token::Comma) {
+            self.bump();
+            qubits.push(self.parse_qubit_ref()?);
+        }
+        self.expect_token(&Token::RParen)?;
+        self.expect_token(&Token::Semicolon)?;
+        check_arity(&gate, qubits.len())?;
+        Ok(StmtKind::QOp {
+            gate,
+            params,
+            qubits,
+        })
+    }
+
+    // Angles are constant: numbers and `pi` with `+ - * /` and parentheses,
+    // kept exact as fractions of π where possible.
+    fn parse_angle(&mut self) -> Result<AngleTerm> {
+        let mut term = self.parse_angle_product()?;
+        loop {
+            match self.current() {
+                Token::Plus => {
+                    self.bump();
+                    term = term.plus(self.parse_angle_product()?);
+                }
+                Token::Minus => {
+                    self.bump();
+                    term = term.plus(self.parse_angle_product()?.negate());
+                }
+                _ => return Ok(term),
+            }
+        }
+    }
+
+    fn parse_angle_product(&mut self) -> Result<AngleTerm> {
+        let mut term = self.parse_angle_factor()?;
+        loop {
+            match self.current() {
+                Token::Star => {
+                    self.bump();
+                    term = term.times(self.parse_angle_factor()?);
+                }
+                Token::Slash => {
+                    let span = self.lookahead_span;
+                    self.bump();
+                    term = term
+                        .divided_by(self.parse_angle_factor()?)
+                        .ok_or_else(|| anyhow::anyhow!("{}: division by zero in angle", span))?;
+                }
+                _ => return Ok(term),
+            }
+        }
+    }
+
+    fn parse_angle_factor(&mut self) -> Result<AngleTerm> {
+        match self.bump() {
+            Token::Minus => Ok(self.parse_angle_factor()?.negate()),
+            Token::Number(s) => AngleTerm::number(&s)
+                .ok_or_else(|| anyhow::anyhow!("invalid number in angle: {}", s)),
+            Token::Ident(name) if name == "pi" => Ok(AngleTerm::pi()),
+            Token::LParen => {
+                let term = self.parse_angle()?;
+                self.expect_token(&Token::RParen)?;
+                Ok(term)
+            }
+            Token::Ident(name) => bail!(
+                "angles are built from numbers and `pi`, found `{}`",
+                name
+            ),
+            other => bail!("expected an angle, found {:?}", other),
+        }
+    }
+
+    fn parse_measure_stmt(&mut self) -> Result<StmtKind> {
         self.bump();
-        let target = self.expect_ident()?;
+        let target = self.parse_qubit_ref()?;
 
         let mut classical = None;
         if matches!(self.current(), Token::Arrow) {
@@ -162,32 +354,95 @@ impl<'a> Parser<'a> {
         }
 
         self.expect_token(&Token::Semicolon)?;
-        Ok(Stmt::Measure { target, classical })
+        Ok(StmtKind::Measure { target, classical })
+    }
+
+    fn parse_reset_stmt(&mut self) -> Result<StmtKind> {
+        self.bump();
+        let target = self.parse_qubit_ref()?;
+        self.expect_token(&Token::Semicolon)?;
+        Ok(StmtKind::Reset { target })
     }
 
-    fn parse_return_stmt(&mut self) -> Result<Stmt> {
+    fn parse_return_stmt(&mut self) -> Result<StmtKind> {
         self.bump();
         if matches!(self.current(), Token::Semicolon) {
             self.bump();
-            Ok(Stmt::Return(None))
+            Ok(StmtKind::Return(None))
         } else {
             let expr = self.parse_expr()?;
             self.expect_token(&Token::Semicolon)?;
-            Ok(Stmt::Return(Some(expr)))
+            Ok(StmtKind::Return(Some(expr)))
         }
     }
 
-    fn parse_expr_stmt(&mut self) -> Result<Stmt> {
+    fn parse_expr_stmt(&mut self) -> Result<StmtKind> {
         let expr = self.parse_expr()?;
         self.expect_token(&Token::Semicolon)?;
-        Ok(Stmt::Expr(expr))
+        Ok(StmtKind::Expr(expr))
     }
 
     fn parse_expr(&mut self) -> Result<Expr> {
-        self.parse_add_sub()
+        self.parse_comparison()
+    }
+
+    // Comparisons do not chain: `a < b < c` is rejected.
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let span = self.lookahead_span;
+        let expr = self.parse_shift()?;
+
+        let op = match self.current() {
+            Token::EqEq => BinOp::Eq,
+            Token::NotEq => BinOp::Ne,
+            Token::Lt => BinOp::Lt,
+            Token::Le => BinOp::Le,
+            Token::Gt => BinOp::Gt,
+            Token::Ge => BinOp::Ge,
+            _ => return Ok(expr),
+        };
+        self.bump();
+        let rhs = self.parse_shift()?;
+
+        if matches!(
+            self.current(),
+            Token::EqEq | Token::NotEq | Token::Lt | Token::Le | Token::Gt | Token::Ge
+        ) {
+            bail!("{}: comparison operators cannot be chained", self.lookahead_span);
+        }
+
+        Ok(Expr::Binary {
+            op,
+            left: Box::new(expr),
+            right: Box::new(rhs),
+            span,
+        })
+    }
+
+    fn parse_shift(&mut self) -> Result<Expr> {
+        let span = self.lookahead_span;
+        let mut expr = self.parse_add_sub()?;
+
+        loop {
+            let op = match self.current() {
+                Token::Shl => BinOp::Shl,
+                Token::Shr => BinOp::Shr,
+                _ => break,
+            };
+            self.bump();
+            let rhs = self.parse_add_sub()?;
+            expr = Expr::Binary {
+                op,
+                left: Box::new(expr),
+                right: Box::new(rhs),
+                span,
+            };
+        }
+
+        Ok(expr)
     }
 
     fn parse_add_sub(&mut self) -> Result<Expr> {
+        let span = self.lookahead_span;
         let mut expr = self.parse_mul_div()?;
 
         loop {
@@ -199,6 +454,7 @@ impl<'a> Parser<'a> {
                         op: BinOp::Add,
                         left: Box::new(expr),
                         right: Box::new(rhs),
+                        span,
                     };
                 }
                 Token::Minus => {
@@ -208,6 +464,7 @@ impl<'a> Parser<'a> {
                         op: BinOp::Sub,
                         left: Box::new(expr),
                         right: Box::new(rhs),
+                        span,
                     };
                 }
                 _ => break,
@@ -218,6 +475,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_mul_div(&mut self) -> Result<Expr> {
+        let span = self.lookahead_span;
         let mut expr = self.parse_primary()?;
 
         loop {
@@ -229,6 +487,7 @@ impl<'a> Parser<'a> {
                         op: BinOp::Mul,
                         left: Box::new(expr),
                         right: Box::new(rhs),
+                        span,
                     };
                 }
                 Token::Slash => {
@@ -238,6 +497,7 @@ impl<'a> Parser<'a> {
                         op: BinOp::Div,
                         left: Box::new(expr),
                         right: Box::new(rhs),
+                        span,
                     };
                 }
                 _ => break,
@@ -288,3 +548,40 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+pub(crate) fn angle_count(gate: &str) -> usize {
+    match gate {
+        "RX" | "RY" | "RZ" => 1,
+        "U3" => 3,
+        _ => 0,
+    }
+}
+
+pub(crate) fn check_angles(gate: &str, found: usize) -> Result<()> {
+    let count = angle_count(gate);
+    if found != count {
+        let plural = if count == 1 { "" } else { "s" };
+        bail!("`{}` takes {} angle{}, found {}", gate, count, plural, found);
+    }
+    Ok(())
+}
+
+pub(crate) fn check_arity(gate: &str, found: usize) -> Result<()> {
+    // Any number of controls, then the target.
+    if gate == "MCX" {
+        if found < 2 {
+            bail!("`MCX` takes at least 2 qubits, found {}", found);
+        }
+        return Ok(());
+    }
+    let arity = match gate {
+        "CX" | "CNOT" | "CZ" | "SWAP" => 2,
+        "CCX" => 3,
+        _ => 1,
+    };
+    if found != arity {
+        let plural = if arity == 1 { "" } else { "s" };
+        bail!("`{}` takes {} qubit{}, found {}", gate, arity, plural, found);
+    }
+    Ok(())
+}
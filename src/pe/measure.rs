@@ -0,0 +1,461 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Angle, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::consteval::{eval_binop, ArithMode};
+
+// Arrays larger than this are not tracked element by element.
+const MAX_TRACKED: i64 = 1 << 16;
+
+// Follows qubits while they stay in computational-basis states and replaces
+// measurements with a known outcome by their value, so
+//
+//     qbit q; X(q); measure q -> c;
+//
+// becomes `qbit q; X(q); let c = 1;`. X and Y flip a known qubit, phase
+// gates leave it alone, and CX/CCX act classically on known controls: a
+// control known to be 0 drops the gate, one known to be 1 is dropped from
+// it. Any other gate, a call taking the qubit, or a loop that touches it
+// forgets what is known. Qubits that nothing can observe afterwards are
+// removed along with their gates.
+//
+// Only statements evaluated under PE are rewritten.
+pub fn fold_measurements(program: &Program) -> Program {
+    let functions = program
+        .functions
+        .iter()
+        .map(|f| {
+            if f.pe_enabled {
+                fold_function(f)
+            } else {
+                f.clone()
+            }
+        })
+        .collect();
+    Program { functions }
+}
+
+// The value of each element of a qubit (one element) or qubit array, while
+// it is known.
+type Bits = Vec<Option<bool>>;
+
+fn fold_function(func: &Function) -> Function {
+    let mut folder = Folder {
+        env: HashMap::new(),
+        qubits: HashMap::new(),
+        touched: HashSet::new(),
+    };
+    let body = folder.block(&func.body, true);
+
+    let mut observed = HashSet::new();
+    let mut decls = HashMap::new();
+    find_observed(&body, &mut observed, &mut decls);
+    let unused: HashSet<String> = folder
+        .touched
+        .into_iter()
+        .filter(|name| decls.get(name) == Some(&1) && !observed.contains(name))
+        .filter(|name| !func.params.contains(name))
+        .collect();
+
+    Function {
+        body: remove_qubits(body, &unused),
+        ..func.clone()
+    }
+}
+
+// A qubit operand: the array and element when it is known which one, or
+// just the array when it could be any of them.
+type Slot = (String, Option<usize>);
+
+struct Folder {
+    // Known values of the classical variables in scope.
+    env: HashMap<String, i64>,
+    qubits: HashMap<String, Bits>,
+    // Qubits that lost a use to folding, and may now be unused.
+    touched: HashSet<String>,
+}
+
+impl Folder {
+    fn block(&mut self, stmts: &[Stmt], mut pe: bool) -> Vec<Stmt> {
+        let env = self.env.clone();
+        let mut shadowed = HashMap::new();
+        let mut out = Vec::new();
+
+        for stmt in stmts {
+            if let Some(on) = stmt
+                .attrs
+                .iter()
+                .rev()
+                .find_map(|a| a.annotation.pe_toggle())
+            {
+                pe = on;
+            }
+            if let StmtKind::QbitDecl { name, .. } = &stmt.kind {
+                if !shadowed.contains_key(name) {
+                    shadowed.insert(name.clone(), self.qubits.contains_key(name));
+                }
+            }
+            if let Some(kind) = self.stmt(&stmt.kind, pe) {
+                out.push(Stmt {
+                    kind,
+                    ..stmt.clone()
+                });
+            }
+        }
+
+        // A qubit declared here goes out of scope; one it shadowed may have
+        // been used before that.
+        for (name, existed) in shadowed {
+            if existed {
+                self.forget_all(&name);
+            } else {
+                self.qubits.remove(&name);
+            }
+        }
+        self.env = env;
+        out
+    }
+
+    // Returns what the statement becomes, if anything.
+    fn stmt(&mut self, kind: &StmtKind, pe: bool) -> Option<StmtKind> {
+        match kind {
+            StmtKind::Let { name, init } => {
+                self.escape(init);
+                match self.eval(init) {
+                    Some(v) => self.env.insert(name.clone(), v),
+                    None => self.env.remove(name),
+                };
+            }
+            StmtKind::QbitDecl { name, size } => {
+                let len = match size {
+                    None => Some(1),
+                    Some(size) => {
+                        self.escape(size);
+                        self.eval(size).filter(|n| (0..=MAX_TRACKED).contains(n))
+                    }
+                };
+                self.env.remove(name);
+                match len {
+                    Some(len) => self
+                        .qubits
+                        .insert(name.clone(), vec![Some(false); len as usize]),
+                    None => self.qubits.remove(name),
+                };
+            }
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits,
+            } => return self.gate(gate, params, qubits, pe),
+            StmtKind::Measure { target, classical } => {
+                let slot = self.slot(target);
+                let known = slot.as_ref().and_then(|s| self.get(s));
+                match (known, pe) {
+                    (Some(bit), true) => {
+                        self.touched.insert(target.name.clone());
+                        let c = classical.as_ref()?;
+                        self.env.insert(c.clone(), bit as i64);
+                        return Some(StmtKind::Let {
+                            name: c.clone(),
+                            init: Expr::Number(bit as i64),
+                        });
+                    }
+                    _ => {
+                        if let Some(c) = classical {
+                            self.env.remove(c);
+                        }
+                    }
+                }
+            }
+            StmtKind::Reset { target } => match self.slot(target) {
+                Some((name, Some(i))) => self.qubits.get_mut(&name).unwrap()[i] = Some(false),
+                Some((name, None)) => self.forget_all(&name),
+                None => {}
+            },
+            StmtKind::Return(Some(expr)) | StmtKind::Expr(expr) => self.escape(expr),
+            StmtKind::Return(None) => {}
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                self.escape(cond);
+                let before = self.qubits.clone();
+                let then_body = self.block(then_body, pe);
+                let after_then = std::mem::replace(&mut self.qubits, before);
+                let else_body = else_body.as_ref().map(|body| self.block(body, pe));
+                join(&mut self.qubits, &after_then);
+                return Some(StmtKind::If {
+                    cond: cond.clone(),
+                    then_body,
+                    else_body,
+                });
+            }
+            StmtKind::Block(body) => return Some(StmtKind::Block(self.block(body, pe))),
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.escape(start);
+                self.escape(end);
+                // What holds on entry must hold on every iteration.
+                let mut modified = HashSet::new();
+                find_modified(body, &mut modified);
+                for name in &modified {
+                    self.forget_all(name);
+                }
+                let (qubits, env) = (self.qubits.clone(), self.env.clone());
+                self.env.remove(var);
+                let body = self.block(body, pe);
+                (self.qubits, self.env) = (qubits, env);
+                return Some(StmtKind::For {
+                    var: var.clone(),
+                    start: start.clone(),
+                    end: end.clone(),
+                    body,
+                });
+            }
+        }
+        Some(kind.clone())
+    }
+
+    fn gate(
+        &mut self,
+        gate: &str,
+        params: &[Angle],
+        qubits: &[QubitRef],
+        pe: bool,
+    ) -> Option<StmtKind> {
+        let keep = StmtKind::QOp {
+            gate: gate.to_string(),
+            params: params.to_vec(),
+            qubits: qubits.to_vec(),
+        };
+        let slots: Vec<Option<Slot>> = qubits.iter().map(|q| self.slot(q)).collect();
+        let controls = match gate {
+            "X" | "Y" | "CX" | "CNOT" | "CCX" | "MCX" => slots.len() - 1,
+            "Z" | "S" | "Sdg" | "T" | "Tdg" | "RZ" | "CZ" => return Some(keep),
+            _ => {
+                for slot in slots.iter().flatten() {
+                    self.forget(slot);
+                }
+                return Some(keep);
+            }
+        };
+
+        let known: Vec<Option<bool>> = slots[..controls]
+            .iter()
+            .map(|s| s.as_ref().and_then(|s| self.get(s)))
+            .collect();
+        if known.contains(&Some(false)) {
+            if !pe {
+                return Some(keep);
+            }
+            self.touched.extend(qubits.iter().map(|q| q.name.clone()));
+            return None;
+        }
+
+        if let Some(target) = &slots[controls] {
+            match (known.iter().all(Option::is_some), self.get(target)) {
+                (true, Some(bit)) => self.set(target, !bit),
+                (true, None) => {}
+                (false, _) => self.forget(target),
+            }
+        }
+        if !pe || known.iter().all(Option::is_none) {
+            return Some(keep);
+        }
+
+        // Controls known to be 1 no longer matter.
+        let mut rest = Vec::new();
+        for (i, qubit) in qubits.iter().enumerate() {
+            if i < controls && known[i].is_some() {
+                self.touched.insert(qubit.name.clone());
+            } else {
+                rest.push(qubit.clone());
+            }
+        }
+        let gate = match rest.len() {
+            1 => "X",
+            2 => "CX",
+            3 => "CCX",
+            _ => "MCX",
+        };
+        Some(StmtKind::QOp {
+            gate: gate.to_string(),
+            params: Vec::new(),
+            qubits: rest,
+        })
+    }
+
+    fn slot(&self, qubit: &QubitRef) -> Option<Slot> {
+        let bits = self.qubits.get(&qubit.name)?;
+        let index = match &qubit.index {
+            None if bits.len() == 1 => Some(0),
+            None => None,
+            Some(index) => self
+                .eval(index)
+                .and_then(|i| usize::try_from(i).ok())
+                .filter(|i| *i < bits.len()),
+        };
+        Some((qubit.name.clone(), index))
+    }
+
+    fn get(&self, (name, index): &Slot) -> Option<bool> {
+        self.qubits[name][(*index)?]
+    }
+
+    fn set(&mut self, (name, index): &Slot, bit: bool) {
+        if let Some(i) = index {
+            self.qubits.get_mut(name).unwrap()[*i] = Some(bit);
+        }
+    }
+
+    fn forget(&mut self, (name, index): &Slot) {
+        match index {
+            Some(i) => self.qubits.get_mut(name).unwrap()[*i] = None,
+            None => self.forget_all(name),
+        }
+    }
+
+    fn forget_all(&mut self, name: &str) {
+        if let Some(bits) = self.qubits.get_mut(name) {
+            bits.fill(None);
+        }
+    }
+
+    // Qubits passed to a call may be left in any state.
+    fn escape(&mut self, expr: &Expr) {
+        if expr.has_call() {
+            expr.for_each_var(&mut |name| self.forget_all(name));
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Option<i64> {
+        match expr {
+            Expr::Number(v) => Some(*v),
+            Expr::Var(name) => self.env.get(name).copied(),
+            Expr::Binary {
+                op, left, right, ..
+            } => eval_binop(*op, self.eval(left)?, self.eval(right)?, ArithMode::Checked).ok(),
+            Expr::Call { .. } => None,
+        }
+    }
+}
+
+// Keeps what both arms of an `if` agree on.
+fn join(into: &mut HashMap<String, Bits>, other: &HashMap<String, Bits>) {
+    into.retain(|name, _| other.contains_key(name));
+    for (name, bits) in into.iter_mut() {
+        for (bit, theirs) in bits.iter_mut().zip(&other[name]) {
+            if bit != theirs {
+                *bit = None;
+            }
+        }
+    }
+}
+
+// Qubits a loop body may change.
+fn find_modified(stmts: &[Stmt], out: &mut HashSet<String>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::QOp { qubits, .. } => out.extend(qubits.iter().map(|q| q.name.clone())),
+            StmtKind::Reset { target } => {
+                out.insert(target.name.clone());
+            }
+            // Measuring never changes what is known.
+            StmtKind::Measure { .. } => {}
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                find_modified(then_body, out);
+                find_modified(else_body.as_deref().unwrap_or_default(), out);
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => find_modified(body, out),
+            kind => kind.for_each_use(&mut |name| {
+                out.insert(name.to_string());
+            }),
+        }
+    }
+}
+
+// Qubits whose state the rest of the program can see: measured, entangled
+// with another qubit or passed somewhere. Also counts the declarations of
+// each name.
+fn find_observed(stmts: &[Stmt], out: &mut HashSet<String>, decls: &mut HashMap<String, usize>) {
+    for stmt in stmts {
+        let mut insert = |name: &str| {
+            out.insert(name.to_string());
+        };
+        match &stmt.kind {
+            StmtKind::QbitDecl { name, size } => {
+                *decls.entry(name.clone()).or_default() += 1;
+                if let Some(size) = size {
+                    size.for_each_var(&mut insert);
+                }
+            }
+            // Indices are classical.
+            StmtKind::QOp { qubits, .. } if qubits.len() == 1 => {}
+            StmtKind::Reset { .. } => {}
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                cond.for_each_var(&mut insert);
+                find_observed(then_body, out, decls);
+                find_observed(else_body.as_deref().unwrap_or_default(), out, decls);
+            }
+            StmtKind::Block(body) => find_observed(body, out, decls),
+            StmtKind::For {
+                start, end, body, ..
+            } => {
+                start.for_each_var(&mut insert);
+                end.for_each_var(&mut insert);
+                find_observed(body, out, decls);
+            }
+            kind => kind.for_each_use(&mut insert),
+        }
+    }
+}
+
+fn remove_qubits(stmts: Vec<Stmt>, names: &HashSet<String>) -> Vec<Stmt> {
+    if names.is_empty() {
+        return stmts;
+    }
+    let mut out = Vec::new();
+    for mut stmt in stmts {
+        let removed = match &mut stmt.kind {
+            StmtKind::QbitDecl { name, size } => {
+                names.contains(name) && !size.as_ref().is_some_and(Expr::has_call)
+            }
+            StmtKind::QOp { qubits, .. } => {
+                matches!(&qubits[..], [qubit] if names.contains(&qubit.name))
+            }
+            StmtKind::Reset { target } => names.contains(&target.name),
+            StmtKind::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                *then_body = remove_qubits(std::mem::take(then_body), names);
+                if let Some(body) = else_body {
+                    *body = remove_qubits(std::mem::take(body), names);
+                }
+                false
+            }
+            StmtKind::Block(body) | StmtKind::For { body, .. } => {
+                *body = remove_qubits(std::mem::take(body), names);
+                false
+            }
+            _ => false,
+        };
+        if !removed {
+            out.push(stmt);
+        }
+    }
+    out
+}
@@ -0,0 +1,820 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::print::{print_program, Annotate};
+use crate::ast::{BinOp, Expr, Function, Program, QubitRef, Stmt, StmtKind};
+use crate::bta::{BindingTime, Bta};
+use crate::consteval::{eval_binop, ArithMode};
+use crate::diag::Diagnostic;
+use crate::lexer::Span;
+
+pub mod measure;
+
+#[derive(Debug, Clone)]
+pub struct PeOptions {
+    pub arith: ArithMode,
+    // Most specialized copies made of any one function.
+    pub max_specializations: usize,
+    // Most iterations a single loop is unrolled into.
+    pub unroll_budget: usize,
+    // Statements evaluated per function before evaluation gives up.
+    pub fuel: u64,
+}
+
+impl Default for PeOptions {
+    fn default() -> Self {
+        Self {
+            arith: ArithMode::default(),
+            max_specializations: 16,
+            unroll_budget: 64,
+            fuel: 10_000,
+        }
+    }
+}
+
+// Values of the variables known at this point. Bindings are immutable and
+// block scoped, so each block works on its own copy.
+type Env = HashMap<String, i64>;
+
+// Online partial evaluation of the classical fragment. Constants are folded
+// and propagated, `if`s with a known condition are replaced by the arm they
+// take, and what remains is the residual program. Quantum statements are
+// always residual.
+//
+// `Function::pe_enabled` decides whether a body starts out evaluated, and
+// whether the function may be specialized; a `#[pe]`/`#[nope]` on a
+// statement switches evaluation for the rest of the enclosing block.
+//
+// A call with some literal arguments to a function that may be specialized
+// is redirected to a copy with those parameters substituted, e.g.
+// `rotate(3, q)` becomes `rotate_n3(q)`. Copies are shared between calls
+// with the same literal arguments and appended after the original
+// functions. A copy that folds down to `return <literal>;` replaces the
+// call with the literal instead.
+//
+// Under PE, a `for` loop whose bounds are known is unrolled into one copy of
+// the body per iteration, so `for i in 0..2 { H(q[i]); }` becomes
+// `H(q[0]); H(q[1]);`. Loops that cannot be unrolled are kept with a
+// warning.
+//
+// Every statement evaluated costs one unit of fuel, from a budget of
+// `PeOptions::fuel` per function or the `N` of a `#[pe(fuel = N)]` on the
+// function or statement. When it runs out, the loop being unrolled or the
+// copy being specialized is left unevaluated, as is the rest of the
+// function, with a warning naming the loops and calls it was in.
+//
+// Measurements with a known outcome are folded first (see `measure`), so
+// their results are constants here.
+pub fn partial_evaluate(program: &Program, options: &PeOptions) -> (Program, Vec<Diagnostic>) {
+    let program = &measure::fold_measurements(program);
+    let mut pe = PartialEvaluator {
+        options,
+        originals: program
+            .functions
+            .iter()
+            .map(|f| (f.name.clone(), f.clone()))
+            .collect(),
+        names: program.functions.iter().map(|f| f.name.clone()).collect(),
+        specs: HashMap::new(),
+        counts: HashMap::new(),
+        recursive: HashSet::new(),
+        specialized: Vec::new(),
+        fuel: Fuel::new(options.fuel),
+        trail: Vec::new(),
+        span: Span::default(),
+        diags: Vec::new(),
+    };
+
+    let mut functions: Vec<Function> = program.functions.iter().map(|f| pe.function(f)).collect();
+    functions.extend(pe.specialized.into_iter().flatten());
+    (Program { functions }, pe.diags)
+}
+
+// Renders a residual program with the source position each statement was
+// produced from, and why binding-time analysis left it dynamic. Copies of
+// a specialized function note the function they came from. A statement
+// that folded down to something that no longer depends on a dynamic value
+// itself, such as `return m;` becoming `return 0;`, gets no reason; one
+// that is only there because of a dynamic `if` around it is explained by
+// that `if`.
+pub fn render_residual(residual: &Program, bta: &Bta) -> String {
+    let stmts = bta.functions.values().flat_map(|f| &f.stmts).collect();
+    print_program(residual, &mut ResidualNotes { stmts })
+}
+
+// Whether a residual statement is quantum, or reads a variable or makes a
+// call; nested statements are looked at on their own.
+fn still_dynamic(kind: &StmtKind) -> bool {
+    let reads = |expr: &Expr| {
+        let mut any = expr.has_call();
+        expr.for_each_var(&mut |_| any = true);
+        any
+    };
+
+    match kind {
+        StmtKind::Let { init: expr, .. } | StmtKind::Return(Some(expr)) | StmtKind::Expr(expr) => {
+            reads(expr)
+        }
+        StmtKind::If { cond, .. } => reads(cond),
+        StmtKind::For { start, end, .. } => reads(start) || reads(end),
+        StmtKind::QbitDecl { .. }
+        | StmtKind::QOp { .. }
+        | StmtKind::Measure { .. }
+        | StmtKind::Reset { .. } => true,
+        StmtKind::Return(None) | StmtKind::Block(_) => false,
+    }
+}
+
+struct ResidualNotes<'a> {
+    // Statement spans are unique across the file, so copies made from
+    // another function still find their binding times.
+    stmts: HashMap<&'a Span, &'a BindingTime>,
+}
+
+impl Annotate for ResidualNotes<'_> {
+    fn function(&mut self, func: &Function) -> Option<String> {
+        Some(format!("from {}", func.span))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Option<String> {
+        let bt = self
+            .stmts
+            .get(&stmt.span)
+            .filter(|_| still_dynamic(&stmt.kind));
+        Some(match bt {
+            Some(BindingTime::Dynamic(cause)) if cause.span == stmt.span => {
+                format!("from {}, dynamic: {}", stmt.span, cause.reason)
+            }
+            Some(BindingTime::Dynamic(cause)) => format!(
+                "from {}, dynamic: {} ({})",
+                stmt.span, cause.reason, cause.span
+            ),
+            _ => format!("from {}", stmt.span),
+        })
+    }
+}
+
+// What a call with particular literal arguments was turned into.
+#[derive(Debug, Clone)]
+enum Spec {
+    Function(String),
+    // The value, and the copy that returns it.
+    Constant(i64, Box<Function>),
+}
+
+struct PartialEvaluator<'a> {
+    options: &'a PeOptions,
+    originals: HashMap<String, Function>,
+    // Every function name in use, so copies get fresh names.
+    names: HashSet<String>,
+    // Keyed by callee and the literal value of each argument, if any.
+    specs: HashMap<(String, Vec<Option<i64>>), Spec>,
+    counts: HashMap<String, usize>,
+    // Copies some call already refers to by name, so they must be kept even
+    // if they fold to a constant.
+    recursive: HashSet<String>,
+    // In creation order; copies that folded to a constant are left empty,
+    // and only made once a call has arguments that cannot be dropped.
+    specialized: Vec<Option<Function>>,
+    fuel: Fuel,
+    // The loops being unrolled and calls being specialized, outermost
+    // first, for the warning when fuel runs out.
+    trail: Vec<(Span, String)>,
+    // The statement being evaluated, for diagnostics.
+    span: Span,
+    diags: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fuel {
+    budget: u64,
+    left: u64,
+    // Set once evaluation needed more than was left. Everything being
+    // evaluated at that point is then given up on.
+    out: bool,
+}
+
+impl Fuel {
+    fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            left: budget,
+            out: false,
+        }
+    }
+}
+
+// A residual statement, and whether it was produced with evaluation on.
+// Only those may be dropped when they turn out to be dead.
+type Residual = Vec<(Stmt, bool)>;
+
+impl PartialEvaluator<'_> {
+    fn function(&mut self, func: &Function) -> Function {
+        self.fuel = Fuel::new(self.options.fuel);
+        let fuel = func.attrs.iter().find_map(|a| a.annotation.fuel());
+        let (body, _) = self.with_fuel(fuel, |pe| {
+            pe.block(&func.body, &Env::new(), func.pe_enabled)
+        });
+        Function {
+            body,
+            ..func.clone()
+        }
+    }
+
+    // Returns the residual block and whether it always returns.
+    fn block(&mut self, stmts: &[Stmt], env: &Env, mut pe: bool) -> (Vec<Stmt>, bool) {
+        let mut env = env.clone();
+        let mut out = Residual::new();
+        let mut returns = false;
+
+        for stmt in stmts {
+            if let Some(on) = stmt
+                .attrs
+                .iter()
+                .rev()
+                .find_map(|a| a.annotation.pe_toggle())
+            {
+                pe = on;
+            }
+
+            let fuel = stmt.attrs.iter().find_map(|a| a.annotation.fuel());
+            returns |= self.with_fuel(fuel, |this| this.stmt(stmt, &mut env, pe, &mut out));
+            // Out of fuel, the rest is kept as written.
+            if self.fuel.out {
+                pe = false;
+            }
+            // The rest is unreachable; outside PE it is kept as written.
+            if returns && pe {
+                break;
+            }
+        }
+
+        remove_dead_lets(&mut out);
+        (out.into_iter().map(|(s, _)| s).collect(), returns)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, env: &mut Env, mut pe: bool, out: &mut Residual) -> bool {
+        self.span = stmt.span;
+        if pe {
+            if self.fuel.left == 0 {
+                self.run_out(stmt.span);
+                pe = false;
+            } else {
+                self.fuel.left -= 1;
+            }
+        }
+        let mut emit = |kind: StmtKind| {
+            let residual = Stmt {
+                kind,
+                attrs: stmt.attrs.clone(),
+                span: stmt.span,
+            };
+            out.push((residual, pe));
+        };
+
+        match &stmt.kind {
+            StmtKind::Let { name, init } => {
+                let init = self.expr_if(pe, init, env);
+                match init {
+                    Expr::Number(v) if pe => {
+                        env.insert(name.clone(), v);
+                    }
+                    _ => {
+                        env.remove(name);
+                    }
+                }
+                emit(StmtKind::Let {
+                    name: name.clone(),
+                    init,
+                });
+            }
+            StmtKind::QbitDecl { name, size } => {
+                let size = size.as_ref().map(|e| self.expr_if(pe, e, env));
+                env.remove(name);
+                emit(StmtKind::QbitDecl {
+                    name: name.clone(),
+                    size,
+                });
+            }
+            StmtKind::Measure { target, classical } => {
+                let target = self.qubit_if(pe, target, env);
+                if let Some(c) = classical {
+                    env.remove(c);
+                }
+                emit(StmtKind::Measure {
+                    target,
+                    classical: classical.clone(),
+                });
+            }
+            StmtKind::QOp {
+                gate,
+                params,
+                qubits,
+            } => {
+                let qubits = qubits.iter().map(|q| self.qubit_if(pe, q, env)).collect();
+                emit(StmtKind::QOp {
+                    gate: gate.clone(),
+                    params: params.clone(),
+                    qubits,
+                });
+            }
+            StmtKind::Reset { target } => {
+                let target = self.qubit_if(pe, target, env);
+                emit(StmtKind::Reset { target });
+            }
+            StmtKind::Return(expr) => {
+                let expr = expr.as_ref().map(|e| self.expr_if(pe, e, env));
+                emit(StmtKind::Return(expr));
+                return true;
+            }
+            StmtKind::Expr(expr) => {
+                let expr = self.expr_if(pe, expr, env);
+                // Without calls the value is simply discarded.
+                if !pe || expr.has_call() {
+                    emit(StmtKind::Expr(expr));
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let cond = self.expr_if(pe, cond, env);
+
+                if let (true, Expr::Number(v)) = (pe, &cond) {
+                    let taken = if *v != 0 {
+                        Some(then_body)
+                    } else {
+                        else_body.as_ref()
+                    };
+                    let Some(body) = taken else {
+                        return false;
+                    };
+                    let (residual, returns) = self.block(body, env, pe);
+                    splice(stmt, residual, out);
+                    return returns;
+                }
+
+                let (then_body, then_returns) = self.block(then_body, env, pe);
+                let (else_body, else_returns) = match else_body {
+                    Some(body) => {
+                        let (body, returns) = self.block(body, env, pe);
+                        (Some(body), returns)
+                    }
+                    None => (None, false),
+                };
+                let else_body = else_body.filter(|b| !b.is_empty() || !pe);
+
+                if pe && then_body.is_empty() && else_body.is_none() && !cond.has_call() {
+                    return false;
+                }
+                emit(StmtKind::If {
+                    cond,
+                    then_body,
+                    else_body,
+                });
+                return then_returns && else_returns;
+            }
+            StmtKind::Block(body) => {
+                let (residual, returns) = self.block(body, env, pe);
+                if pe {
+                    splice(stmt, residual, out);
+                } else {
+                    emit(StmtKind::Block(residual));
+                }
+                return returns;
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let start = self.expr_if(pe, start, env);
+                let end = self.expr_if(pe, end, env);
+
+                let mut pe_body = pe;
+                if pe {
+                    if let Some((first, trips)) = self.trip_count(&start, &end, stmt.span) {
+                        let unrolled = out.len();
+                        self.trail
+                            .push((stmt.span, "while unrolling this loop".to_string()));
+                        let mut returns = false;
+                        for i in 0..trips {
+                            let mut iter_env = env.clone();
+                            iter_env.insert(var.clone(), first + i as i64);
+                            let (residual, iter_returns) = self.block(body, &iter_env, pe);
+                            splice(stmt, residual, out);
+                            returns = iter_returns;
+                            if returns || self.fuel.out {
+                                break;
+                            }
+                        }
+                        self.trail.pop();
+                        if !self.fuel.out {
+                            return returns;
+                        }
+                        // Keep the loop as written instead.
+                        out.truncate(unrolled);
+                        pe_body = false;
+                    }
+                }
+
+                let mut body_env = env.clone();
+                body_env.remove(var);
+                let (body, _) = self.block(body, &body_env, pe_body);
+                let residual = Stmt {
+                    kind: StmtKind::For {
+                        var: var.clone(),
+                        start,
+                        end,
+                        body,
+                    },
+                    attrs: stmt.attrs.clone(),
+                    span: stmt.span,
+                };
+                out.push((residual, pe));
+            }
+        }
+        false
+    }
+
+    // The first value and number of iterations of a loop that can be
+    // unrolled, or a warning saying why it cannot.
+    fn trip_count(&mut self, start: &Expr, end: &Expr, span: Span) -> Option<(i64, u64)> {
+        let (Expr::Number(start), Expr::Number(end)) = (start, end) else {
+            let bound = if matches!(start, Expr::Number(_)) {
+                end
+            } else {
+                start
+            };
+            self.diags.push(Diagnostic::warning(
+                format!(
+                    "cannot unroll loop: its bound `{}` is only known at runtime",
+                    bound
+                ),
+                span,
+            ));
+            return None;
+        };
+
+        let trips = if end > start { end.abs_diff(*start) } else { 0 };
+        if trips > self.options.unroll_budget as u64 {
+            self.diags.push(Diagnostic::warning(
+                format!(
+                    "cannot unroll loop: it runs {} times, more than the unroll budget of {}",
+                    trips, self.options.unroll_budget
+                ),
+                span,
+            ));
+            return None;
+        }
+        Some((*start, trips))
+    }
+
+    fn qubit_if(&mut self, pe: bool, target: &QubitRef, env: &Env) -> QubitRef {
+        QubitRef {
+            name: target.name.clone(),
+            index: target.index.as_ref().map(|e| self.expr_if(pe, e, env)),
+        }
+    }
+
+    fn expr_if(&mut self, pe: bool, expr: &Expr, env: &Env) -> Expr {
+        if pe {
+            self.expr(expr, env)
+        } else {
+            expr.clone()
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, env: &Env) -> Expr {
+        match expr {
+            Expr::Number(_) => expr.clone(),
+            Expr::Var(name) => match env.get(name) {
+                Some(v) => Expr::Number(*v),
+                None => expr.clone(),
+            },
+            Expr::Call { callee, args } => {
+                let args: Vec<Expr> = args.iter().map(|a| self.expr(a, env)).collect();
+                self.call(callee, args)
+            }
+            Expr::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let left = self.expr(left, env);
+                let right = self.expr(right, env);
+                self.binary(*op, left, right, *span)
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &str, args: Vec<Expr>) -> Expr {
+        let key: Vec<Option<i64>> = args
+            .iter()
+            .map(|a| match a {
+                Expr::Number(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        let residual_call = |args| Expr::Call {
+            callee: callee.to_string(),
+            args,
+        };
+
+        if key.iter().all(Option::is_none) {
+            return residual_call(args);
+        }
+        let spec = match self.specialize(callee, key) {
+            Some(spec) => spec,
+            None => return residual_call(args),
+        };
+
+        // Arguments with calls in them may have quantum side effects, so the
+        // call is only folded away when it drops none of them.
+        let name = match spec {
+            Spec::Constant(v, _) if !args.iter().any(Expr::has_call) => return Expr::Number(v),
+            Spec::Constant(_, copy) => self.keep_copy(*copy),
+            Spec::Function(name) => name,
+        };
+        Expr::Call {
+            callee: name,
+            args: args
+                .into_iter()
+                .filter(|a| !matches!(a, Expr::Number(_)))
+                .collect(),
+        }
+    }
+
+    // A copy that folded to a constant, kept after all for a call that
+    // cannot drop its arguments.
+    fn keep_copy(&mut self, copy: Function) -> String {
+        let name = copy.name.clone();
+        if !self.specialized.iter().flatten().any(|f| f.name == name) {
+            self.specialized.push(Some(copy));
+        }
+        name
+    }
+
+    fn specialize(&mut self, callee: &str, key: Vec<Option<i64>>) -> Option<Spec> {
+        let func = self.originals.get(callee)?;
+        if !func.pe_enabled || func.params.len() != key.len() {
+            return None;
+        }
+
+        let memo_key = (callee.to_string(), key);
+        if let Some(spec) = self.specs.get(&memo_key) {
+            if let Spec::Function(name) = spec {
+                self.recursive.insert(name.clone());
+            }
+            return Some(spec.clone());
+        }
+
+        let count = self.counts.entry(callee.to_string()).or_insert(0);
+        if *count >= self.options.max_specializations {
+            if *count == self.options.max_specializations {
+                self.diags.push(Diagnostic::warning(
+                    format!(
+                        "stopped specializing `{}` after {} copies; remaining calls are left as they are",
+                        callee, self.options.max_specializations
+                    ),
+                    self.span,
+                ));
+            }
+            *count += 1;
+            return None;
+        }
+        *count += 1;
+
+        let func = func.clone();
+        let key = &memo_key.1;
+        let name = self.fresh_name(&func, key);
+
+        let mut env = Env::new();
+        let mut params = Vec::new();
+        for (param, value) in func.params.iter().zip(key) {
+            match value {
+                Some(v) => {
+                    env.insert(param.clone(), *v);
+                }
+                None => params.push(param.clone()),
+            }
+        }
+
+        // Registered before the body is evaluated so recursive calls with
+        // the same arguments reuse it.
+        let spec = Spec::Function(name.clone());
+        self.specs.insert(memo_key.clone(), spec);
+        let slot = self.specialized.len();
+        self.specialized.push(None);
+
+        let span = self.span;
+        let fuel = func.attrs.iter().find_map(|a| a.annotation.fuel());
+        self.trail.push((
+            span,
+            format!("while specializing this call to `{}`", callee),
+        ));
+        let (mut body, ran_out) = self.with_fuel(fuel, |pe| {
+            let (body, _) = pe.block(&func.body, &env, true);
+            (body, pe.fuel.out)
+        });
+        self.trail.pop();
+        self.span = span;
+
+        // Other copies may already call this one, so it is kept, with the
+        // literal arguments bound at the top of the original body.
+        if ran_out {
+            body = func
+                .params
+                .iter()
+                .zip(key)
+                .filter_map(|(param, value)| {
+                    Some(Stmt {
+                        kind: StmtKind::Let {
+                            name: param.clone(),
+                            init: Expr::Number((*value)?),
+                        },
+                        attrs: Vec::new(),
+                        span: func.span,
+                    })
+                })
+                .chain(func.body.iter().cloned())
+                .collect();
+        }
+
+        let folded = match body.as_slice() {
+            [Stmt {
+                kind: StmtKind::Return(Some(Expr::Number(v))),
+                ..
+            }] if !self.recursive.contains(&name) => Some(*v),
+            _ => None,
+        };
+        let copy = Function {
+            name: name.clone(),
+            params,
+            body,
+            ..func
+        };
+        let spec = match folded {
+            Some(v) => Spec::Constant(v, Box::new(copy)),
+            None => {
+                self.specialized[slot] = Some(copy);
+                Spec::Function(name)
+            }
+        };
+        self.specs.insert(memo_key, spec.clone());
+        Some(spec)
+    }
+
+    // `rotate` called with `n = 3` becomes `rotate_n3`; negative values
+    // use `m` for the sign.
+    fn fresh_name(&mut self, func: &Function, key: &[Option<i64>]) -> String {
+        let mut base = func.name.clone();
+        for (param, value) in func.params.iter().zip(key) {
+            if let Some(v) = value {
+                let sign = if *v < 0 { "m" } else { "" };
+                base.push_str(&format!("_{}{}{}", param, sign, v.unsigned_abs()));
+            }
+        }
+
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.names.contains(&name) {
+            name = format!("{}_{}", base, n);
+            n += 1;
+        }
+        self.names.insert(name.clone());
+        name
+    }
+
+    // Runs `f` on a budget of its own, when a `#[pe(fuel = N)]` gives one.
+    fn with_fuel<T>(&mut self, fuel: Option<u64>, f: impl FnOnce(&mut Self) -> T) -> T {
+        let Some(budget) = fuel else {
+            return f(self);
+        };
+        let outer = std::mem::replace(&mut self.fuel, Fuel::new(budget));
+        let result = f(self);
+        self.fuel = outer;
+        result
+    }
+
+    fn run_out(&mut self, span: Span) {
+        if self.fuel.out {
+            return;
+        }
+        self.fuel.out = true;
+        let mut diag = Diagnostic::warning(
+            format!(
+                "partial evaluation ran out of fuel after {} steps; the code being evaluated \
+                 is left as it is (raise the limit with `#[pe(fuel = N)]`)",
+                self.fuel.budget
+            ),
+            span,
+        );
+        for (span, note) in self.trail.iter().rev() {
+            diag = diag.with_note(*span, note.clone());
+        }
+        self.diags.push(diag);
+    }
+
+    // Folds known operands. Operations that would fail are left in place
+    // for `consteval` to report.
+    fn binary(&self, op: BinOp, left: Expr, right: Expr, span: Span) -> Expr {
+        if let (Expr::Number(a), Expr::Number(b)) = (&left, &right) {
+            if let Ok(v) = eval_binop(op, *a, *b, self.options.arith) {
+                return Expr::Number(v);
+            }
+        }
+
+        match (op, &left, &right) {
+            (BinOp::Add, Expr::Number(0), _) | (BinOp::Mul, Expr::Number(1), _) => right,
+            (BinOp::Add | BinOp::Sub | BinOp::Shl | BinOp::Shr, _, Expr::Number(0))
+            | (BinOp::Mul | BinOp::Div, _, Expr::Number(1)) => left,
+            (BinOp::Mul, Expr::Number(0), x) | (BinOp::Mul, x, Expr::Number(0))
+                if !x.has_call() =>
+            {
+                Expr::Number(0)
+            }
+            _ => Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+                span,
+            },
+        }
+    }
+}
+
+// Inlines the residual of a block that was evaluated away. Bindings made in
+// it must not leak into the enclosing scope, so it stays a block if it
+// declares anything.
+fn splice(stmt: &Stmt, residual: Vec<Stmt>, out: &mut Residual) {
+    let declares = residual.iter().any(|s| {
+        matches!(
+            s.kind,
+            StmtKind::Let { .. }
+                | StmtKind::QbitDecl { .. }
+                | StmtKind::Measure {
+                    classical: Some(_),
+                    ..
+                }
+        )
+    });
+
+    if declares {
+        let block = Stmt {
+            kind: StmtKind::Block(residual),
+            attrs: Vec::new(),
+            span: stmt.span,
+        };
+        out.push((block, true));
+    } else {
+        out.extend(residual.into_iter().map(|s| (s, true)));
+    }
+}
+
+// Drops `let`s made under PE whose value is no longer read, walking
+// backwards with the set of names that are still needed.
+fn remove_dead_lets(out: &mut Residual) {
+    let mut live: HashSet<String> = HashSet::new();
+    let mut keep = vec![true; out.len()];
+
+    for (i, (stmt, pe)) in out.iter().enumerate().rev() {
+        match &stmt.kind {
+            StmtKind::Let { name, init } => {
+                let needed = live.remove(name);
+                if !needed && *pe && !init.has_call() {
+                    keep[i] = false;
+                    continue;
+                }
+                init.for_each_var(&mut |v| {
+                    live.insert(v.to_string());
+                });
+            }
+            StmtKind::QbitDecl { name, size } => {
+                live.remove(name);
+                if let Some(size) = size {
+                    size.for_each_var(&mut |v| {
+                        live.insert(v.to_string());
+                    });
+                }
+            }
+            StmtKind::Measure {
+                target,
+                classical: Some(c),
+            } => {
+                live.remove(c);
+                target.for_each_var(&mut |v| {
+                    live.insert(v.to_string());
+                });
+            }
+            kind => kind.for_each_use(&mut |v| {
+                live.insert(v.to_string());
+            }),
+        }
+    }
+
+    let mut keep = keep.into_iter();
+    out.retain(|_| keep.next().unwrap());
+}
@@ -0,0 +1,122 @@
+use qxad::ast::Program;
+use qxad::bta::{analyze, render, BindingTime};
+use qxad::lexer::Lexer;
+use qxad::parser::Parser;
+
+fn parse(src: &str) -> Program {
+    let mut lex = Lexer::new(src);
+    Parser::new(&mut lex).parse_program().expect("parse failed")
+}
+
+#[test]
+fn test_constants_are_static_and_measurements_dynamic() {
+    let src = r#"
+        fn main() {
+            let x = 1 + 2 * 3;
+            qbit q;
+            measure q -> c;
+            let y = x + c;
+            return x;
+        }
+    "#;
+
+    let program = parse(src);
+    let bta = analyze(&program);
+    assert!(bta.diags.is_empty());
+
+    let main = &bta.functions["main"];
+    let body = &program.functions[0].body;
+    assert!(main.stmts[&body[0].span].is_static());
+    match &main.stmts[&body[3].span] {
+        BindingTime::Dynamic(cause) => assert_eq!(cause.span, body[2].span),
+        BindingTime::Static => panic!("`y` depends on a measurement"),
+    }
+    assert!(main.ret.is_static());
+}
+
+#[test]
+fn test_parameters_join_over_call_sites() {
+    let src = r#"
+        fn double(v) { return v + v; }
+        fn twice(v) { return v * 2; }
+        fn main(n) {
+            let a = double(3);
+            let b = twice(4);
+            let c = twice(n);
+            return a + b + c;
+        }
+    "#;
+
+    let bta = analyze(&parse(src));
+    assert!(bta.functions["double"].params[0].is_static());
+    assert!(bta.functions["double"].ret.is_static());
+    assert!(!bta.functions["twice"].params[0].is_static());
+    assert!(!bta.functions["main"].params[0].is_static());
+}
+
+#[test]
+fn test_static_annotation_conflicts() {
+    let src = r#"
+        #[static]
+        fn table(k) { return k * 4; }
+
+        fn main(n) {
+            qbit q;
+            H(q);
+            measure q -> c;
+            #[static]
+            let s = c + 1;
+            let t = table(n);
+            return s + t;
+        }
+    "#;
+
+    let bta = analyze(&parse(src));
+    assert_eq!(bta.diags.len(), 2);
+    assert_eq!(bta.diags[0].span.line, 9);
+    assert_eq!(bta.diags[0].notes[0].0.line, 8);
+    assert!(bta.diags[1].message.contains("`table` is #[static]"));
+}
+
+#[test]
+fn test_static_measurement_conflicts() {
+    let src = r#"
+        fn main() {
+            qbit q;
+            #[static]
+            measure q -> c;
+            return c;
+        }
+    "#;
+
+    let bta = analyze(&parse(src));
+    assert_eq!(bta.diags.len(), 1);
+    assert_eq!(bta.diags[0].span.line, 4);
+    assert_eq!(
+        bta.diags[0].message,
+        "`c` is marked #[static] but is only known at runtime"
+    );
+    assert_eq!(bta.diags[0].notes[0].0.line, 5);
+}
+
+#[test]
+fn test_dynamic_annotation_and_render() {
+    let src = r#"
+        fn main() {
+            #[dynamic]
+            let x = 1;
+            let y = x + 1;
+            return y;
+        }
+    "#;
+
+    let program = parse(src);
+    let bta = analyze(&program);
+    assert!(!bta.functions["main"].ret.is_static());
+
+    let out = render(&program, &bta);
+    assert!(out.starts_with("fn main() {"));
+    assert!(out.lines().next().unwrap().ends_with("// returns dynamic"));
+    assert!(out.contains("    #[dynamic]\n    let x = 1;"));
+    assert!(out.contains("// dynamic: `x` is marked #[dynamic] (3:13)"));
+}
@@ -0,0 +1,1005 @@
+use qxad::ast::{Angle, QubitRef, StmtKind};
+use qxad::circuit::basis::{self, equivalences};
+use qxad::circuit::pass::preset;
+use qxad::circuit::phase::t_count;
+use qxad::circuit::rewrite::{identities, RuleCounts};
+use qxad::circuit::rules::{self, Rule};
+use qxad::circuit::toffoli::{decompose_toffolis, CcxMode, McxMode, ToffoliOptions};
+use qxad::circuit::unitary::{same_up_to_phase, unitary};
+use qxad::circuit::zx;
+use qxad::circuit::{
+    cancel, cancel_counting, fuse, map_runs, phase_polynomials, Dag, Op, OpKind, Optimizer, Pass,
+    PassManager, PassStats, PhaseCounts, Target, Wire,
+};
+use qxad::lexer::{Lexer, Span};
+use qxad::parser::Parser;
+
+// The operations of the first run in `main`.
+fn ops(src: &str) -> Vec<Op> {
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    let mut first = None;
+    map_runs(&program.functions[0].body, &mut |ops| {
+        first.get_or_insert_with(|| ops.clone());
+        ops
+    });
+    first.expect("no quantum operations")
+}
+
+fn qubit(name: &str) -> Wire {
+    Wire::Qubit(name.to_string())
+}
+
+#[test]
+fn test_neighbours_per_wire() {
+    let src = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            H(a);
+            X(b);
+            Y(a);
+            CX(a, b);
+        }
+    "#;
+
+    let dag = Dag::from_ops(ops(src));
+    let on_a = dag.on_wire(&qubit("a"));
+    let on_b = dag.on_wire(&qubit("b"));
+    assert_eq!(on_a.len(), 3);
+    assert_eq!(on_b.len(), 2);
+
+    let (h, y, cx) = (on_a[0], on_a[1], on_a[2]);
+    assert_eq!(dag.successor(h, &qubit("a")), Some(y));
+    assert_eq!(dag.predecessor(y, &qubit("a")), Some(h));
+    assert_eq!(dag.predecessor(h, &qubit("b")), None);
+    assert_eq!(dag.predecessor(cx, &qubit("b")), Some(on_b[0]));
+    assert_eq!(dag.predecessors(cx), vec![on_b[0], y]);
+    assert_eq!(dag.op(cx).to_string(), "CX(a, b)");
+}
+
+#[test]
+fn test_layers_and_depth() {
+    let src = r#"
+        fn main() {
+            qbit r[3];
+            H(r[0]);
+            H(r[1]);
+            CX(r[0], r[1]);
+            X(r[2]);
+            measure r[1] -> c;
+        }
+    "#;
+
+    let dag = Dag::from_ops(ops(src));
+    assert_eq!(dag.depth(), 3);
+    assert_eq!(
+        dag.to_string(),
+        "0: H(r[0]) H(r[1]) X(r[2])\n1: CX(r[0], r[1])\n2: measure r[1] -> c\n"
+    );
+    assert_eq!(dag.on_wire(&Wire::Bit("c".to_string())).len(), 1);
+}
+
+#[test]
+fn test_remove_joins_neighbours() {
+    let src = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            H(a);
+            CX(a, b);
+            H(a);
+            Z(b);
+        }
+    "#;
+
+    let mut dag = Dag::from_ops(ops(src));
+    let on_a = dag.on_wire(&qubit("a"));
+    let removed = dag.remove(on_a[1]);
+    assert_eq!(removed.name(), "CX");
+    assert!(!dag.contains(on_a[1]));
+
+    assert_eq!(dag.successor(on_a[0], &qubit("a")), Some(on_a[2]));
+    assert_eq!(dag.on_wire(&qubit("b")).len(), 1);
+    assert_eq!(dag.depth(), 2);
+
+    let ops = dag.to_ops();
+    let names: Vec<&str> = ops.iter().map(|op| op.name()).collect();
+    assert_eq!(names, vec!["H", "H", "Z"]);
+}
+
+#[test]
+fn test_round_trip_and_run_boundaries() {
+    let src = r#"
+        fn main(n) {
+            qbit r[n];
+            H(r[0]);
+            X(r[1]);
+            H(r[n]);
+            Z(r[0]);
+        }
+    "#;
+
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    let mut runs = Vec::new();
+    let body = map_runs(&program.functions[0].body, &mut |ops| {
+        runs.push(ops.len());
+        Dag::from_ops(ops).to_ops()
+    });
+
+    // `r[n]` is not a fixed qubit, so it splits the gates into two runs.
+    assert_eq!(runs, vec![2, 1]);
+    assert_eq!(body, program.functions[0].body);
+    assert!(matches!(body[2].kind, StmtKind::QOp { .. }));
+}
+
+fn cancelled(src: &str) -> Vec<String> {
+    cancel(ops(src)).iter().map(|op| op.to_string()).collect()
+}
+
+#[test]
+fn test_cancel_walks_past_commuting_gates() {
+    let through_control = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            Z(a);
+            CX(a, b);
+            Z(a);
+        }
+    "#;
+    assert_eq!(cancelled(through_control), ["CX(a, b)"]);
+
+    let through_target = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            X(b);
+            CX(a, b);
+            CX(a, b);
+            X(b);
+            H(a);
+        }
+    "#;
+    assert_eq!(cancelled(through_target), ["H(a)"]);
+
+    let inverse_pairs = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            S(a);
+            T(a);
+            CX(a, b);
+            Sdg(a);
+            CX(b, a);
+        }
+    "#;
+    assert_eq!(cancelled(inverse_pairs), ["T(a)", "CX(a, b)", "CX(b, a)"]);
+
+    let blocked = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            X(a);
+            CX(a, b);
+            X(a);
+        }
+    "#;
+    assert_eq!(cancelled(blocked), ["X(a)", "CX(a, b)", "X(a)"]);
+}
+
+fn optimized(src: &str, passes: Vec<Pass>) -> (Vec<String>, Vec<PassStats>) {
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    let mut manager = PassManager::new(passes, Target::default(), identities());
+    let program = manager.run_program(&program).unwrap();
+    let body = program.functions[0]
+        .body
+        .iter()
+        .map(|s| s.kind.to_string())
+        .collect();
+    (body, manager.stats().to_vec())
+}
+
+#[test]
+fn test_cancel_pass_cancels_past_other_qubits() {
+    let src = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            H(a);
+            X(b);
+            T(a);
+            Tdg(a);
+            H(a);
+        }
+    "#;
+    let (body, stats) = optimized(src, preset(1, Optimizer::Peephole));
+    assert_eq!(body, ["qbit a;", "qbit b;", "X(b);"]);
+    assert_eq!(stats.len(), 1);
+    assert_eq!((stats[0].gates_before, stats[0].gates_after), (5, 1));
+    assert_eq!((stats[0].depth_before, stats[0].depth_after), (4, 1));
+
+    // Level 0 runs nothing, and `#[nope]` code is left alone at any level.
+    let (body, stats) = optimized(src, preset(0, Optimizer::Peephole));
+    assert_eq!(body.len(), 7);
+    assert!(stats.is_empty());
+    let nope = src.replace("fn main", "#[nope]\n        fn main");
+    assert_eq!(optimized(&nope, preset(3, Optimizer::Peephole)).0.len(), 7);
+}
+
+#[test]
+fn test_stats_count_only_gates() {
+    let src = r#"
+        fn main() {
+            qbit q;
+            H(q);
+            H(q);
+            measure q -> c;
+            reset q;
+        }
+    "#;
+    let (_, stats) = optimized(src, vec![Pass::Cancel]);
+    assert_eq!((stats[0].gates_before, stats[0].gates_after), (2, 0));
+}
+
+#[test]
+fn test_pass_lists_and_presets() {
+    let passes: Vec<Pass> = ["zx", "cancel", "phase", "fuse"]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+    assert_eq!(preset(2, Optimizer::Zx), passes);
+    assert_eq!(preset(1, Optimizer::Zx), [Pass::Zx, Pass::Cancel]);
+    assert_eq!(preset(3, Optimizer::Zx)[0], Pass::Zx);
+    // `--opt` picks the optimizer at every level.
+    let peephole = preset(3, Optimizer::Peephole);
+    assert!(!peephole.contains(&Pass::Zx), "{:?}", peephole);
+    assert_eq!(peephole[..3], passes[1..]);
+    let err = "dce".parse::<Pass>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown pass `dce` (expected zx, cancel, phase, fuse, toffoli or basis)"
+    );
+
+    // Each pass starts from what the one before it left.
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            H(q0);
+            H(q0);
+            CX(q0, q1);
+            T(q1);
+            CX(q0, q1);
+            CX(q0, q1);
+            T(q1);
+            CX(q0, q1);
+            X(q0);
+            Z(q0);
+        }
+    "#;
+    let (_, stats) = optimized(src, preset(2, Optimizer::Peephole));
+    assert_eq!(stats[0].gates_before, 10);
+    for pair in stats.windows(2) {
+        assert_eq!(pair[0].gates_after, pair[1].gates_before);
+        assert_eq!(pair[0].depth_after, pair[1].depth_before);
+    }
+    assert!(stats[2].gates_after < 10);
+    let line = stats[0].to_string();
+    assert!(line.starts_with("cancel: "), "{}", line);
+}
+
+// The wires of `ops` in the order they first appear.
+fn wires(ops: &[Op]) -> Vec<Wire> {
+    let mut wires: Vec<Wire> = Vec::new();
+    for op in ops {
+        for wire in op.wires() {
+            if !wires.contains(&wire) {
+                wires.push(wire);
+            }
+        }
+    }
+    wires
+}
+
+fn assert_same_up_to_phase(circuit: &[Op], reduced: &[Op]) {
+    let wires = wires(&[circuit, reduced].concat());
+    let before = unitary(circuit, &wires).unwrap();
+    let after = unitary(reduced, &wires).unwrap();
+    assert!(
+        same_up_to_phase(&before, &after),
+        "{:?} became {:?}",
+        circuit.iter().map(Op::to_string).collect::<Vec<_>>(),
+        reduced.iter().map(Op::to_string).collect::<Vec<_>>()
+    );
+}
+
+// Xorshift, so that random tests are the same on every run.
+fn rng(mut seed: u64) -> impl FnMut(usize) -> usize {
+    move |n| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % n as u64) as usize
+    }
+}
+
+// Gates drawn from `gates` on distinct qubits among `q0` and the next
+// `qubits - 1`, rotations by multiples of π/4 or by any angle.
+fn random_circuit(gates: &[&str], qubits: usize, next: &mut impl FnMut(usize) -> usize) -> Vec<Op> {
+    let len = 4 + next(24);
+    (0..len)
+        .map(|_| {
+            let gate = gates[next(gates.len())];
+            let arity = match gate {
+                "CX" | "CNOT" | "SWAP" => 2,
+                "CCX" => 3,
+                _ => 1,
+            };
+            let mut picked: Vec<usize> = Vec::new();
+            while picked.len() < arity {
+                let q = next(qubits);
+                if !picked.contains(&q) {
+                    picked.push(q);
+                }
+            }
+            let picked = picked
+                .iter()
+                .map(|q| QubitRef::new(format!("q{}", q)))
+                .collect();
+            let mut op = Op::gate(gate, picked, Span::default());
+            if let OpKind::Gate { params, .. } = &mut op.kind {
+                if gate.starts_with('R') {
+                    params.push(match next(2) {
+                        0 => Angle::pi(next(8) as i64, 4),
+                        _ => Angle::radians(next(1000) as f64 / 100.0),
+                    });
+                }
+            }
+            op
+        })
+        .collect()
+}
+
+#[test]
+fn test_cancel_keeps_the_unitary_of_random_circuits() {
+    let gates = [
+        "H", "X", "Y", "Z", "S", "Sdg", "T", "Tdg", "CX", "CNOT", "CCX", "SWAP", "RX", "RY", "RZ",
+        "RZ",
+    ];
+    let mut next = rng(0x2545_f491_4f6c_dd1d);
+
+    let mut removed = 0;
+    for _ in 0..300 {
+        let circuit = random_circuit(&gates, 3, &mut next);
+        let reduced = cancel(circuit.clone());
+        removed += circuit.len() - reduced.len();
+        // Rotations by 2π are dropped, which changes the global phase.
+        assert_same_up_to_phase(&circuit, &reduced);
+    }
+    assert!(removed > 100, "only {} gates cancelled", removed);
+}
+
+#[test]
+fn test_rotations_merge_exactly() {
+    let src = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            RZ(pi/4, a);
+            RZ(pi/4, a);
+            CX(a, b);
+            RZ(pi/4, a);
+            RZ(pi/4, a);
+            RZ(0.25 * pi, a);
+            RX(pi/2, b);
+            RZ(pi - pi/4, a);
+            RX(-pi, b);
+            RY(1, a);
+            RY(2 * pi - 1, a);
+            H(a);
+            RZ(5 * pi / 2, a);
+        }
+    "#;
+
+    assert_eq!(
+        cancelled(src),
+        ["CX(a, b)", "RX(-pi/2, b)", "H(a)", "RZ(pi/2, a)"]
+    );
+}
+
+#[test]
+fn test_identities_rewrite_until_nothing_changes() {
+    let src = r#"
+        fn main() {
+            qbit r[2];
+            H(r[0]);
+            X(r[1]);
+            X(r[0]);
+            H(r[0]);
+            S(r[1]);
+            S(r[1]);
+            T(r[1]);
+            T(r[1]);
+            H(r[1]);
+        }
+    "#;
+
+    let mut counts = RuleCounts::new();
+    let ops: Vec<String> =
+        cancel_counting(ops(src), &Target::default(), &identities(), &mut counts)
+            .iter()
+            .map(Op::to_string)
+            .collect();
+    assert_eq!(ops, ["Z(r[0])", "Y(r[1])", "S(r[1])", "H(r[1])"]);
+    assert_eq!(counts.get("H(a); X(a); H(a) => Z(a)"), 2);
+    assert_eq!(counts.get("S(a); S(a) => Z(a)"), 1);
+    assert_eq!(counts.get("T(a); T(a) => S(a)"), 1);
+    assert_eq!(counts.get("X(a); Z(a) => Y(a)"), 1);
+    assert_eq!(counts.total(), 5);
+    assert_eq!(
+        counts.to_string(),
+        concat!(
+            "H(a); X(a); H(a) => Z(a): 2 gates removed\n",
+            "S(a); S(a) => Z(a): 1 gate removed\n",
+            "T(a); T(a) => S(a): 1 gate removed\n",
+            "X(a); Z(a) => Y(a): 1 gate removed\n",
+        )
+    );
+}
+
+#[test]
+fn test_multi_qubit_gates_cancel_and_commute() {
+    let spellings = r#"
+        fn main() {
+            qbit r[3];
+            CX(r[0], r[1]);
+            CCX(r[0], r[1], r[2]);
+            CCX(r[1], r[0], r[2]);
+            CNOT(r[0], r[1]);
+        }
+    "#;
+    assert!(cancelled(spellings).is_empty());
+
+    let shared_control = r#"
+        fn main() {
+            qbit r[3];
+            CX(r[0], r[1]);
+            CX(r[0], r[2]);
+            CCX(r[0], r[2], r[1]);
+            CX(r[0], r[1]);
+        }
+    "#;
+    assert_eq!(
+        cancelled(shared_control),
+        ["CX(r[0], r[2])", "CCX(r[0], r[2], r[1])"]
+    );
+
+    let targets_differ = r#"
+        fn main() {
+            qbit r[3];
+            CCX(r[0], r[1], r[2]);
+            CCX(r[0], r[2], r[1]);
+        }
+    "#;
+    assert_eq!(cancelled(targets_differ).len(), 2);
+}
+
+#[test]
+fn test_three_cnots_become_a_swap_when_the_target_has_one() {
+    let src = r#"
+        fn main() {
+            qbit a;
+            qbit b;
+            CX(a, b);
+            CNOT(b, a);
+            CX(a, b);
+            H(a);
+        }
+    "#;
+
+    let mut counts = RuleCounts::new();
+    let swapped: Vec<String> =
+        cancel_counting(ops(src), &Target::default(), &identities(), &mut counts)
+            .iter()
+            .map(Op::to_string)
+            .collect();
+    assert_eq!(swapped, ["SWAP(a, b)", "H(a)"]);
+
+    let target = Target::with_basis(&["H".to_string(), "CX".to_string()]).unwrap();
+    let kept = cancel_counting(ops(src), &target, &identities(), &mut counts);
+    assert_eq!(kept.len(), 4);
+
+    assert!(Target::with_basis(&["FOO".to_string()]).is_err());
+}
+
+fn names(ops: &[Op]) -> Vec<&str> {
+    ops.iter().map(Op::name).collect()
+}
+
+#[test]
+fn test_single_qubit_runs_fuse_into_one_gate() {
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            H(q0);
+            T(q0);
+            X(q1);
+            H(q0);
+            S(q0);
+            CX(q0, q1);
+            T(q1);
+        }
+    "#;
+    let circuit = ops(src);
+
+    let fused = fuse(circuit.clone(), &Target::default());
+    assert_eq!(names(&fused), ["U3", "X", "CX", "T"]);
+    assert_same_up_to_phase(&circuit, &fused);
+
+    let zyz = Target::with_basis(&["RZ".to_string(), "RY".to_string(), "CX".to_string()]).unwrap();
+    let fused = fuse(circuit.clone(), &zyz);
+    assert!(names(&fused).iter().filter(|n| n.starts_with('R')).count() <= 3);
+    assert_same_up_to_phase(&circuit, &fused);
+
+    // Nothing to fuse into on a target without U3 or RZ and RY.
+    let clifford = Target::with_basis(&[
+        "H".to_string(),
+        "S".to_string(),
+        "T".to_string(),
+        "X".to_string(),
+        "CX".to_string(),
+    ])
+    .unwrap();
+    assert_eq!(fuse(circuit.clone(), &clifford), circuit);
+}
+
+#[test]
+fn test_fusion_keeps_the_unitary_of_random_runs() {
+    let gates = [
+        "H", "X", "Y", "Z", "S", "Sdg", "T", "Tdg", "RX", "RY", "RZ", "CX",
+    ];
+    let zyz = Target::with_basis(&["RZ".to_string(), "RY".to_string(), "CX".to_string()]).unwrap();
+    let mut next = rng(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..200 {
+        let circuit = random_circuit(&gates, 2, &mut next);
+        for target in [&Target::default(), &zyz] {
+            let fused = fuse(circuit.clone(), target);
+            assert!(fused.len() <= circuit.len());
+            assert_same_up_to_phase(&circuit, &fused);
+        }
+    }
+}
+
+#[test]
+fn test_rules_file_rewrites_multi_qubit_patterns() {
+    let path = std::env::temp_dir().join("qxad_test_rules.txt");
+    std::fs::write(
+        &path,
+        "// Hadamards turn a CX around.\n\
+         H(a); H(b); CX(a, b); H(a); H(b) => CX(b, a)\n\
+         \n\
+         RZ(x, a); X(a); RZ(y, a) => X(a); RZ(y - x, a)\n",
+    )
+    .unwrap();
+    let mut rules = identities();
+    rules.extend(rules::load(&path).unwrap());
+
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            H(q0);
+            H(q1);
+            CX(q0, q1);
+            H(q1);
+            H(q0);
+            RZ(pi/4, q1);
+            X(q1);
+            RZ(pi/2, q1);
+        }
+    "#;
+    let mut counts = RuleCounts::new();
+    let circuit = ops(src);
+    let rewritten = cancel_counting(circuit.clone(), &Target::default(), &rules, &mut counts);
+    let text: Vec<String> = rewritten.iter().map(Op::to_string).collect();
+    assert_eq!(text, ["CX(q1, q0)", "X(q1)", "RZ(pi/4, q1)"]);
+    assert_eq!(
+        counts.get("H(a); H(b); CX(a, b); H(a); H(b) => CX(b, a)"),
+        4
+    );
+    assert_same_up_to_phase(&circuit, &rewritten);
+}
+
+#[test]
+fn test_rules_are_checked_when_loaded() {
+    let sound = Rule::parse("RX(x, a); RY(pi, a); RX(x, a) => RY(pi, a)").unwrap();
+    assert!(sound.check().is_ok());
+    assert_eq!(
+        sound.to_string(),
+        "RX(x, a); RY(pi, a); RX(x, a) => RY(pi, a)"
+    );
+
+    let unsound = Rule::parse("H(a); S(a) => S(a)").unwrap();
+    assert!(unsound.check().is_err());
+
+    let errors = [
+        ("H(a) => FOO(a)", "unknown gate `FOO`"),
+        (
+            "H(a); H(b) => X(a)",
+            "`H(b)` shares no qubit with the other gates",
+        ),
+        (
+            "CX(a, b); X(a) => Y(c)",
+            "qubit `c` is not on the left side of the rule",
+        ),
+        (
+            "RZ(x + y, a); X(a) => X(a)",
+            "angles on the left side of a rule are names or constants, found `x + y`",
+        ),
+        (
+            "H(a) => X(a)",
+            "the right side of a rule must have fewer gates than the left",
+        ),
+        ("CX(a, a); X(a) =>", "`CX` uses qubit `a` twice"),
+    ];
+    for (text, message) in errors {
+        let err = Rule::parse(text).unwrap_err();
+        assert_eq!(err.to_string(), message, "{}", text);
+    }
+
+    let path = std::env::temp_dir().join("qxad_test_bad_rules.txt");
+    std::fs::write(&path, "H(a); H(a) =>\nT(a); T(a) => Z(a)\n").unwrap();
+    let err = rules::load(&path).unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        format!(
+            "{}:2: the two sides of the rule are different unitaries",
+            path.display()
+        )
+    );
+
+    for rule in identities() {
+        assert!(rule.check().is_ok(), "{}", rule);
+    }
+}
+
+#[test]
+fn test_phase_polynomials_merge_phases_on_equal_parities() {
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            qbit q2;
+            CX(q0, q1);
+            T(q1);
+            CX(q0, q1);
+            H(q2);
+            CX(q0, q1);
+            T(q1);
+            CX(q0, q1);
+            T(q2);
+            H(q2);
+            T(q2);
+        }
+    "#;
+    let mut counts = PhaseCounts::new();
+    let circuit = ops(src);
+    let optimized = phase_polynomials(circuit.clone(), &Target::default(), &mut counts);
+    let text: Vec<String> = optimized.iter().map(Op::to_string).collect();
+    assert_eq!(
+        text,
+        [
+            "H(q2)",
+            "T(q2)",
+            "H(q2)",
+            "T(q2)",
+            "CX(q0, q1)",
+            "S(q1)",
+            "CX(q0, q1)"
+        ]
+    );
+    assert_eq!(counts.to_string(), "T-count: 4 -> 2\nCX-count: 4 -> 2\n");
+    assert_same_up_to_phase(&circuit, &optimized);
+}
+
+#[test]
+fn test_phase_polynomials_keep_the_unitary_of_random_circuits() {
+    let gates = ["CX", "CX", "CX", "T", "T", "Tdg", "S", "Z", "RZ", "H", "X"];
+    let mut next = rng(0x0123_4567_89ab_cdef);
+
+    let mut counts = PhaseCounts::new();
+    for _ in 0..200 {
+        let circuit = random_circuit(&gates, 3, &mut next);
+        let optimized = phase_polynomials(circuit.clone(), &Target::default(), &mut counts);
+        assert!(t_count(&optimized) <= t_count(&circuit));
+        assert_same_up_to_phase(&circuit, &optimized);
+    }
+    assert!(counts.t_after < counts.t_before);
+    assert!(counts.cx_after <= counts.cx_before);
+}
+
+const ZX_GATES: &[&str] = &[
+    "H", "X", "Z", "S", "Sdg", "T", "Tdg", "RZ", "CX", "CX", "CX", "H",
+];
+
+#[test]
+fn test_zx_extraction_keeps_the_unitary_of_random_circuits() {
+    let mut next = rng(0x5851_f42d_4c95_7f2d);
+    for _ in 0..200 {
+        let circuit = random_circuit(ZX_GATES, 3, &mut next);
+        let extracted = zx::simplify(&circuit, &Target::default()).unwrap();
+        assert_same_up_to_phase(&circuit, &extracted);
+    }
+}
+
+#[test]
+fn test_zx_removes_more_t_gates_than_the_peephole_pipeline() {
+    let mut next = rng(0x2545_f491_4f6c_dd1d);
+    let target = Target::default();
+    let (mut peephole_t, mut zx_t) = (0, 0);
+    for _ in 0..200 {
+        let circuit = random_circuit(ZX_GATES, 3, &mut next);
+        let peephole = cancel_counting(
+            circuit.clone(),
+            &target,
+            &identities(),
+            &mut RuleCounts::new(),
+        );
+        let peephole = phase_polynomials(peephole, &target, &mut PhaseCounts::new());
+        let optimized = zx::optimize(circuit.clone(), &target);
+        assert!(t_count(&optimized) <= t_count(&circuit));
+        assert_same_up_to_phase(&circuit, &peephole);
+        assert_same_up_to_phase(&circuit, &optimized);
+        peephole_t += t_count(&peephole);
+        zx_t += t_count(&optimized);
+    }
+    assert!(zx_t < peephole_t);
+}
+
+#[test]
+fn test_equivalences_are_sound() {
+    for rule in equivalences() {
+        assert!(rule.check().is_ok(), "{}", rule);
+    }
+}
+
+#[test]
+fn test_translation_into_native_gate_sets() {
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            qbit q2;
+            H(q0);
+            Y(q1);
+            CCX(q0, q1, q2);
+            SWAP(q0, q2);
+            U3(pi/2, pi/4, -pi/4, q1);
+            RX(pi/4, q2);
+            CZ(q1, q2);
+            SXdg(q0);
+            Tdg(q1);
+        }
+    "#;
+    let circuit = ops(src);
+    let bases: [&[&str]; 3] = [
+        &["RZ", "SX", "X", "CX"],
+        &["RZ", "RX", "CZ"],
+        &["H", "S", "Sdg", "T", "Tdg", "CX"],
+    ];
+    for basis in bases {
+        let basis: Vec<String> = basis.iter().map(|g| g.to_string()).collect();
+        let target = Target::with_basis(&basis).unwrap();
+        let translated = basis::translate(circuit.clone(), &target, &equivalences()).unwrap();
+        for op in &translated {
+            assert!(
+                basis.iter().any(|g| g == op.name()),
+                "{} in {:?}",
+                op,
+                basis
+            );
+        }
+        assert_same_up_to_phase(&circuit, &translated);
+    }
+}
+
+#[test]
+fn test_translation_fails_for_gates_outside_the_basis() {
+    let clifford_t: Vec<String> = ["H", "S", "T", "CX"].map(String::from).to_vec();
+    let src = r#"
+        fn main() {
+            qbit q0;
+            RZ(pi/4, q0);
+            RZ(0.3, q0);
+        }
+    "#;
+    let target = Target::with_basis(&clifford_t).unwrap();
+    let err = basis::translate(ops(src), &target, &equivalences()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "5:13: cannot express `RZ(0.3, q0)` in the basis H, S, T, CX"
+    );
+
+    let single: Vec<String> = ["RZ", "SX"].map(String::from).to_vec();
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            X(q0);
+            CX(q0, q1);
+        }
+    "#;
+    let target = Target::with_basis(&single).unwrap();
+    let err = basis::translate(ops(src), &target, &equivalences()).unwrap_err();
+    assert!(
+        err.to_string().contains("cannot express `CX(q0, q1)`"),
+        "{}",
+        err
+    );
+}
+
+fn toffolis(circuit: &[Op], ccx: CcxMode, mcx: McxMode) -> Vec<Op> {
+    let options = ToffoliOptions { ccx, mcx };
+    decompose_toffolis(circuit.to_vec(), &Target::default(), &options).unwrap()
+}
+
+#[test]
+fn test_ccx_decompositions() {
+    let exact = toffolis(&ops(CCX_ONLY), CcxMode::Exact, McxMode::Ancillae);
+    assert_eq!(t_count(&exact), 7);
+    assert_same_up_to_phase(&ops(CCX_ONLY), &exact);
+
+    // The second CCX undoes the phase of the first, as long as only gates
+    // diagonal on its qubits come in between.
+    let src = r#"
+        fn main() {
+            qbit q0;
+            qbit q1;
+            qbit q2;
+            H(q0);
+            H(q1);
+            CCX(q0, q1, q2);
+            T(q2);
+            S(q0);
+            Tdg(q1);
+            CCX(q0, q1, q2);
+        }
+    "#;
+    let circuit = ops(src);
+    let relative = toffolis(&circuit, CcxMode::RelativePhase, McxMode::Ancillae);
+    assert_eq!(t_count(&relative), 8 + 2);
+    assert_same_up_to_phase(&circuit, &relative);
+
+    // An H in between does not commute with the phase.
+    let blocked = src.replacen("S(q0);", "H(q0);", 1);
+    let circuit = ops(&blocked);
+    let exact = toffolis(&circuit, CcxMode::RelativePhase, McxMode::Ancillae);
+    assert_eq!(t_count(&exact), 14 + 2);
+    assert_same_up_to_phase(&circuit, &exact);
+}
+
+const CCX_ONLY: &str = r#"
+    fn main() {
+        qbit q0;
+        qbit q1;
+        qbit q2;
+        CCX(q0, q1, q2);
+    }
+"#;
+
+#[test]
+fn test_mcx_decompositions() {
+    let src = r#"
+        fn main() {
+            qbit c0;
+            qbit c1;
+            qbit c2;
+            qbit c3;
+            qbit t;
+            qbit a0;
+            qbit a1;
+            H(a0);
+            T(a1);
+            MCX(c0, c1, c2, t);
+            MCX(c0, c1, c2, c3, t);
+        }
+    "#;
+    let circuit = ops(src);
+    let wires = wires(&circuit);
+    let expected = unitary(&circuit, &wires).unwrap();
+    for mcx in [McxMode::Ancillae, McxMode::NoAncillae] {
+        for ccx in [CcxMode::Exact, CcxMode::RelativePhase] {
+            let decomposed = toffolis(&circuit, ccx, mcx);
+            assert!(decomposed.iter().all(|op| op.qubits().len() <= 2));
+            let actual = unitary(&decomposed, &wires).unwrap();
+            assert!(same_up_to_phase(&expected, &actual), "{:?} {:?}", mcx, ccx);
+        }
+    }
+
+    // Ladders borrow idle qubits, 4 CCX for each one.
+    let options = ToffoliOptions::default();
+    let target = Target::with_basis(&["CCX".to_string(), "H".to_string()]).unwrap();
+    let ladder = decompose_toffolis(circuit.clone(), &target, &options).unwrap();
+    let ccx = ladder.iter().filter(|op| op.name() == "CCX").count();
+    assert_eq!(ccx, 4 + 8);
+    let exact = toffolis(&circuit, CcxMode::Exact, McxMode::Ancillae);
+    let relative = toffolis(&circuit, CcxMode::RelativePhase, McxMode::Ancillae);
+    assert!(t_count(&relative) < t_count(&exact));
+
+    let parity = toffolis(&circuit, CcxMode::Exact, McxMode::NoAncillae);
+    assert!(parity.iter().all(|op| op.name() != "CCX"));
+}
+
+#[test]
+fn test_mcx_borrows_a_single_idle_qubit() {
+    let src = r#"
+        fn main() {
+            qbit c0;
+            qbit c1;
+            qbit c2;
+            qbit c3;
+            qbit c4;
+            qbit t;
+            qbit a;
+            H(a);
+            MCX(c0, c1, c2, c3, c4, t);
+        }
+    "#;
+    let circuit = ops(src);
+    let wires = wires(&circuit);
+    let expected = unitary(&circuit, &wires).unwrap();
+    let decomposed = toffolis(&circuit, CcxMode::Exact, McxMode::Ancillae);
+    let actual = unitary(&decomposed, &wires).unwrap();
+    assert!(same_up_to_phase(&expected, &actual));
+
+    // Two halves of 3 and 3 controls, each a ladder of 4 CCX, run twice.
+    let target = Target::with_basis(&["CCX".to_string(), "H".to_string()]).unwrap();
+    let split = decompose_toffolis(circuit, &target, &ToffoliOptions::default()).unwrap();
+    assert!(split[1..].iter().all(|op| op.name() == "CCX"));
+    assert_eq!(split.len(), 1 + 16);
+}
+
+#[test]
+fn test_mcx_without_ancillae_fails_against_the_mcx() {
+    let src = r#"
+        fn main() {
+            qbit c0;
+            qbit c1;
+            qbit c2;
+            qbit t;
+            MCX(c0, c1, c2, t);
+        }
+    "#;
+    let basis: Vec<String> = ["H", "S", "Sdg", "T", "Tdg", "CX"]
+        .iter()
+        .map(|g| g.to_string())
+        .collect();
+    let target = Target::with_basis(&basis).unwrap();
+    let err = decompose_toffolis(ops(src), &target, &ToffoliOptions::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "7:13: cannot decompose `MCX(c0, c1, c2, t)` without ancillae: it takes phases of \
+         pi/8, which the basis H, S, Sdg, T, Tdg, CX cannot express"
+    );
+
+    let wide: Vec<String> = (0..13).map(|i| format!("q{}", i)).collect();
+    let src = format!(
+        "fn main() {{ {} MCX({}); }}",
+        wide.iter()
+            .map(|q| format!("qbit {};", q))
+            .collect::<String>(),
+        wide.join(", ")
+    );
+    let err =
+        decompose_toffolis(ops(&src), &Target::default(), &ToffoliOptions::default()).unwrap_err();
+    assert!(
+        err.to_string().contains("13 qubits take 8191 phases"),
+        "{}",
+        err
+    );
+}
@@ -1,4 +1,10 @@
+use qxad::ast::StmtKind;
+use qxad::circuit::pass::preset;
+use qxad::circuit::rewrite::identities;
+use qxad::circuit::{Optimizer, PassManager, Target};
+use qxad::lexer::annotate::Annotation;
 use qxad::lexer::{Lexer, Token};
+use qxad::parser::Parser;
 
 fn collect_tokens(mut lex: Lexer) -> Vec<Token> {
     let mut toks = Vec::new();
@@ -74,7 +80,22 @@ fn test_hybrid_classical_and_quantum() {
         })
         .collect();
 
-    assert_eq!(qops.len(), 1);
-    assert_eq!(qops[0].0, "X");
-    assert_eq!(qops[0].1, "q");
+    // Every gate is lexed; the pass manager cancels them, outside `#[nope]`.
+    assert_eq!(qops.len(), 5);
+    let src = "#[pe] fn main() { qbit q; H(q); H(q); X(q); X(q); #[nope] X(q); }";
+    let program = Parser::new(&mut Lexer::new(src)).parse_program().unwrap();
+    let mut manager = PassManager::new(
+        preset(1, Optimizer::Peephole),
+        Target::default(),
+        identities(),
+    );
+    let program = manager.run_program(&program).unwrap();
+    let gates: Vec<_> = program.functions[0]
+        .body
+        .iter()
+        .filter(|s| matches!(s.kind, StmtKind::QOp { .. }))
+        .collect();
+    assert_eq!(gates.len(), 1);
+    assert_eq!(gates[0].kind.to_string(), "X(q);");
+    assert_eq!(gates[0].attrs[0].annotation, Annotation::NoPartialEval);
 }
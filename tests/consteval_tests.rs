@@ -0,0 +1,97 @@
+use qxad::ast::BinOp;
+use qxad::consteval::{check_program, eval_binop, ArithError, ArithMode};
+use qxad::diag::Diagnostic;
+use qxad::lexer::Lexer;
+use qxad::parser::Parser;
+
+fn check(src: &str, mode: ArithMode) -> Vec<Diagnostic> {
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    check_program(&program, mode)
+}
+
+#[test]
+fn test_division_by_zero() {
+    let src = r#"
+        fn main(n) {
+            let x = 1 / 0;
+            let zero = 2 - 2;
+            let y = n / zero;
+            return x + y;
+        }
+    "#;
+
+    let diags = check(src, ArithMode::Checked);
+    assert_eq!(diags.len(), 2);
+    assert!(diags.iter().all(|d| d.is_error()));
+    assert_eq!(diags[0].span.line, 3);
+    assert_eq!(diags[0].message, "attempt to divide by zero in `1 / 0`");
+    assert_eq!(diags[1].span.line, 5);
+}
+
+#[test]
+fn test_errors_point_at_the_operation() {
+    let src = "fn main(a) {\n    let x = a + (1 / 0);\n    return x;\n}";
+
+    let diags = check(src, ArithMode::Checked);
+    assert_eq!(diags.len(), 1);
+    assert_eq!((diags[0].span.line, diags[0].span.col), (2, 18));
+    assert_eq!(diags[0].message, "attempt to divide by zero in `1 / 0`");
+}
+
+#[test]
+fn test_overflow_follows_constants() {
+    let src = r#"
+        fn main() {
+            let big = 9223372036854775807;
+            let x = big + 1;
+            return x * 2;
+        }
+    "#;
+
+    let diags = check(src, ArithMode::Checked);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(
+        diags[0].message,
+        "attempt to compute `9223372036854775807 + 1`, which would overflow"
+    );
+
+    assert!(check(src, ArithMode::Wrapping).is_empty());
+}
+
+#[test]
+fn test_shift_out_of_range() {
+    let src = "fn main(a) { let x = 1 << 64; let y = a >> (0 - 1); return 1 << 63; }";
+
+    let diags = check(src, ArithMode::Checked);
+    assert_eq!(diags.len(), 2);
+    assert!(diags[1].message.contains("shift amount -1"));
+
+    assert!(check(src, ArithMode::Wrapping).is_empty());
+}
+
+#[test]
+fn test_eval_binop_modes() {
+    assert_eq!(eval_binop(BinOp::Add, 2, 3, ArithMode::Checked), Ok(5));
+    assert_eq!(
+        eval_binop(BinOp::Mul, i64::MAX, 2, ArithMode::Checked),
+        Err(ArithError::Overflow)
+    );
+    assert_eq!(
+        eval_binop(BinOp::Mul, i64::MAX, 2, ArithMode::Wrapping),
+        Ok(-2)
+    );
+    assert_eq!(
+        eval_binop(BinOp::Div, i64::MIN, -1, ArithMode::Checked),
+        Err(ArithError::Overflow)
+    );
+    assert_eq!(
+        eval_binop(BinOp::Div, 1, 0, ArithMode::Wrapping),
+        Err(ArithError::DivByZero)
+    );
+    assert_eq!(eval_binop(BinOp::Shl, 1, 65, ArithMode::Wrapping), Ok(2));
+    assert_eq!(
+        "wrapping".parse::<ArithMode>().unwrap(),
+        ArithMode::Wrapping
+    );
+}
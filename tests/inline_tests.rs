@@ -0,0 +1,185 @@
+use qxad::ast::Program;
+use qxad::inline::{inline_program, InlineOptions};
+use qxad::lexer::Lexer;
+use qxad::lint::{check_program, LintConfig};
+use qxad::parser::Parser;
+
+fn parse(src: &str) -> Program {
+    let mut lex = Lexer::new(src);
+    Parser::new(&mut lex).parse_program().expect("parse failed")
+}
+
+fn inlined(src: &str, threshold: usize) -> String {
+    inline_program(&parse(src), &InlineOptions { threshold }).to_string()
+}
+
+#[test]
+fn test_gates_from_both_callees_meet_in_caller() {
+    let src = r#"
+        fn prep(q) {
+            X(q);
+            H(q);
+        }
+
+        fn unprep(q) {
+            H(q);
+            X(q);
+        }
+
+        fn main() {
+            qbit q;
+            prep(q);
+            unprep(q);
+            measure q;
+        }
+    "#;
+
+    let expected = "\
+fn main() {
+    qbit q;
+    X(q);
+    H(q);
+    H(q);
+    X(q);
+    measure q;
+}
+";
+    assert_eq!(inlined(src, 16), expected);
+}
+
+#[test]
+fn test_locals_are_renamed_and_spans_kept() {
+    let src = r#"
+        fn scaled(n) {
+            let t = n * 2;
+            return t + 1;
+        }
+
+        fn main(a) {
+            let t = 5;
+            let x = scaled(a + t);
+            return scaled(x);
+        }
+    "#;
+
+    let program = inline_program(&parse(src), &InlineOptions::default());
+    let expected = "\
+fn main(a) {
+    let t = 5;
+    let n_scaled = a + t;
+    let t_scaled = n_scaled * 2;
+    let x = t_scaled + 1;
+    let t_scaled_2 = x * 2;
+    return t_scaled_2 + 1;
+}
+";
+    assert_eq!(program.to_string(), expected);
+
+    // `let t_scaled = n * 2;` still points into `scaled`.
+    assert_eq!(program.functions[0].body[2].span.line, 3);
+}
+
+#[test]
+fn test_attributes_and_cost_model() {
+    let src = r#"
+        #[inline(never)]
+        fn opaque(q) {
+            H(q);
+        }
+
+        #[inline]
+        fn big(q) {
+            H(q);
+            X(q);
+            H(q);
+        }
+
+        fn small(q) {
+            Z(q);
+        }
+
+        fn count(n) {
+            return count(n);
+        }
+
+        fn main(a, b) {
+            opaque(a);
+            big(a);
+            small(a);
+            small(b);
+            return count(1);
+        }
+    "#;
+
+    let expected = "\
+#[inline(never)]
+fn opaque(q) {
+    H(q);
+}
+
+fn small(q) {
+    Z(q);
+}
+
+fn count(n) {
+    return count(n);
+}
+
+fn main(a, b) {
+    opaque(a);
+    H(a);
+    X(a);
+    H(a);
+    small(a);
+    small(b);
+    return count(1);
+}
+";
+    // `small` is called twice, so inlining it would cost 2.
+    assert_eq!(inlined(src, 1), expected);
+}
+
+#[test]
+fn test_inline_attribute_on_statement_is_unused() {
+    let src = r#"
+        fn main() {
+            #[inline]
+            let x = 1;
+            return x;
+        }
+    "#;
+
+    let diags = check_program(&parse(src), &LintConfig::new());
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].code, Some("unused_attributes"));
+}
+
+#[test]
+fn test_calls_in_qubit_sizes_and_indices_keep_the_callee() {
+    let src = r#"
+        fn three() {
+            return 3;
+        }
+
+        fn main() {
+            let n = three();
+            qbit r[three()];
+            H(r[three() - 1]);
+            measure r[three() - 1];
+        }
+    "#;
+
+    let expected = "\
+fn three() {
+    return 3;
+}
+
+fn main() {
+    let n = 3;
+    qbit r[three()];
+    H(r[three() - 1]);
+    measure r[three() - 1];
+}
+";
+    assert_eq!(inlined(src, 10), expected);
+}
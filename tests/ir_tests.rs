@@ -0,0 +1,279 @@
+use qxad::ir::{lower_program, optimize_module, verify, InstKind, Module, Terminator, Value};
+use qxad::lexer::Lexer;
+use qxad::parser::Parser;
+
+fn lower(src: &str) -> anyhow::Result<Module> {
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    lower_program(&program)
+}
+
+#[test]
+fn test_loop_and_measurement_dump() {
+    let src = r#"
+        fn main(n) {
+            qbit r[n];
+            for i in 0..n {
+                H(r[i]);
+            }
+            measure r[0] -> c;
+            if c {
+                return 1;
+            }
+            return 0;
+        }
+    "#;
+
+    let module = lower(src).unwrap();
+    verify(&module).unwrap();
+    let expected = "\
+fn main(%0: int) -> int {
+bb0:
+    %1: qreg = qalloc %0
+    %2: int = const 0
+    jmp bb1
+bb1:
+    %3: int = phi [bb0: %2], [bb2: %7]
+    %4: int = lt %3, %0
+    br %4, bb2, bb3
+bb2:
+    %5: qubit = qindex %1, %3
+    gate H %5
+    %6: int = const 1
+    %7: int = add %3, %6
+    jmp bb1
+bb3:
+    %8: int = const 0
+    %9: qubit = qindex %1, %8
+    %10: int = measure %9
+    br %10, bb4, bb5
+bb4:
+    %11: int = const 1
+    ret %11
+bb5:
+    %12: int = const 0
+    ret %12
+}
+";
+    assert_eq!(module.to_string(), expected);
+}
+
+#[test]
+fn test_qubit_parameters_are_inferred_through_calls() {
+    let src = r#"
+        fn flip(q) {
+            X(q);
+            return q;
+        }
+
+        fn twice(a, k) {
+            flip(a);
+            return flip(a);
+        }
+    "#;
+
+    let module = lower(src).unwrap();
+    verify(&module).unwrap();
+    let headers: Vec<String> = module
+        .to_string()
+        .lines()
+        .filter(|l| l.starts_with("fn "))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        headers,
+        [
+            "fn flip(%0: qubit) -> qubit {",
+            "fn twice(%0: qubit, %1: int) -> qubit {",
+        ]
+    );
+}
+
+#[test]
+fn test_verifier_rejects_broken_ssa() {
+    let src = r#"
+        fn main(n) {
+            for i in 0..n {
+                let x = i + 1;
+            }
+            return n;
+        }
+    "#;
+    let module = lower(src).unwrap();
+    verify(&module).unwrap();
+
+    // A phi missing the value from the loop latch.
+    let mut broken = module.clone();
+    if let InstKind::Phi(incoming) = &mut broken.functions[0].blocks[1].insts[0].kind {
+        incoming.pop();
+    }
+    let err = verify(&broken).unwrap_err().to_string();
+    assert!(err.contains("one value per predecessor"), "{}", err);
+
+    // Returning a value defined inside the loop body from the exit block.
+    let mut broken = module.clone();
+    let body_value = broken.functions[0].blocks[2].insts[1].result.unwrap();
+    broken.functions[0].blocks[3].term = Terminator::Return(Some(body_value));
+    let err = verify(&broken).unwrap_err().to_string();
+    assert!(err.contains("before it is defined"), "{}", err);
+
+    // Using a value nothing defines.
+    let mut broken = module;
+    broken.functions[0].blocks[3].term = Terminator::Return(Some(Value(99)));
+    assert!(verify(&broken).is_err());
+}
+
+#[test]
+fn test_type_errors_are_reported() {
+    let err = lower("fn f(q) { H(q); return q + 1; }").unwrap_err();
+    assert!(format!("{:#}", err).contains("expected an integer, found a qubit"));
+
+    let err = lower("fn f() { qbit q; H(q[0]); }").unwrap_err();
+    assert!(format!("{:#}", err).contains("`q` is a qubit, which cannot be used as `q[0]`"));
+}
+
+fn optimize(src: &str) -> String {
+    let mut module = lower(src).unwrap();
+    optimize_module(&mut module);
+    verify(&module).unwrap();
+    module.to_string()
+}
+
+#[test]
+fn test_constants_fold_and_dead_branches_go_away() {
+    let src = r#"
+        fn main(n) {
+            let k = 2 * 3;
+            if k == 6 {
+                return n + 0;
+            }
+            return 1;
+        }
+    "#;
+
+    let expected = "\
+fn main(%0: int) -> int {
+bb0:
+    jmp bb1
+bb1:
+    ret %0
+}
+";
+    assert_eq!(optimize(src), expected);
+}
+
+#[test]
+fn test_quantum_instructions_are_never_dead() {
+    let src = r#"
+        fn main(n) {
+            qbit q;
+            let unused = n * 2;
+            H(q);
+            measure q -> c;
+            reset q;
+            return n;
+        }
+    "#;
+
+    let expected = "\
+fn main(%0: int) -> int {
+bb0:
+    %1: qubit = qalloc
+    gate H %1
+    %4: int = measure %1
+    reset %1
+    ret %0
+}
+";
+    assert_eq!(optimize(src), expected);
+}
+
+#[test]
+fn test_duplicate_expressions_are_merged() {
+    let src = r#"
+        fn main(a, b) {
+            let x = a * b;
+            let y = b * a;
+            return x + y;
+        }
+    "#;
+
+    let out = optimize(src);
+    assert_eq!(out.matches("mul").count(), 1, "{}", out);
+    assert!(out.contains("add %2, %2"), "{}", out);
+}
+
+#[test]
+fn test_loop_invariants_move_to_the_preheader() {
+    let src = r#"
+        fn main(n) {
+            qbit r[n];
+            for i in 0..4 {
+                let j = n * 4;
+                H(r[j]);
+                measure r[i] -> c;
+            }
+            return 0;
+        }
+    "#;
+
+    let mut module = lower(src).unwrap();
+    optimize_module(&mut module);
+    verify(&module).unwrap();
+    let func = &module.functions[0];
+    let entry: Vec<String> = func.blocks[0]
+        .insts
+        .iter()
+        .map(|i| i.kind.to_string())
+        .collect();
+    assert!(entry.iter().any(|k| k.starts_with("mul")), "{:?}", entry);
+    assert!(entry.iter().any(|k| k.starts_with("qindex")), "{:?}", entry);
+
+    // The gate and the measurement still run once per iteration.
+    let body: Vec<String> = func.blocks[2]
+        .insts
+        .iter()
+        .map(|i| i.kind.to_string())
+        .collect();
+    assert!(body.iter().any(|k| k.starts_with("gate H")), "{:?}", body);
+    assert!(body.iter().any(|k| k.starts_with("measure")), "{:?}", body);
+}
+
+#[test]
+fn test_checked_invariants_stay_in_a_loop_that_may_not_run() {
+    let src = r#"
+        fn main(n, m) {
+            qbit r[n];
+            for i in 0..m {
+                let j = n * 4;
+                H(r[j]);
+            }
+            return 0;
+        }
+    "#;
+
+    let mut module = lower(src).unwrap();
+    optimize_module(&mut module);
+    verify(&module).unwrap();
+    let func = &module.functions[0];
+    let kinds = |b: usize| -> Vec<String> {
+        func.blocks[b]
+            .insts
+            .iter()
+            .map(|i| i.kind.to_string())
+            .collect()
+    };
+
+    // `n * 4` might overflow and `r[j]` be out of bounds, which must not
+    // fail when `m` is 0.
+    let entry = kinds(0);
+    assert!(!entry.iter().any(|k| k.starts_with("mul")), "{:?}", entry);
+    assert!(
+        !entry.iter().any(|k| k.starts_with("qindex")),
+        "{:?}",
+        entry
+    );
+    let body = kinds(2);
+    assert!(body.iter().any(|k| k.starts_with("mul")), "{:?}", body);
+    assert!(body.iter().any(|k| k.starts_with("qindex")), "{:?}", body);
+}
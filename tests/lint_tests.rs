@@ -0,0 +1,251 @@
+use qxad::diag::{Diagnostic, Severity};
+use qxad::lexer::Lexer;
+use qxad::lint::{check_program, Level, LintConfig};
+use qxad::parser::Parser;
+
+fn lint(src: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut lex = Lexer::new(src);
+    let program = Parser::new(&mut lex).parse_program().expect("parse failed");
+    check_program(&program, config)
+}
+
+fn codes(diags: &[Diagnostic]) -> Vec<&'static str> {
+    diags.iter().filter_map(|d| d.code).collect()
+}
+
+#[test]
+fn test_unused_bindings() {
+    let src = r#"
+        fn main() {
+            let x = 1;
+            let y = 2;
+            qbit q;
+            qbit r;
+            H(r);
+            measure r -> c;
+            return y;
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(
+        codes(&diags),
+        vec!["unused_variables", "unused_qubits", "unused_measurements"]
+    );
+    assert_eq!(diags[0].span.line, 3);
+    assert!(diags.iter().all(|d| d.severity == Severity::Warning));
+}
+
+#[test]
+fn test_shadowed_let_is_unused() {
+    let src = "fn main() { let x = 1; let x = 2; return x; }";
+    let diags = lint(src, &LintConfig::new());
+
+    assert_eq!(codes(&diags), vec!["unused_variables"]);
+    assert_eq!(diags[0].span.col, 13);
+}
+
+#[test]
+fn test_unreachable_after_return() {
+    let src = "fn main() { return 1; let a = 2; return a; }";
+    let diags = lint(src, &LintConfig::new());
+
+    assert_eq!(codes(&diags), vec!["unreachable_code"]);
+    assert_eq!(diags[0].notes.len(), 1);
+}
+
+#[test]
+fn test_redundant_pe_attribute() {
+    let src = r#"
+        #[pe]
+        fn main() {
+            #[allow(qubit_leak)] qbit q;
+            #[pe]
+            X(q);
+            #[nope]
+            X(q);
+            #[nope] #[pe]
+            X(q);
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["unused_attributes", "unused_attributes"]);
+    assert_eq!(diags[0].span.line, 5);
+    assert_eq!(diags[1].span.line, 9);
+}
+
+#[test]
+fn test_allow_and_deny_attributes() {
+    let src = r#"
+        #[deny(unused_variables)]
+        fn main() {
+            #[allow(unused_variables)]
+            let a = 1;
+            let b = 2;
+            #[allow(warnings)]
+            qbit q;
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["unused_variables"]);
+    assert_eq!(diags[0].severity, Severity::Error);
+    assert_eq!(diags[0].span.line, 6);
+}
+
+#[test]
+fn test_config_levels() {
+    let src = "fn main() { let a = 1; qbit q; }";
+
+    let mut config = LintConfig::new();
+    config.set("warnings", Level::Deny).unwrap();
+    config.set("unused_qubits", Level::Allow).unwrap();
+    let diags = lint(src, &config);
+    assert_eq!(codes(&diags), vec!["unused_variables"]);
+    assert!(diags[0].is_error());
+
+    assert!(config.set("no_such_lint", Level::Warn).is_err());
+}
+
+#[test]
+fn test_qubit_leak_points_at_decl_and_exit() {
+    let src = r#"
+        fn main() {
+            qbit q;
+            H(q);
+            return 0;
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["qubit_leak"]);
+    assert_eq!(diags[0].span.line, 3);
+    assert_eq!(diags[0].notes[0].0.line, 4);
+    assert_eq!(diags[0].notes[1].0.line, 5);
+}
+
+#[test]
+fn test_measured_reset_and_returned_qubits_do_not_leak() {
+    let src = r#"
+        fn measured() { qbit a; H(a); measure a -> c; return c; }
+        fn cleared() { qbit b; X(b); reset b; }
+        fn returned() { qbit q; H(q); return q; }
+        #[nope]
+        fn uncomputed() { qbit u; H(u); X(u); X(u); H(u); }
+    "#;
+
+    assert!(lint(src, &LintConfig::new()).is_empty());
+}
+
+#[test]
+fn test_inverse_gates_uncompute() {
+    let src = r#"
+        #[nope]
+        fn phases() { qbit a; S(a); T(a); Tdg(a); Sdg(a); }
+        #[nope]
+        fn controlled(q, r) {
+            qbit b;
+            qbit c;
+            CX(q, b);
+            CCX(q, r, c);
+            CCX(r, q, c);
+            CNOT(q, b);
+        }
+    "#;
+
+    assert!(lint(src, &LintConfig::new()).is_empty());
+}
+
+#[test]
+fn test_inverse_gate_on_other_operands_leaks() {
+    let src = r#"
+        #[nope]
+        fn main(p, q) {
+            qbit a;
+            S(a);
+            S(a);
+            qbit b;
+            CX(p, b);
+            CX(q, b);
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["qubit_leak", "qubit_leak"]);
+}
+
+#[test]
+fn test_qubit_leak_at_end_of_function() {
+    let src = r#"
+        fn prepare(q) { H(q); }
+        fn main() {
+            qbit q;
+            prepare(q);
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["qubit_leak"]);
+    assert_eq!(diags[0].notes[0].1, "passed to a call here");
+    assert_eq!(diags[0].notes[1].0.line, 6);
+}
+
+#[test]
+fn test_lints_follow_if_arms() {
+    let src = r#"
+        fn main(a) {
+            qbit q;
+            if a > 0 {
+                H(q);
+            } else {
+                let t = 1;
+                return t;
+            }
+            if a > 1 {
+                return 1;
+            } else {
+                return 2;
+            }
+            reset q;
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["qubit_leak", "unreachable_code"]);
+    assert_eq!(diags[0].notes[0].0.line, 5);
+    assert_eq!(diags[0].notes[1].0.line, 11);
+    assert_eq!(diags[1].span.line, 15);
+}
+
+#[test]
+fn test_qubit_arrays_in_loops() {
+    let src = r#"
+        fn cleaned(n) {
+            qbit q[n];
+            for i in 0..n {
+                H(q[i]);
+            }
+            for i in 0..n {
+                measure q[i];
+            }
+            return;
+        }
+
+        fn leaked(n) {
+            qbit q[n];
+            for i in 0..n {
+                X(q[i]);
+                reset q[i + 1];
+            }
+            for k in 0..n {
+                return;
+            }
+        }
+    "#;
+
+    let diags = lint(src, &LintConfig::new());
+    assert_eq!(codes(&diags), vec!["qubit_leak", "unused_variables"]);
+    assert_eq!(diags[0].span.line, 14);
+    assert!(diags[1].message.contains("`k`"));
+}
@@ -0,0 +1,602 @@
+use qxad::ast::Program;
+use qxad::bta;
+use qxad::lexer::{Lexer, Span};
+use qxad::parser::Parser;
+use qxad::pe::{partial_evaluate, render_residual, PeOptions};
+
+fn parse(src: &str) -> Program {
+    let mut lex = Lexer::new(src);
+    Parser::new(&mut lex).parse_program().expect("parse failed")
+}
+
+fn residual(src: &str) -> String {
+    partial_evaluate(&parse(src), &PeOptions::default())
+        .0
+        .to_string()
+}
+
+#[test]
+fn test_classic_sample_folds_to_constant() {
+    let src = "fn main() { let x = 1 + 2 * 3; let y = x + 5; return y; }";
+    assert_eq!(residual(src), "fn main() {\n    return 12;\n}\n");
+}
+
+#[test]
+fn test_static_if_takes_one_arm() {
+    let src = r#"
+        fn main(a) {
+            let k = 3;
+            if k * 2 > 5 {
+                X(a);
+            } else {
+                H(a);
+            }
+            if k == 0 {
+                return 0;
+            }
+            return a + k;
+        }
+    "#;
+
+    let expected = "\
+fn main(a) {
+    X(a);
+    return a + 3;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_dynamic_if_keeps_both_arms() {
+    let src = r#"
+        fn main(a) {
+            let k = 2;
+            if a < k {
+                let t = k + 1;
+                return t * a;
+            } else {
+                return a * 1;
+            }
+        }
+    "#;
+
+    let expected = "\
+fn main(a) {
+    if a < 2 {
+        return 3 * a;
+    } else {
+        return a;
+    }
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_nope_function_and_block_are_left_alone() {
+    let src = r#"
+        #[nope]
+        fn kept() {
+            let x = 1 + 1;
+            return x;
+        }
+
+        #[pe]
+        fn mixed() {
+            let x = 1 + 1;
+            if x == 2 {
+                #[nope]
+                let y = x + 1;
+                return y;
+            }
+            return x;
+        }
+    "#;
+
+    let expected = "\
+#[nope]
+fn kept() {
+    let x = 1 + 1;
+    return x;
+}
+
+#[pe]
+fn mixed() {
+    let x = 2;
+    {
+        #[nope]
+        let y = x + 1;
+        return y;
+    }
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_nope_does_not_carry_into_the_next_function() {
+    let src = r#"
+        #[nope]
+        fn kept() {
+            let x = 1 + 1;
+            return x;
+        }
+
+        fn folded() {
+            let x = 1 + 1;
+            #[nope]
+            let y = x + 1;
+            return y;
+        }
+
+        fn after() {
+            let x = 2 + 2;
+            return x;
+        }
+    "#;
+
+    let expected = "\
+#[nope]
+fn kept() {
+    let x = 1 + 1;
+    return x;
+}
+
+fn folded() {
+    let x = 2;
+    #[nope]
+    let y = x + 1;
+    return y;
+}
+
+fn after() {
+    return 4;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_static_argument_specializes_callee() {
+    let src = r#"
+        fn rotate(n, q) {
+            if n > 2 {
+                H(q);
+            }
+            return q;
+        }
+
+        fn main(a, b) {
+            rotate(3, a);
+            rotate(3, b);
+            return rotate(1, a);
+        }
+    "#;
+
+    let expected = "\
+fn rotate(n, q) {
+    if n > 2 {
+        H(q);
+    }
+    return q;
+}
+
+fn main(a, b) {
+    rotate_n3(a);
+    rotate_n3(b);
+    return rotate_n1(a);
+}
+
+fn rotate_n3(q) {
+    H(q);
+    return q;
+}
+
+fn rotate_n1(q) {
+    return q;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_constant_callee_folds_into_caller() {
+    let src = r#"
+        fn square(n) {
+            return n * n;
+        }
+
+        fn main() {
+            return square(4) + opaque(2);
+        }
+
+        #[nope]
+        fn opaque(n) {
+            return n;
+        }
+    "#;
+
+    let expected = "\
+fn square(n) {
+    return n * n;
+}
+
+fn main() {
+    return 16 + opaque(2);
+}
+
+#[nope]
+fn opaque(n) {
+    return n;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_constant_callee_keeps_arguments_with_calls() {
+    let src = r#"
+        fn f(x, n) {
+            return n * 2;
+        }
+
+        fn g(y) {
+            qbit q;
+            H(q);
+            measure q -> c;
+            return c + y;
+        }
+
+        fn main(y) {
+            let r = f(g(y), 2);
+            let s = f(y, 2);
+            return r + s;
+        }
+    "#;
+
+    let out = residual(src);
+    assert!(out.contains(
+        "\
+fn main(y) {
+    let r = f_n2(g(y));
+    return r + 4;
+}
+
+fn f_n2(x) {
+    return 4;
+}
+"
+    ));
+}
+
+#[test]
+fn test_specializations_are_capped() {
+    let src = "fn count(n) { return count(n + 1); } fn main() { return count(0); }";
+    let options = PeOptions {
+        max_specializations: 2,
+        ..PeOptions::default()
+    };
+    let (program, diags) = partial_evaluate(&parse(src), &options);
+
+    let names: Vec<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["count", "main", "count_n0", "count_n1"]);
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].message.contains("stopped specializing `count`"));
+}
+
+#[test]
+fn test_static_loop_is_unrolled() {
+    let src = r#"
+        fn main() {
+            let n = 3;
+            qbit q[n];
+            for i in 0..n {
+                H(q[i]);
+                if i == 1 {
+                    X(q[i + 1]);
+                }
+            }
+            for i in 0..n {
+                measure q[i];
+            }
+            return 0;
+        }
+    "#;
+
+    let expected = "\
+fn main() {
+    qbit q[3];
+    H(q[0]);
+    H(q[1]);
+    X(q[2]);
+    H(q[2]);
+    measure q[0];
+    measure q[1];
+    measure q[2];
+    return 0;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_loops_that_cannot_be_unrolled_are_kept() {
+    let src = r#"
+        fn main(m) {
+            qbit q[m];
+            for i in 0..m {
+                H(q[i]);
+            }
+            for i in 0..4 {
+                let j = i * 2;
+                X(q[j]);
+            }
+            #[nope]
+            for i in 0..m {
+                reset q[i];
+            }
+            return;
+        }
+    "#;
+    let options = PeOptions {
+        unroll_budget: 2,
+        ..PeOptions::default()
+    };
+    let (program, diags) = partial_evaluate(&parse(src), &options);
+
+    let expected = "\
+fn main(m) {
+    qbit q[m];
+    for i in 0..m {
+        H(q[i]);
+    }
+    for i in 0..4 {
+        let j = i * 2;
+        X(q[j]);
+    }
+    #[nope]
+    for i in 0..m {
+        reset q[i];
+    }
+    return;
+}
+";
+    assert_eq!(program.to_string(), expected);
+
+    let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "cannot unroll loop: its bound `m` is only known at runtime",
+            "cannot unroll loop: it runs 4 times, more than the unroll budget of 2",
+        ]
+    );
+}
+
+#[test]
+fn test_deterministic_measurement_folds_and_frees_the_qubit() {
+    let src = r#"
+        fn main(a) {
+            qbit q;
+            X(q);
+            measure q -> c;
+            if c == 1 {
+                H(a);
+            }
+            return c;
+        }
+    "#;
+
+    let expected = "\
+fn main(a) {
+    H(a);
+    return 1;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_known_controls_are_applied_classically() {
+    let src = r#"
+        fn main(b) {
+            qbit r[3];
+            X(r[0]);
+            CX(r[0], r[1]);
+            CCX(r[0], b, r[2]);
+            CX(r[2], b);
+            measure r[1] -> c;
+            return c;
+        }
+    "#;
+
+    // `r[0]` is 1 and `r[1]` follows it, but `r[2]` now depends on `b`.
+    let expected = "\
+fn main(b) {
+    qbit r[3];
+    X(r[0]);
+    X(r[1]);
+    CX(b, r[2]);
+    CX(r[2], b);
+    return 1;
+}
+";
+    assert_eq!(residual(src), expected);
+}
+
+#[test]
+fn test_unknown_states_are_not_folded() {
+    let src = r#"
+        fn main(n) {
+            qbit q;
+            qbit p;
+            H(q);
+            measure q -> c;
+            X(p);
+            for i in 0..n {
+                X(p);
+            }
+            measure p -> d;
+            #[nope]
+            qbit s;
+            measure s -> e;
+            return c + d + e;
+        }
+    "#;
+
+    let out = residual(src);
+    assert!(out.contains("measure q -> c;"), "{}", out);
+    assert!(out.contains("measure p -> d;"), "{}", out);
+    assert!(out.contains("measure s -> e;"), "{}", out);
+}
+
+#[test]
+fn test_residual_dump_notes_where_statements_came_from() {
+    let src = r#"
+fn main(a) {
+    let k = 2;
+    for i in 0..k {
+        H(a);
+    }
+    if a > k {
+        return 1;
+    }
+    return 0;
+}
+"#;
+
+    let program = parse(src);
+    let (residual, _) = partial_evaluate(&program, &PeOptions::default());
+    let expected = "\
+fn main(a) {                     // from 2:1
+    H(a);                            // from 5:9, dynamic: quantum operation
+    H(a);                            // from 5:9, dynamic: quantum operation
+    if a > 2 {                       // from 7:5, dynamic: `a` is a parameter of entry function `main` (2:1)
+        return 1;                        // from 8:9
+    }
+    return 0;                        // from 10:5
+}
+";
+    assert_eq!(
+        render_residual(&residual, &bta::analyze(&program)),
+        expected
+    );
+}
+
+#[test]
+fn test_running_out_of_fuel_keeps_the_loop() {
+    let src = r#"
+fn main(a) {
+    for i in 0..4 {
+        for j in 0..4 {
+            H(a);
+        }
+    }
+    let k = 1 + 1;
+    return a + k;
+}
+"#;
+
+    let options = PeOptions {
+        fuel: 10,
+        ..PeOptions::default()
+    };
+    let (residual, diags) = partial_evaluate(&parse(src), &options);
+    let expected = "\
+fn main(a) {
+    for i in 0..4 {
+        for j in 0..4 {
+            H(a);
+        }
+    }
+    let k = 1 + 1;
+    return a + k;
+}
+";
+    assert_eq!(residual.to_string(), expected);
+
+    assert_eq!(diags.len(), 1);
+    let message = diags[0].to_string();
+    assert!(
+        message.contains("ran out of fuel after 10 steps"),
+        "{}",
+        message
+    );
+    assert_eq!(
+        diags[0].notes,
+        [
+            (
+                Span { line: 4, col: 9 },
+                "while unrolling this loop".to_string()
+            ),
+            (
+                Span { line: 3, col: 5 },
+                "while unrolling this loop".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_fuel_attribute_raises_the_budget() {
+    let src = r#"
+        #[pe(fuel = 100)]
+        fn spin(n, q) {
+            for i in 0..n {
+                for j in 0..n {
+                    X(q);
+                }
+            }
+            return n;
+        }
+
+        fn main(q) {
+            let k = spin(4, q);
+            return k;
+        }
+    "#;
+
+    let options = PeOptions {
+        fuel: 10,
+        ..PeOptions::default()
+    };
+    let (residual, diags) = partial_evaluate(&parse(src), &options);
+    assert!(
+        diags.iter().all(|d| !d.message.contains("fuel")),
+        "{:?}",
+        diags
+    );
+    let residual = residual.to_string();
+    assert_eq!(residual.matches("X(q);").count(), 1 + 16, "{}", residual);
+    assert!(residual.contains("let k = spin_n4(q);"), "{}", residual);
+}
+
+#[test]
+fn test_residual_dump_drops_reasons_that_folded_away() {
+    let src = r#"
+fn main(a) {
+    qbit q;
+    measure q -> m;
+    if a > 0 {
+        return m + 1;
+    }
+    return m;
+}
+"#;
+
+    let program = parse(src);
+    let (residual, _) = partial_evaluate(&program, &PeOptions::default());
+    let expected = "\
+fn main(a) {                     // from 2:1
+    if a > 0 {                       // from 5:5, dynamic: `a` is a parameter of entry function `main` (2:1)
+        return 1;                        // from 6:9
+    }
+    return 0;                        // from 8:5
+}
+";
+    assert_eq!(
+        render_residual(&residual, &bta::analyze(&program)),
+        expected
+    );
+}